@@ -1,3 +1,5 @@
+use crate::state::Snapshot;
+
 pub trait Readable {
     fn read(&self, addr: u16) -> u8 {
         unimplemented!("unimplemented Readable trait")
@@ -20,4 +22,4 @@ pub trait Writable {
     }
 }
 
-pub trait Addressable: Readable + Writable {}
+pub trait Addressable: Readable + Writable + Snapshot {}
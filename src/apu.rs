@@ -1,7 +0,0 @@
-use crate::addressable::*;
-
-pub struct Apu {}
-
-impl Readable for Apu {}
-impl Writable for Apu {}
-impl Addressable for Apu {}
@@ -0,0 +1,147 @@
+// 带限重采样(blip buffer)
+//
+// APU以CPU时钟(约1.79MHz)输出,直接抽取到44.1kHz会产生严重的混叠。
+// 混音器的输出是阶跃信号,只需在电平变化时记录变化量(delta),
+// 以加窗sinc函数作为冲激响应写入采样缓冲,读取时积分即得到带限的阶跃
+
+/// 冲激响应的长度(采样数)
+const KERNEL_WIDTH: usize = 16;
+/// 冲激响应在一个采样间隔内的相位数
+const KERNEL_PHASES: usize = 64;
+
+/// 带限重采样缓冲
+pub struct BlipBuffer {
+    /// 每个时钟对应的采样数
+    factor: f64,
+    /// 当前帧起点在缓冲中的位置(以采样为单位,含小数部分)
+    time: f64,
+    /// 变化量缓冲
+    deltas: Vec<f32>,
+    /// 积分器的值
+    integrator: f32,
+    /// 缓冲中最多保留的采样数,超出时丢弃最早的采样
+    capacity: usize,
+    kernels: Vec<[f32; KERNEL_WIDTH]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        BlipBuffer {
+            factor: sample_rate as f64 / clock_rate,
+            time: 0.0,
+            deltas: Vec::new(),
+            integrator: 0.0,
+            capacity: sample_rate as usize,
+            kernels: (0..=KERNEL_PHASES).map(kernel).collect(),
+        }
+    }
+
+    /// 修改输入时钟频率与输出采样率之比
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+    }
+
+    /// 在当前帧的第clock个时钟处加入一个电平变化
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let position = self.time + clock as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * KERNEL_PHASES as f64).round() as usize;
+        if self.deltas.len() < index + KERNEL_WIDTH {
+            self.deltas.resize(index + KERNEL_WIDTH, 0.0);
+        }
+        for (slot, weight) in self.deltas[index..].iter_mut().zip(&self.kernels[phase]) {
+            *slot += delta * weight;
+        }
+    }
+
+    /// 结束当前帧,之后的时钟从0开始计算
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.time += clocks as f64 * self.factor;
+        let available = self.samples_available();
+        if available > self.capacity {
+            self.discard(available - self.capacity);
+        }
+    }
+
+    /// 与另一个缓冲对齐时间,使两者产生的采样一一对应
+    pub fn sync_time(&mut self, other: &BlipBuffer) {
+        self.time = other.time;
+    }
+
+    /// 已经完成的采样数
+    pub fn samples_available(&self) -> usize {
+        self.time as usize
+    }
+
+    /// 读取采样,返回读取的数量
+    pub fn read_samples(&mut self, output: &mut [f32]) -> usize {
+        let count = output.len().min(self.samples_available());
+        for (i, sample) in output.iter_mut().take(count).enumerate() {
+            self.integrator += self.deltas.get(i).copied().unwrap_or(0.0);
+            *sample = self.integrator;
+        }
+        self.remove(count);
+        count
+    }
+
+    fn discard(&mut self, count: usize) {
+        for i in 0..count {
+            self.integrator += self.deltas.get(i).copied().unwrap_or(0.0);
+        }
+        self.remove(count);
+    }
+
+    fn remove(&mut self, count: usize) {
+        self.deltas.drain(..count.min(self.deltas.len()));
+        self.time -= count as f64;
+    }
+}
+
+/// 生成一个相位的冲激响应,冲激位于第KERNEL_WIDTH/2-1个采样之后phase/KERNEL_PHASES处
+fn kernel(phase: usize) -> [f32; KERNEL_WIDTH] {
+    use std::f64::consts::PI;
+    let offset = phase as f64 / KERNEL_PHASES as f64;
+    let half = KERNEL_WIDTH as f64 / 2.0;
+    let mut kernel = [0.0; KERNEL_WIDTH];
+    let mut sum = 0.0;
+    for (i, weight) in kernel.iter_mut().enumerate() {
+        let x = i as f64 - (half - 1.0) - offset;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        // Blackman窗
+        let n = (x + half) / (2.0 * half);
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+        *weight = sinc * window;
+        sum += *weight;
+    }
+    // 归一化使阶跃的最终电平等于delta
+    kernel.map(|weight| (weight / sum) as f32)
+}
+
+#[test]
+fn test_blip_step() {
+    let mut blip = BlipBuffer::new(1_764_000.0, 44100);
+    blip.add_delta(100, 0.5);
+    blip.end_frame(17640);
+    assert_eq!(blip.samples_available(), 441);
+    let mut output = [0.0; 441];
+    assert_eq!(blip.read_samples(&mut output), 441);
+    // 阶跃之前为0,稳定后等于delta
+    assert_eq!(output[0], 0.0);
+    assert!((output[440] - 0.5).abs() < 1e-4);
+    assert_eq!(blip.samples_available(), 0);
+}
+
+#[test]
+fn test_blip_capacity() {
+    let mut blip = BlipBuffer::new(1000.0, 100);
+    blip.add_delta(0, 1.0);
+    blip.end_frame(30_000);
+    assert_eq!(blip.samples_available(), 100);
+    let mut output = [0.0; 1];
+    blip.read_samples(&mut output);
+    assert!((output[0] - 1.0).abs() < 1e-4);
+}
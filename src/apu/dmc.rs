@@ -0,0 +1,240 @@
+use crate::{meta::Region, snapshot};
+
+// DMC(增量调制)通道的寄存器
+//
+// $4010 IL-- RRRR  IRQ使能, 循环, 速率序号
+// $4011 -DDD DDDD  直接写入输出电平
+// $4012 AAAA AAAA  采样地址 = $C000 + A * 64
+// $4013 LLLL LLLL  采样长度 = L * 16 + 1 字节
+
+/// NTSC的速率表(以CPU周期为单位)
+static NTSC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+/// PAL的速率表(以CPU周期为单位)
+static PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// 内存读取单元每取一个字节使CPU暂停的周期数
+pub const DMA_STALL_CYCLES: u16 = 4;
+/// OAM DMA进行中时DMC的读取穿插在OAM DMA的周期之间,只额外暂停2个周期
+pub const OAM_DMA_OVERLAP_STALL_CYCLES: u16 = 2;
+
+/// DMC通道
+/// 内存读取单元需要通过总线读取采样数据,
+/// 采样缓冲为空时通过`poll_dma_request`向总线请求,总线读取后调用`fill_sample_buffer`
+#[derive(Debug, Clone, Copy)]
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    pub irq: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    /// 7位输出电平
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    /// 输出单元
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+snapshot!(
+    Dmc,
+    irq_enabled,
+    irq,
+    looping,
+    timer_period,
+    timer,
+    output_level,
+    sample_address,
+    sample_length,
+    current_address,
+    bytes_remaining,
+    sample_buffer,
+    shift_register,
+    bits_remaining,
+    silence
+);
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        let rates = match region {
+            Region::Pal => &PAL_RATES,
+            Region::Ntsc | Region::Dendy => &NTSC_RATES,
+        };
+        Dmc {
+            rates,
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer_period: rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    /// 写入寄存器,addr为0~3
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0b0100_0000 != 0;
+                self.timer_period = self.rates[(data & 0b1111) as usize];
+            }
+            1 => self.output_level = data & 0b0111_1111,
+            2 => self.sample_address = 0xC000 | (data as u16) << 6,
+            3 => self.sample_length = (data as u16) << 4 | 1,
+            _ => unreachable!(),
+        }
+    }
+
+    /// 通过$4015使能或禁用
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// 采样缓冲为空且还有剩余字节时,返回需要读取的地址
+    pub fn poll_dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// 总线完成读取后填充采样缓冲
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // 地址超过$FFFF后回绕到$8000
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// 每个CPU周期调用一次
+    pub fn clock(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        // 一个输出周期结束,从采样缓冲取下一个字节
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// 当前输出(0~127)
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+#[test]
+fn test_dmc_sample_playback() {
+    let mut dmc = Dmc::new(Region::Ntsc);
+    // 速率54, 地址$C040, 长度17字节
+    dmc.write(0, 0x0F);
+    dmc.write(2, 1);
+    dmc.write(3, 1);
+    assert_eq!(dmc.poll_dma_request(), None);
+    dmc.set_enabled(true);
+    assert_eq!(dmc.poll_dma_request(), Some(0xC040));
+    dmc.fill_sample_buffer(0xFF);
+    assert_eq!(dmc.poll_dma_request(), None);
+    assert!(dmc.is_active());
+
+    // 第一个输出周期是静音的,结束时取走缓冲
+    for _ in 0..8 * 54 {
+        dmc.clock();
+    }
+    assert_eq!(dmc.output(), 0);
+    assert_eq!(dmc.poll_dma_request(), Some(0xC041));
+    for _ in 0..8 * 54 {
+        dmc.clock();
+    }
+    assert_eq!(dmc.output(), 16);
+}
+
+#[test]
+fn test_dmc_irq() {
+    let mut dmc = Dmc::new(Region::Ntsc);
+    dmc.write(0, 0x80);
+    dmc.write(3, 0);
+    dmc.set_enabled(true);
+    dmc.fill_sample_buffer(0);
+    assert!(dmc.irq);
+    assert!(!dmc.is_active());
+    // 写入$4015清除IRQ
+    dmc.set_enabled(true);
+    assert!(!dmc.irq);
+
+    // 循环播放时不产生IRQ
+    dmc.write(0, 0xC0);
+    dmc.fill_sample_buffer(0);
+    assert!(!dmc.irq);
+    assert_eq!(dmc.poll_dma_request(), None);
+    assert!(dmc.is_active());
+}
@@ -0,0 +1,86 @@
+use crate::snapshot;
+
+/// 包络发生器
+/// 每个四分之一帧被时钟驱动一次,生成从15递减到0的音量,
+/// 也可以输出固定音量
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Envelope {
+    /// 写入通道的第4个寄存器后重新开始
+    start: bool,
+    /// 循环标志,与长度计数器的暂停标志共用同一位
+    looping: bool,
+    /// 使用固定音量
+    constant: bool,
+    /// 固定音量或分频器的周期
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+snapshot!(Envelope, start, looping, constant, volume, divider, decay);
+
+impl Envelope {
+    /// 写入寄存器的 --LC VVVV 位
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// 四分之一帧时钟
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    /// 当前音量(0~15)
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[test]
+fn test_envelope_decay() {
+    let mut envelope = Envelope::default();
+    // 周期为1(分频器每2个时钟输出一次),不循环
+    envelope.write(0b0000_0001);
+    envelope.restart();
+    envelope.clock();
+    assert_eq!(envelope.output(), 15);
+    for _ in 0..2 * 15 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.output(), 0);
+    envelope.clock();
+    envelope.clock();
+    assert_eq!(envelope.output(), 0);
+
+    // 循环时回到15
+    envelope.write(0b0010_0001);
+    envelope.clock();
+    envelope.clock();
+    assert_eq!(envelope.output(), 15);
+
+    envelope.write(0b0001_0111);
+    assert_eq!(envelope.output(), 7);
+}
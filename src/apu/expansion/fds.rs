@@ -0,0 +1,308 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::snapshot;
+
+// FDS的声音寄存器
+//
+// $4040~$407F 64个6位采样的波形,只有$4089的bit7为1时可以写入
+// $4080       MDVV VVVV  音量包络: 关闭包络, 方向(增大), 速度或直接设置的增益
+// $4082       频率低8位
+// $4083       MH-- FFFF  停止波形, 停止调制与包络, 频率高4位
+// $4084       MDVV VVVV  调制包络,格式与$4080相同
+// $4085       -CCC CCCC  调制计数器(7位有符号数)
+// $4086       调制频率低8位
+// $4087       H--- FFFF  停止调制, 调制频率高4位
+// $4088       ---- -TTT  写入调制表,只有停止调制时可以写入
+// $4089       W--- --VV  写入波形, 主音量(2/2, 2/3, 2/4, 2/5)
+// $408A       包络的速度倍数
+// $4090/$4092 读取音量/调制增益
+
+/// 最大输出约为APU方波的2.4倍
+const OUTPUT_SCALE: f32 = PULSE_MAX_OUTPUT * 2.4 / (63.0 * 32.0);
+/// 主音量的倍数(以1/30为单位)
+const MASTER_VOLUME: [u16; 4] = [30, 20, 15, 12];
+/// 调制表的值对调制计数器的改变,4表示清零
+const MOD_ADJUST: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Envelope {
+    /// 关闭时增益直接由寄存器设置
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    counter: u32,
+}
+
+snapshot!(Envelope, disabled, increase, speed, gain, counter);
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.disabled = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3F;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.counter = 0;
+    }
+
+    /// 每 8 * (master_speed + 1) * (speed + 1) 个CPU周期改变一次增益
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < 8 * (master_speed as u32 + 1) * (self.speed as u32 + 1) {
+            return;
+        }
+        self.counter = 0;
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+/// 任天堂磁碟机: 一个64步的波表通道,带有频率调制
+pub struct Fds {
+    wave: Vec<u8>,
+    wave_write: bool,
+    wave_halt: bool,
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    wave_position: u8,
+    /// 写入波形时保持的输出
+    wave_output: u8,
+    volume: Envelope,
+    modulation: Envelope,
+    /// 停止调制与两个包络
+    envelope_halt: bool,
+    master_volume: u8,
+    master_speed: u8,
+    mod_table: Vec<u8>,
+    mod_position: u8,
+    mod_counter: i8,
+    mod_frequency: u16,
+    mod_halt: bool,
+    mod_accumulator: u32,
+}
+
+snapshot!(
+    Fds,
+    wave,
+    wave_write,
+    wave_halt,
+    wave_frequency,
+    wave_accumulator,
+    wave_position,
+    wave_output,
+    volume,
+    modulation,
+    envelope_halt,
+    master_volume,
+    master_speed,
+    mod_table,
+    mod_position,
+    mod_counter,
+    mod_frequency,
+    mod_halt,
+    mod_accumulator
+);
+
+impl Fds {
+    pub fn new() -> Self {
+        Fds {
+            wave: vec![0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            wave_output: 0,
+            volume: Envelope::default(),
+            modulation: Envelope::default(),
+            envelope_halt: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            mod_table: vec![0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_frequency: 0,
+            mod_halt: true,
+            mod_accumulator: 0,
+        }
+    }
+
+    /// 调制后的波形频率
+    fn modulated_frequency(&self) -> u16 {
+        let pitch = self.wave_frequency as i32;
+        if self.mod_halt {
+            return pitch as u16;
+        }
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (pitch + temp).clamp(0, 0xFFFF) as u16
+    }
+
+    /// 调制单元前进一步
+    fn step_modulation(&mut self) {
+        let value = self.mod_table[self.mod_position as usize];
+        self.mod_counter = if value == 4 {
+            0
+        } else {
+            // 7位有符号数回绕
+            let counter = self.mod_counter as i32 + MOD_ADJUST[value as usize] as i32;
+            (((counter + 64) & 0x7F) - 64) as i8
+        };
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+    }
+}
+
+impl ExpansionAudio for Fds {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[(addr - 0x4040) as usize] = data & 0x3F,
+            0x4080 => self.volume.write(data),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.wave_halt = data & 0x80 != 0;
+                self.envelope_halt = data & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+            }
+            0x4084 => self.modulation.write(data),
+            0x4085 => self.mod_counter = (((data & 0x7F) as i8) << 1) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | (data as u16 & 0x0F) << 8;
+                self.mod_halt = data & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // 每次写入占据调制表的两项
+            0x4088 if self.mod_halt => {
+                let position = self.mod_position as usize & 0x3E;
+                self.mod_table[position] = data & 0b111;
+                self.mod_table[position + 1] = data & 0b111;
+                self.mod_position = (position as u8 + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = data & 0b11;
+            }
+            0x408A => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        // 高2位为开放总线,这里读取为$40
+        match addr {
+            0x4040..=0x407F => Some(self.wave[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulation.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.envelope_halt && !self.wave_halt {
+            self.volume.clock(self.master_speed);
+            self.modulation.clock(self.master_speed);
+        }
+        if !self.mod_halt {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator > 0xFFFF {
+                self.mod_accumulator &= 0xFFFF;
+                self.step_modulation();
+            }
+        }
+        if !self.wave_halt && !self.wave_write {
+            self.wave_accumulator += self.modulated_frequency() as u32;
+            if self.wave_accumulator > 0xFFFF {
+                self.wave_accumulator &= 0xFFFF;
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+            self.wave_output = self.wave[self.wave_position as usize];
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let gain = self.volume.gain.min(32) as u16;
+        let output = self.wave_output as u16 * gain * MASTER_VOLUME[self.master_volume as usize];
+        output as f32 / 30.0 * OUTPUT_SCALE
+    }
+}
+
+#[test]
+fn test_fds_wave() {
+    let mut fds = Fds::new();
+    // 写入锯齿波形
+    fds.write(0x4089, 0x80);
+    for i in 0..64 {
+        fds.write(0x4040 + i, i as u8);
+    }
+    assert_eq!(fds.read(0x4041), Some(0x41));
+    fds.write(0x4089, 0x00);
+    // 直接设置增益为32, 频率$800即每32个周期前进一步
+    fds.write(0x4080, 0x80 | 32);
+    fds.write(0x4082, 0x00);
+    fds.write(0x4083, 0x08);
+    for _ in 0..32 * 10 {
+        fds.clock();
+    }
+    assert_eq!(fds.wave_position, 10);
+    assert_eq!(fds.wave_output, 10);
+    assert!(fds.output() > 0.0);
+    // 停止后回到波形开头
+    fds.write(0x4083, 0x80);
+    assert_eq!(fds.wave_position, 0);
+}
+
+#[test]
+fn test_fds_modulation() {
+    let mut fds = Fds::new();
+    fds.write(0x4080, 0x80 | 32);
+    fds.write(0x4082, 0x00);
+    fds.write(0x4083, 0x01);
+    // 调制表全部为+1,调制增益为4
+    for _ in 0..32 {
+        fds.write(0x4088, 1);
+    }
+    fds.write(0x4084, 0x80 | 4);
+    fds.write(0x4086, 0x00);
+    fds.write(0x4087, 0x08);
+    assert_eq!(fds.modulated_frequency(), 0x100);
+    // 每32个周期调制计数器加1,频率随之升高
+    for _ in 0..32 * 16 {
+        fds.clock();
+    }
+    assert_eq!(fds.mod_counter, 16);
+    assert!(fds.modulated_frequency() > 0x100);
+
+    // 调制计数器在7位有符号数内回绕
+    fds.write(0x4085, 0x3F);
+    assert_eq!(fds.mod_counter, 63);
+    fds.step_modulation();
+    assert_eq!(fds.mod_counter, -64);
+}
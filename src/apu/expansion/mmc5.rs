@@ -0,0 +1,135 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::{apu::pulse::Pulse, snapshot};
+
+// MMC5的声音与其他寄存器
+//
+// $5000~$5003 方波1, 与APU的方波相同但没有扫描单元
+// $5004~$5007 方波2
+// $5010       I--- ---M  PCM: IRQ使能, 读取模式
+// $5011       PCM的电平,写入0被忽略
+// $5015       ---- --21  通道使能(写)/长度计数器状态(读)
+// $5205/$5206 无符号8位乘法器,读取乘积的低/高8位
+// $5C00~$5FF5 扩展RAM,NSF中作为普通的RAM使用
+
+/// 包络与长度计数器由MMC5自己的240Hz时钟驱动
+const FRAME_PERIOD: u16 = 7457;
+/// 方波的音量与APU的方波相同
+const PULSE_SCALE: f32 = PULSE_MAX_OUTPUT / 15.0;
+/// PCM的最大电平约为两个方波之和
+const PCM_SCALE: f32 = PULSE_MAX_OUTPUT * 2.0 / 255.0;
+const EXRAM_START: u16 = 0x5C00;
+const EXRAM_END: u16 = 0x5FF5;
+
+/// MMC5: 两个方波与PCM
+pub struct Mmc5 {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    frame_divider: u16,
+    multiplicand: u8,
+    multiplier: u8,
+    exram: Vec<u8>,
+}
+
+snapshot!(
+    Mmc5,
+    pulse1,
+    pulse2,
+    pcm,
+    frame_divider,
+    multiplicand,
+    multiplier,
+    exram
+);
+
+impl Mmc5 {
+    pub fn new() -> Self {
+        Mmc5 {
+            pulse1: Pulse::mmc5(),
+            pulse2: Pulse::mmc5(),
+            pcm: 0,
+            frame_divider: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            exram: vec![0; (EXRAM_END - EXRAM_START) as usize + 1],
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+}
+
+impl ExpansionAudio for Mmc5 {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            // 方波没有扫描单元,忽略$5001与$5005
+            0x5000 | 0x5002 | 0x5003 => self.pulse1.write(addr - 0x5000, data),
+            0x5004 | 0x5006 | 0x5007 => self.pulse2.write(addr - 0x5004, data),
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse1.length.set_enabled(data & 0b01 != 0);
+                self.pulse2.length.set_enabled(data & 0b10 != 0);
+            }
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            EXRAM_START..=EXRAM_END => self.exram[(addr - EXRAM_START) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5015 => Some(
+                self.pulse1.length.is_active() as u8 | (self.pulse2.length.is_active() as u8) << 1,
+            ),
+            0x5205 => Some(self.product() as u8),
+            0x5206 => Some((self.product() >> 8) as u8),
+            EXRAM_START..=EXRAM_END => Some(self.exram[(addr - EXRAM_START) as usize]),
+            _ => None,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.pulse1.clock();
+        self.pulse2.clock();
+        self.frame_divider += 1;
+        if self.frame_divider == FRAME_PERIOD {
+            self.frame_divider = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.quarter_frame();
+                pulse.half_frame();
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        pulse as f32 * PULSE_SCALE + self.pcm as f32 * PCM_SCALE
+    }
+}
+
+#[test]
+fn test_mmc5() {
+    let mut mmc5 = Mmc5::new();
+    mmc5.write(0x5015, 0b01);
+    // 固定音量12, 周期大于$3FF时APU的方波会被扫描单元静音
+    mmc5.write(0x5000, 0b1011_1100);
+    mmc5.write(0x5002, 0xFF);
+    mmc5.write(0x5003, 0b0000_1111);
+    assert_eq!(mmc5.read(0x5015), Some(0b01));
+    let mut peak = 0;
+    for _ in 0..0x800 * 16 {
+        mmc5.clock();
+        peak = peak.max(mmc5.pulse1.output());
+    }
+    assert_eq!(peak, 12);
+
+    mmc5.write(0x5205, 200);
+    mmc5.write(0x5206, 100);
+    assert_eq!(mmc5.read(0x5205), Some((20000 & 0xFF) as u8));
+    assert_eq!(mmc5.read(0x5206), Some((20000 >> 8) as u8));
+    mmc5.write(0x5C10, 0x55);
+    assert_eq!(mmc5.read(0x5C10), Some(0x55));
+    assert_eq!(mmc5.read(0x5FF8), None);
+}
@@ -0,0 +1,34 @@
+use crate::state::Snapshot;
+
+mod fds;
+mod mmc5;
+mod n163;
+mod sunsoft5b;
+mod vrc6;
+mod vrc7;
+
+pub use fds::Fds;
+pub use mmc5::Mmc5;
+pub use n163::N163;
+pub use sunsoft5b::Sunsoft5b;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+/// APU方波在最大音量时混音后的输出,扩展音源的音量以此为参照
+const PULSE_MAX_OUTPUT: f32 = 0.1488;
+
+/// 卡带上的扩展音源
+///
+/// 扩展音源的寄存器位于$4020~$FFFF,由APU按CPU周期驱动,输出与APU的输出相加
+pub trait ExpansionAudio: Snapshot {
+    /// 写入寄存器,addr为CPU地址,不属于该音源的地址直接忽略
+    fn write(&mut self, addr: u16, data: u8);
+    /// 读取寄存器,不属于该音源的地址返回None
+    fn read(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+    /// 每个CPU周期调用一次
+    fn clock(&mut self);
+    /// 当前的输出,与APU混音器的输出使用同样的量级
+    fn output(&self) -> f32;
+}
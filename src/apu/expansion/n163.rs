@@ -0,0 +1,169 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::snapshot;
+
+// Namco 163的寄存器
+//
+// $F800 IAAA AAAA  内部RAM的地址, I为读写后地址自动加1
+// $4800 DDDD DDDD  读写内部RAM
+//
+// 内部RAM的$40~$7F为8个通道的寄存器,通道n(0~7)位于$40 + 8n:
+// +0 频率低8位, +2 频率中8位, +4 LLLL LLFF  波形长度(256 - L * 4)与频率高2位
+// +1/+3/+5 相位(24位), +6 波形在RAM中的起始位置(以4位采样为单位)
+// +7 ---- VVVV  音量, $7F的bit4~6为启用的通道数减1
+//
+// 波形数据也保存在内部RAM中,每个字节包含两个4位采样,低4位在前
+
+const RAM_SIZE: usize = 0x80;
+/// 每个通道更新一次需要的CPU周期
+const CHANNEL_CYCLES: u8 = 15;
+/// 最大音量的通道约为APU方波的1.5倍
+const OUTPUT_SCALE: f32 = PULSE_MAX_OUTPUT * 1.5 / (8.0 * 15.0);
+
+/// Namco 163: 最多8个波表通道,轮流更新
+pub struct N163 {
+    ram: Vec<u8>,
+    address: u8,
+    auto_increment: bool,
+    divider: u8,
+    /// 下一个更新的通道
+    channel: u8,
+    /// 每个通道最近的采样(0~15)
+    samples: [u8; 8],
+}
+
+snapshot!(
+    N163,
+    ram,
+    address,
+    auto_increment,
+    divider,
+    channel,
+    samples
+);
+
+impl N163 {
+    pub fn new() -> Self {
+        N163 {
+            ram: vec![0; RAM_SIZE],
+            address: 0,
+            auto_increment: false,
+            divider: 0,
+            channel: 7,
+            samples: [8; 8],
+        }
+    }
+
+    /// 启用的通道数,从通道7开始向下
+    fn active_channels(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0b111) + 1
+    }
+
+    fn register(&self, channel: u8, offset: usize) -> u8 {
+        self.ram[0x40 + channel as usize * 8 + offset]
+    }
+
+    fn volume(&self, channel: u8) -> u8 {
+        self.register(channel, 7) & 0x0F
+    }
+
+    /// 累加相位并读取波形中对应的采样
+    fn update_channel(&mut self, channel: u8) {
+        let base = 0x40 + channel as usize * 8;
+        let frequency = self.ram[base] as u32
+            | (self.ram[base + 2] as u32) << 8
+            | (self.ram[base + 4] as u32 & 0b11) << 16;
+        let length = (256 - (self.ram[base + 4] & 0xFC) as u32) << 16;
+        let mut phase = self.ram[base + 1] as u32
+            | (self.ram[base + 3] as u32) << 8
+            | (self.ram[base + 5] as u32) << 16;
+        phase = (phase + frequency) % length;
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let position = ((phase >> 16) + self.ram[base + 6] as u32) as usize & 0xFF;
+        let byte = self.ram[position / 2 % RAM_SIZE];
+        self.samples[channel as usize] = if position.is_multiple_of(2) {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+    }
+}
+
+impl ExpansionAudio for N163 {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800 => {
+                self.ram[self.address as usize] = data;
+                if self.auto_increment {
+                    self.address = (self.address + 1) & 0x7F;
+                }
+            }
+            0xF800 => {
+                self.address = data & 0x7F;
+                self.auto_increment = data & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        // 读取时的地址自增需要可变的访问,NSF很少读取,这里不自增
+        (addr == 0x4800).then(|| self.ram[self.address as usize])
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < CHANNEL_CYCLES {
+            return;
+        }
+        self.divider = 0;
+        self.update_channel(self.channel);
+        let last = 8 - self.active_channels();
+        self.channel = if self.channel <= last {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    /// 硬件轮流输出各个通道,这里输出它们的平均值
+    fn output(&self) -> f32 {
+        let active = self.active_channels();
+        let sum: i32 = (8 - active..8)
+            .map(|channel| {
+                (self.samples[channel as usize] as i32 - 8) * self.volume(channel) as i32
+            })
+            .sum();
+        sum as f32 / active as f32 * OUTPUT_SCALE
+    }
+}
+
+#[test]
+fn test_n163() {
+    let mut n163 = N163::new();
+    // 自动加1,写入8个采样的方波: 0,0,0,0,15,15,15,15
+    n163.write(0xF800, 0x80);
+    for data in [0x00, 0x00, 0xFF, 0xFF] {
+        n163.write(0x4800, data);
+    }
+    n163.write(0xF800, 0x02);
+    assert_eq!(n163.read(0x4800), Some(0xFF));
+
+    // 1个通道(通道7),波形长度8(256 - $F8),频率$10000即每次更新前进1个采样,音量15
+    n163.write(0xF800, 0x80 | 0x78);
+    for data in [0x00, 0x00, 0x00, 0x00, 0xF9, 0x00, 0x00, 0x0F] {
+        n163.write(0x4800, data);
+    }
+    assert_eq!(n163.active_channels(), 1);
+    let mut wave = Vec::new();
+    for _ in 0..8 {
+        for _ in 0..CHANNEL_CYCLES {
+            n163.clock();
+        }
+        wave.push(n163.samples[7]);
+    }
+    assert_eq!(wave, [0, 0, 0, 15, 15, 15, 15, 0]);
+    assert!(n163.output() < 0.0);
+}
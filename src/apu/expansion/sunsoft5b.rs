@@ -0,0 +1,302 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::snapshot;
+
+// Sunsoft 5B(与YM2149兼容)
+//
+// $C000 ---- RRRR  选择内部寄存器
+// $E000 DDDD DDDD  写入选择的寄存器
+//
+// R0~R5  三个方波的周期(12位,低8位在前)
+// R6     噪声周期(5位)
+// R7     --CB Acba  C~A为关闭噪声, c~a为关闭方波
+// R8~R10 ---E VVVV  使用包络, 音量
+// R11~12 包络周期(16位)
+// R13    ---- CAAH  包络形状: 继续, 上升, 交替, 保持
+
+/// 方波与噪声每16个CPU周期被时钟驱动一次
+const TONE_DIVIDER: u8 = 16;
+/// 包络每8个CPU周期被时钟驱动一次,32步的一个周期为256 * 包络周期
+const ENVELOPE_DIVIDER: u8 = 8;
+/// 最大音量的方波约为APU方波的1.2倍
+const OUTPUT_SCALE: f32 = PULSE_MAX_OUTPUT * 1.2;
+
+/// 对数音量,每一级1.5dB,0级静音
+fn volume_table() -> [f32; 32] {
+    let mut table = [0.0; 32];
+    for (level, volume) in table.iter_mut().enumerate().skip(1) {
+        *volume = 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0);
+    }
+    table
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+}
+
+snapshot!(Tone, period, counter, high);
+
+impl Tone {
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Envelope {
+    period: u16,
+    counter: u16,
+    /// 一个周期内的位置(0~31)
+    step: u8,
+    shape: u8,
+    attack: bool,
+    holding: bool,
+}
+
+snapshot!(Envelope, period, counter, step, shape, attack, holding);
+
+impl Envelope {
+    fn set_shape(&mut self, shape: u8) {
+        self.shape = shape & 0x0F;
+        self.step = 0;
+        self.counter = 0;
+        self.attack = shape & 0b0100 != 0;
+        self.holding = false;
+    }
+
+    fn clock(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period.max(1) {
+            return;
+        }
+        self.counter = 0;
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+        // 一个周期结束
+        let (continuing, alternate, hold) = (
+            self.shape & 0b1000 != 0,
+            self.shape & 0b0010 != 0,
+            self.shape & 0b0001 != 0,
+        );
+        if !continuing {
+            self.attack = false;
+            self.holding = true;
+        } else if hold {
+            self.attack ^= alternate;
+            self.holding = true;
+        } else {
+            self.attack ^= alternate;
+            self.step = 0;
+        }
+    }
+
+    /// 当前的音量等级(0~31)
+    fn level(&self) -> u8 {
+        match (self.holding, self.attack) {
+            (true, true) => 31,
+            (true, false) => 0,
+            (false, true) => self.step,
+            (false, false) => 31 - self.step,
+        }
+    }
+}
+
+/// Sunsoft 5B: 三个方波,可以混入噪声并使用包络
+pub struct Sunsoft5b {
+    register: u8,
+    tones: [Tone; 3],
+    /// 方波与噪声的使能,为1时关闭
+    mixer: u8,
+    volumes: [u8; 3],
+    noise_period: u8,
+    noise_counter: u8,
+    /// 17位的线性反馈移位寄存器
+    noise_shift: u32,
+    envelope: Envelope,
+    tone_divider: u8,
+    envelope_divider: u8,
+    volume_table: [f32; 32],
+}
+
+snapshot!(
+    Sunsoft5b,
+    register,
+    tones,
+    mixer,
+    volumes,
+    noise_period,
+    noise_counter,
+    noise_shift,
+    envelope,
+    tone_divider,
+    envelope_divider
+);
+
+impl Sunsoft5b {
+    pub fn new() -> Self {
+        Sunsoft5b {
+            register: 0,
+            tones: [Tone::default(); 3],
+            mixer: 0xFF,
+            volumes: [0; 3],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope: Envelope::default(),
+            tone_divider: 0,
+            envelope_divider: 0,
+            volume_table: volume_table(),
+        }
+    }
+
+    fn write_register(&mut self, data: u8) {
+        match self.register {
+            0..=5 => {
+                let tone = &mut self.tones[self.register as usize / 2];
+                tone.period = if self.register.is_multiple_of(2) {
+                    (tone.period & 0x0F00) | data as u16
+                } else {
+                    (tone.period & 0x00FF) | (data as u16 & 0x0F) << 8
+                };
+            }
+            6 => self.noise_period = data & 0x1F,
+            7 => self.mixer = data,
+            8..=10 => self.volumes[self.register as usize - 8] = data & 0x1F,
+            11 => self.envelope.period = (self.envelope.period & 0xFF00) | data as u16,
+            12 => self.envelope.period = (self.envelope.period & 0x00FF) | (data as u16) << 8,
+            13 => self.envelope.set_shape(data),
+            _ => {}
+        }
+    }
+
+    fn clock_noise(&mut self) {
+        self.noise_counter += 1;
+        if self.noise_counter < self.noise_period.max(1) * 2 {
+            return;
+        }
+        self.noise_counter = 0;
+        let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+        self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+    }
+
+    /// 通道当前的音量等级(0~31),固定音量的16级对应包络的奇数级
+    fn level(&self, channel: usize) -> u8 {
+        let volume = self.volumes[channel];
+        if volume & 0x10 != 0 {
+            self.envelope.level()
+        } else if volume == 0 {
+            0
+        } else {
+            volume * 2 + 1
+        }
+    }
+}
+
+impl ExpansionAudio for Sunsoft5b {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xC000 => self.register = data & 0x0F,
+            0xE000 => self.write_register(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.envelope_divider += 1;
+        if self.envelope_divider == ENVELOPE_DIVIDER {
+            self.envelope_divider = 0;
+            self.envelope.clock();
+        }
+        self.tone_divider += 1;
+        if self.tone_divider == TONE_DIVIDER {
+            self.tone_divider = 0;
+            for tone in &mut self.tones {
+                tone.clock();
+            }
+            self.clock_noise();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let noise = self.noise_shift & 1 != 0;
+        let mut output = 0.0;
+        for channel in 0..3 {
+            let tone_off = self.mixer & (1 << channel) != 0;
+            let noise_off = self.mixer & (0b1000 << channel) != 0;
+            if (self.tones[channel].high || tone_off) && (noise || noise_off) {
+                output += self.volume_table[self.level(channel) as usize];
+            }
+        }
+        output * OUTPUT_SCALE
+    }
+}
+
+#[test]
+fn test_sunsoft5b_tone() {
+    let mut chip = Sunsoft5b::new();
+    let mut write = |register, data| {
+        chip.write(0xC000, register);
+        chip.write(0xE000, data);
+    };
+    // 方波A: 周期4, 音量15, 只开启方波A
+    write(0, 4);
+    write(1, 0);
+    write(7, 0b11_1110);
+    write(8, 15);
+    let mut changes = 0;
+    let mut last = chip.output();
+    for _ in 0..16 * 4 * 10 {
+        chip.clock();
+        if chip.output() != last {
+            changes += 1;
+            last = chip.output();
+        }
+    }
+    // 每4个时钟翻转一次
+    assert_eq!(changes, 10);
+    assert!((chip.volume_table[31] - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_sunsoft5b_envelope() {
+    let mut envelope = Envelope {
+        period: 1,
+        ..Envelope::default()
+    };
+    // 上升后保持在最大值
+    envelope.set_shape(0b1101);
+    assert_eq!(envelope.level(), 0);
+    for _ in 0..31 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.level(), 31);
+    envelope.clock();
+    assert!(envelope.holding);
+    assert_eq!(envelope.level(), 31);
+
+    // 下降后不继续则保持为0
+    envelope.set_shape(0b0000);
+    assert_eq!(envelope.level(), 31);
+    for _ in 0..32 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.level(), 0);
+
+    // 锯齿波不断重复
+    envelope.set_shape(0b1000);
+    for _ in 0..32 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.level(), 31);
+}
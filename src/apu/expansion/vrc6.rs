@@ -0,0 +1,233 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::snapshot;
+
+// VRC6的寄存器
+//
+// $9000/$A000 MDDD VVVV  方波: 数字模式, 占空比, 音量
+// $9001/$A001 FFFF FFFF  方波: 频率低8位
+// $9002/$A002 E--- FFFF  方波: 使能, 频率高4位
+// $9003       ---- -ABH  频率控制: 频率右移8位, 右移4位, 全部暂停
+// $B000       --AA AAAA  锯齿波: 累加值
+// $B001       FFFF FFFF  锯齿波: 频率低8位
+// $B002       E--- FFFF  锯齿波: 使能, 频率高4位
+
+/// 最大音量的方波与APU的方波相当
+const OUTPUT_SCALE: f32 = PULSE_MAX_OUTPUT / 15.0;
+
+/// 12位的分频器,写入$9003后可以变为8位或4位
+#[derive(Debug, Default, Clone, Copy)]
+struct Timer {
+    period: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+snapshot!(Timer, period, counter, enabled);
+
+impl Timer {
+    /// 写入频率的低8位或E--- FFFF
+    fn write(&mut self, high: bool, data: u8) {
+        if high {
+            self.period = (self.period & 0x00FF) | ((data as u16 & 0x0F) << 8);
+            self.enabled = data & 0x80 != 0;
+        } else {
+            self.period = (self.period & 0x0F00) | data as u16;
+        }
+    }
+
+    /// 推进一个CPU周期,返回是否输出一个时钟
+    fn clock(&mut self, shift: u8) -> bool {
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Pulse {
+    /// 数字模式时一直输出音量
+    digital: bool,
+    duty: u8,
+    volume: u8,
+    /// 16步的序列,从15向0递减
+    step: u8,
+    timer: Timer,
+}
+
+snapshot!(Pulse, digital, duty, volume, step, timer);
+
+impl Pulse {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => {
+                self.digital = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0x0F;
+            }
+            1 => self.timer.write(false, data),
+            _ => {
+                self.timer.write(true, data);
+                if !self.timer.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if self.timer.enabled && self.timer.clock(shift) {
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        }
+    }
+
+    /// 当前输出(0~15)
+    fn output(&self) -> u8 {
+        if self.timer.enabled && (self.digital || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Sawtooth {
+    rate: u8,
+    accumulator: u8,
+    /// 每2个时钟累加一次,第14个时钟时清零
+    step: u8,
+    timer: Timer,
+}
+
+snapshot!(Sawtooth, rate, accumulator, step, timer);
+
+impl Sawtooth {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => self.rate = data & 0x3F,
+            1 => self.timer.write(false, data),
+            _ => {
+                self.timer.write(true, data);
+                if !self.timer.enabled {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.timer.enabled || !self.timer.clock(shift) {
+            return;
+        }
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// 当前输出(0~31),取累加值的高5位
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// Konami VRC6: 两个方波与一个锯齿波
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Vrc6 {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    halt: bool,
+    /// 频率右移的位数
+    shift: u8,
+}
+
+snapshot!(Vrc6, pulse1, pulse2, sawtooth, halt, shift);
+
+impl Vrc6 {
+    pub fn new() -> Self {
+        let mut vrc6 = Vrc6::default();
+        vrc6.pulse1.step = 15;
+        vrc6.pulse2.step = 15;
+        vrc6
+    }
+}
+
+impl ExpansionAudio for Vrc6 {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9000..=0x9002 => self.pulse1.write(addr - 0x9000, data),
+            0x9003 => {
+                self.halt = data & 0b001 != 0;
+                self.shift = if data & 0b100 != 0 {
+                    8
+                } else if data & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000..=0xA002 => self.pulse2.write(addr - 0xA000, data),
+            0xB000..=0xB002 => self.sawtooth.write(addr - 0xB000, data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse1.clock(self.shift);
+        self.pulse2.clock(self.shift);
+        self.sawtooth.clock(self.shift);
+    }
+
+    fn output(&self) -> f32 {
+        let output = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        output as f32 * OUTPUT_SCALE
+    }
+}
+
+#[test]
+fn test_vrc6_pulse() {
+    let mut vrc6 = Vrc6::new();
+    // 占空比4/16, 音量10, 周期1(每2个CPU周期一步)
+    vrc6.write(0x9000, 0b0011_1010);
+    vrc6.write(0x9001, 1);
+    vrc6.write(0x9002, 0x80);
+    let mut wave = Vec::new();
+    for _ in 0..16 {
+        vrc6.clock();
+        vrc6.clock();
+        wave.push(vrc6.pulse1.output());
+    }
+    assert_eq!(wave.iter().filter(|v| **v == 10).count(), 4);
+    assert_eq!(wave.iter().filter(|v| **v == 0).count(), 12);
+
+    // 关闭后不再输出
+    vrc6.write(0x9002, 0);
+    assert_eq!(vrc6.output(), 0.0);
+}
+
+#[test]
+fn test_vrc6_sawtooth() {
+    let mut vrc6 = Vrc6::new();
+    vrc6.write(0xB000, 42);
+    vrc6.write(0xB002, 0x80);
+    let mut peak = 0;
+    for _ in 0..14 {
+        vrc6.clock();
+        peak = peak.max(vrc6.sawtooth.output());
+    }
+    // 累加6次后为252,输出其高5位
+    assert_eq!(peak, 252 >> 3);
+    assert_eq!(vrc6.sawtooth.output(), 0);
+}
@@ -0,0 +1,519 @@
+use super::{ExpansionAudio, PULSE_MAX_OUTPUT};
+use crate::snapshot;
+
+// VRC7(YM2413的精简版,6个FM通道,没有节奏模式)
+//
+// $9010 选择内部寄存器
+// $9030 写入选择的寄存器
+//
+// $00~$07 自定义音色:
+//   $00/$01 AVSK MMMM  调制器/载波: 振幅调制, 颤音, 持续型包络, 按键缩放速率, 倍频
+//   $02     KKTT TTTT  调制器的按键缩放电平, 总衰减
+//   $03     KK-C MFFF  载波的按键缩放电平, 载波/调制器使用半波整流, 反馈
+//   $04/$05 AAAA DDDD  调制器/载波的起音与衰减速率
+//   $06/$07 SSSS RRRR  调制器/载波的持续电平与释音速率
+// $10~$15 通道0~5的F-Number低8位
+// $20~$25 --SK OOOF  持续, 按键, 八度, F-Number最高位
+// $30~$35 IIII VVVV  音色(0为自定义), 音量(衰减)
+//
+// 衰减以0.375dB为单位,包络为0(最大音量)~127
+
+/// 每36个CPU周期输出一个采样(3.58MHz的晶振72分频)
+const SAMPLE_CYCLES: u8 = 36;
+/// 内置的15种音色
+#[rustfmt::skip]
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+/// 倍频的2倍,0表示1/2
+const MULTIPLIER: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+/// 最高八度时按键缩放的衰减,按F-Number的高4位查表,每低一个八度减少6dB
+const KSL_TABLE: [i32; 16] = [
+    0, 48, 64, 74, 80, 86, 90, 94, 96, 100, 102, 104, 106, 108, 110, 112,
+];
+/// 包络改变一个单位需要累积的值,速率为4R时每个单位约为15256 >> R个采样
+const ENVELOPE_STEP: u32 = 15256 * 4;
+const MAX_LEVEL: u16 = 127;
+/// 超过此衰减时输出为0
+const SILENT: i32 = 255;
+/// 正弦表的大小,相位的高10位
+const SINE_SIZE: usize = 1024;
+/// 振幅调制约3.7Hz,在0~13之间变化
+const AM_PERIOD: u16 = 517;
+const AM_DEPTH: u8 = 13;
+/// 颤音约6.4Hz,分为8步
+const PM_PERIOD: u16 = 971;
+const PM_TABLE: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
+/// 每个通道的最大输出约为APU方波的1.5倍
+const OUTPUT_SCALE: f32 = PULSE_MAX_OUTPUT * 1.5 / 4096.0;
+
+// 包络的阶段
+const ATTACK: u8 = 0;
+const DECAY: u8 = 1;
+const SUSTAIN: u8 = 2;
+const RELEASE: u8 = 3;
+const OFF: u8 = 4;
+
+/// 音色中一个算子的参数
+struct OperatorPatch {
+    am: bool,
+    vibrato: bool,
+    /// 持续型包络在持续阶段保持音量,否则继续以释音速率衰减
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    /// operator为0时是调制器,为1时是载波
+    fn new(patch: &[u8; 8], operator: usize) -> Self {
+        let flags = patch[operator];
+        OperatorPatch {
+            am: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: flags & 0x0F,
+            key_scale_level: patch[2 + operator] >> 6,
+            rectified: patch[3] & (0x08 << operator) != 0,
+            attack: patch[4 + operator] >> 4,
+            decay: patch[4 + operator] & 0x0F,
+            sustain_level: patch[6 + operator] >> 4,
+            release: patch[6 + operator] & 0x0F,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Operator {
+    /// 19位的相位
+    phase: u32,
+    state: u8,
+    level: u16,
+    counter: u32,
+}
+
+snapshot!(Operator, phase, state, level, counter);
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator {
+            phase: 0,
+            state: OFF,
+            level: MAX_LEVEL,
+            counter: 0,
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.state = ATTACK;
+        self.counter = 0;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != OFF {
+            self.state = RELEASE;
+        }
+    }
+
+    /// 包络的衰减,停止发声时输出为0
+    fn attenuation(&self) -> i32 {
+        if self.state == OFF {
+            SILENT
+        } else {
+            self.level as i32
+        }
+    }
+
+    /// 按速率推进包络,rate为0~63
+    fn advance_envelope(&mut self, rate: u8) {
+        if rate == 0 {
+            return;
+        }
+        if self.state == ATTACK && rate >= 60 {
+            self.level = 0;
+            self.state = DECAY;
+            return;
+        }
+        // 起音是指数曲线,每步改变较多,因此步进更快
+        let step = if self.state == ATTACK {
+            ENVELOPE_STEP / 2
+        } else {
+            ENVELOPE_STEP
+        };
+        self.counter += (4 + rate as u32 % 4) << (rate / 4);
+        while self.counter >= step {
+            self.counter -= step;
+            if self.state == ATTACK {
+                self.level = self.level.saturating_sub((self.level >> 3) + 1);
+                if self.level == 0 {
+                    self.state = DECAY;
+                    break;
+                }
+            } else {
+                self.level = (self.level + 1).min(MAX_LEVEL);
+            }
+        }
+    }
+
+    /// 一个采样的包络
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, sustain: bool) {
+        let rate = |value: u8| {
+            if value == 0 {
+                0
+            } else {
+                (value * 4 + key_scale).min(63)
+            }
+        };
+        match self.state {
+            ATTACK => self.advance_envelope(rate(patch.attack)),
+            DECAY => {
+                self.advance_envelope(rate(patch.decay));
+                if self.level >= patch.sustain_level as u16 * 8 {
+                    self.state = SUSTAIN;
+                }
+            }
+            SUSTAIN if !patch.sustained => self.advance_envelope(rate(patch.release)),
+            RELEASE => {
+                let release = if sustain { 5 } else { patch.release };
+                self.advance_envelope(rate(release));
+                if self.level >= MAX_LEVEL {
+                    self.state = OFF;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Channel {
+    frequency: u16,
+    octave: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    /// 调制器最近两次的输出,用于反馈
+    feedback: [i32; 2],
+}
+
+snapshot!(
+    Channel, frequency, octave, key, sustain, instrument, volume, modulator, carrier, feedback
+);
+
+impl Channel {
+    /// 写入$20~$25
+    fn write_key(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0xFF) | (data as u16 & 1) << 8;
+        self.octave = (data >> 1) & 0b111;
+        self.sustain = data & 0x20 != 0;
+        let key = data & 0x10 != 0;
+        if key && !self.key {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key && self.key {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key = key;
+    }
+
+    /// 按键缩放速率,由八度与F-Number最高位决定
+    fn key_scale_rate(&self, patch: &OperatorPatch) -> u8 {
+        let rate = self.octave << 1 | (self.frequency >> 8) as u8;
+        if patch.key_scale_rate {
+            rate
+        } else {
+            rate >> 2
+        }
+    }
+
+    /// 按键缩放电平的衰减,KSL为1~3时每八度1.5dB、3dB、6dB
+    fn key_scale_level(&self, patch: &OperatorPatch) -> i32 {
+        if patch.key_scale_level == 0 {
+            return 0;
+        }
+        let level =
+            (KSL_TABLE[(self.frequency >> 5) as usize] - 16 * (7 - self.octave as i32)).max(0);
+        level >> (3 - patch.key_scale_level)
+    }
+
+    /// 相位的增量,vibrato为颤音的偏移
+    fn phase_step(&self, patch: &OperatorPatch, vibrato: i32) -> u32 {
+        let mut frequency = self.frequency as i32;
+        if patch.vibrato {
+            frequency += ((frequency >> 6) * vibrato) >> 2;
+        }
+        (frequency as u32 * MULTIPLIER[patch.multiplier as usize]) << self.octave >> 2
+    }
+}
+
+/// Konami VRC7: 6个双算子的FM通道
+pub struct Vrc7 {
+    register: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    divider: u8,
+    am_counter: u16,
+    /// 振幅调制的位置,0~25为一个周期
+    am_step: u8,
+    pm_counter: u16,
+    pm_step: u8,
+    output: i32,
+    sine: Vec<i32>,
+    /// 衰减对应的线性音量(4096为0dB)
+    attenuation: Vec<i32>,
+}
+
+snapshot!(
+    Vrc7, register, custom, channels, divider, am_counter, am_step, pm_counter, pm_step, output
+);
+
+impl Vrc7 {
+    pub fn new() -> Self {
+        let sine = (0..SINE_SIZE)
+            .map(|i| {
+                let angle = i as f64 / SINE_SIZE as f64 * std::f64::consts::TAU;
+                (angle.sin() * 4096.0).round() as i32
+            })
+            .collect();
+        let attenuation = (0..SILENT)
+            .map(|level| (10f64.powf(-(level as f64) * 0.375 / 20.0) * 4096.0).round() as i32)
+            .collect();
+        Vrc7 {
+            register: 0,
+            custom: [0; 8],
+            channels: [Channel::default(); 6],
+            divider: 0,
+            am_counter: 0,
+            am_step: 0,
+            pm_counter: 0,
+            pm_step: 0,
+            output: 0,
+            sine,
+            attenuation,
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> [u8; 8] {
+        match instrument {
+            0 => self.custom,
+            _ => PATCHES[instrument as usize - 1],
+        }
+    }
+
+    fn write_register(&mut self, data: u8) {
+        let register = self.register as usize;
+        match register {
+            0x00..=0x07 => self.custom[register] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[register - 0x10];
+                channel.frequency = (channel.frequency & 0x100) | data as u16;
+            }
+            0x20..=0x25 => self.channels[register - 0x20].write_key(data),
+            0x30..=0x35 => {
+                let channel = &mut self.channels[register - 0x30];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// 振幅调制的衰减(0~13)
+    fn am_level(&self) -> i32 {
+        let step = self.am_step;
+        (if step <= AM_DEPTH {
+            step
+        } else {
+            2 * AM_DEPTH - step
+        }) as i32
+    }
+
+    /// 算子的输出(-4096~4096),phase为10位的相位,加上调制之后查表
+    fn operator_output(&self, phase: i32, attenuation: i32, rectified: bool) -> i32 {
+        let index = phase as usize & (SINE_SIZE - 1);
+        if attenuation >= SILENT || (rectified && index >= SINE_SIZE / 2) {
+            return 0;
+        }
+        (self.sine[index] * self.attenuation[attenuation.max(0) as usize]) >> 12
+    }
+
+    /// 计算一个通道的输出并推进它的相位与包络
+    fn clock_channel(&mut self, index: usize) -> i32 {
+        let mut channel = self.channels[index];
+        let patch = self.patch(channel.instrument);
+        let modulator = OperatorPatch::new(&patch, 0);
+        let carrier = OperatorPatch::new(&patch, 1);
+        let am = self.am_level();
+        let vibrato = PM_TABLE[self.pm_step as usize];
+
+        let total_level = (patch[2] & 0x3F) as i32 * 2;
+        let mut level = channel.modulator.attenuation()
+            + total_level
+            + channel.key_scale_level(&modulator)
+            + if modulator.am { am } else { 0 };
+        let feedback = match patch[3] & 0b111 {
+            0 => 0,
+            shift => (channel.feedback[0] + channel.feedback[1]) >> (9 - shift),
+        };
+        let modulation = self.operator_output(
+            (channel.modulator.phase >> 9) as i32 + feedback,
+            level,
+            modulator.rectified,
+        );
+        channel.feedback = [channel.feedback[1], modulation];
+
+        level = channel.carrier.attenuation()
+            + channel.volume as i32 * 8
+            + channel.key_scale_level(&carrier)
+            + if carrier.am { am } else { 0 };
+        let output = self.operator_output(
+            (channel.carrier.phase >> 9) as i32 + modulation,
+            level,
+            carrier.rectified,
+        );
+
+        let modulator_step = channel.phase_step(&modulator, vibrato);
+        let carrier_step = channel.phase_step(&carrier, vibrato);
+        channel.modulator.phase = (channel.modulator.phase + modulator_step) & 0x7FFFF;
+        channel.carrier.phase = (channel.carrier.phase + carrier_step) & 0x7FFFF;
+        let modulator_rate = channel.key_scale_rate(&modulator);
+        let carrier_rate = channel.key_scale_rate(&carrier);
+        channel
+            .modulator
+            .clock_envelope(&modulator, modulator_rate, channel.sustain);
+        channel
+            .carrier
+            .clock_envelope(&carrier, carrier_rate, channel.sustain);
+        self.channels[index] = channel;
+        output
+    }
+
+    fn clock_lfo(&mut self) {
+        self.am_counter += 1;
+        if self.am_counter == AM_PERIOD {
+            self.am_counter = 0;
+            self.am_step = (self.am_step + 1) % (2 * AM_DEPTH);
+        }
+        self.pm_counter += 1;
+        if self.pm_counter == PM_PERIOD {
+            self.pm_counter = 0;
+            self.pm_step = (self.pm_step + 1) % PM_TABLE.len() as u8;
+        }
+    }
+}
+
+impl ExpansionAudio for Vrc7 {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9010 => self.register = data,
+            0x9030 => self.write_register(data),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < SAMPLE_CYCLES {
+            return;
+        }
+        self.divider = 0;
+        self.output = (0..self.channels.len())
+            .map(|channel| self.clock_channel(channel))
+            .sum();
+        self.clock_lfo();
+    }
+
+    fn output(&self) -> f32 {
+        self.output as f32 * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+fn write_vrc7(vrc7: &mut Vrc7, register: u8, data: u8) {
+    vrc7.write(0x9010, register);
+    vrc7.write(0x9030, data);
+}
+
+#[test]
+fn test_vrc7_tone() {
+    let mut vrc7 = Vrc7::new();
+    // 自定义音色: 调制器衰减最大,载波为持续的正弦波,立即起音
+    let patch = [0x00, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F];
+    for (register, data) in patch.into_iter().enumerate() {
+        write_vrc7(&mut vrc7, register as u8, data);
+    }
+    // 八度5, F-Number 290, 约440Hz
+    write_vrc7(&mut vrc7, 0x30, 0x00);
+    write_vrc7(&mut vrc7, 0x10, (290 & 0xFF) as u8);
+    write_vrc7(&mut vrc7, 0x20, 0x10 | 5 << 1 | (290 >> 8) as u8);
+
+    // 0.1秒内约有88次过零
+    let mut crossings = 0;
+    let mut positive = false;
+    let mut peak = 0.0f32;
+    for _ in 0..178_977 {
+        vrc7.clock();
+        let output = vrc7.output();
+        peak = peak.max(output);
+        if (output > 0.0) != positive && output != 0.0 {
+            crossings += 1;
+            positive = output > 0.0;
+        }
+    }
+    assert!((86..=90).contains(&crossings), "{}", crossings);
+    assert!(peak > OUTPUT_SCALE * 3000.0);
+
+    // 松开按键后以最快的释音速率衰减到0
+    write_vrc7(&mut vrc7, 0x20, 5 << 1 | 1);
+    for _ in 0..SAMPLE_CYCLES as usize * 200 {
+        vrc7.clock();
+    }
+    assert_eq!(vrc7.channels[0].carrier.state, OFF);
+    assert_eq!(vrc7.output(), 0.0);
+}
+
+#[test]
+fn test_vrc7_envelope() {
+    let patch = OperatorPatch::new(&PATCHES[0], 1);
+    let mut operator = Operator::default();
+    operator.key_on();
+    // 起音后衰减到持续电平
+    for _ in 0..150_000 {
+        operator.clock_envelope(&patch, 0, false);
+    }
+    assert_eq!(operator.state, SUSTAIN);
+    assert!(operator.level >= patch.sustain_level as u16 * 8);
+    operator.key_off();
+    for _ in 0..200_000 {
+        operator.clock_envelope(&patch, 0, false);
+    }
+    assert_eq!(operator.state, OFF);
+}
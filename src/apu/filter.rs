@@ -0,0 +1,84 @@
+// 主机的音频输出电路包含三个一阶滤波器:
+// 90Hz的高通, 440Hz的高通, 14kHz的低通
+
+/// 一阶滤波器
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    HighPass {
+        alpha: f32,
+        prev_in: f32,
+        prev_out: f32,
+    },
+    LowPass {
+        alpha: f32,
+        prev_out: f32,
+    },
+}
+
+impl Filter {
+    pub fn high_pass(sample_rate: u32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        Filter::HighPass {
+            alpha: rc / (rc + dt),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    pub fn low_pass(sample_rate: u32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        Filter::LowPass {
+            alpha: dt / (rc + dt),
+            prev_out: 0.0,
+        }
+    }
+
+    /// 主机的滤波器链
+    pub fn console_chain(sample_rate: u32) -> [Filter; 3] {
+        [
+            Filter::high_pass(sample_rate, 90.0),
+            Filter::high_pass(sample_rate, 440.0),
+            Filter::low_pass(sample_rate, 14000.0),
+        ]
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        match self {
+            Filter::HighPass {
+                alpha,
+                prev_in,
+                prev_out,
+            } => {
+                *prev_out = *alpha * (*prev_out + input - *prev_in);
+                *prev_in = input;
+                *prev_out
+            }
+            Filter::LowPass { alpha, prev_out } => {
+                *prev_out += *alpha * (input - *prev_out);
+                *prev_out
+            }
+        }
+    }
+}
+
+#[test]
+fn test_high_pass_removes_dc() {
+    let mut filter = Filter::high_pass(44100, 90.0);
+    let mut output = 0.0;
+    for _ in 0..44100 {
+        output = filter.process(0.5);
+    }
+    assert!(output.abs() < 0.001);
+}
+
+#[test]
+fn test_low_pass_settles() {
+    let mut filter = Filter::low_pass(44100, 14000.0);
+    let mut output = 0.0;
+    for _ in 0..100 {
+        output = filter.process(0.5);
+    }
+    assert!((output - 0.5).abs() < 0.001);
+}
@@ -0,0 +1,192 @@
+use std::cell::Cell;
+
+use crate::{meta::Region, snapshot};
+
+// 帧计数器($4017)
+//
+// MI-- ----
+// ||
+// |+-------- IRQ禁止
+// +--------- 模式(0: 4步, 1: 5步)
+//
+// 4步模式:  - - - f   (f为最后一步时产生的IRQ)
+//           - l - l   (l为半帧时钟)
+//           e e e e   (e为四分之一帧时钟)
+// 5步模式:  - - - - -
+//           - l - - l
+//           e e e - e
+//
+// 4步模式的IRQ在最后一步前后的3个周期(29828~29830)都会被设置,
+// 写入$4017后新的模式在3或4个CPU周期后才生效(取决于写入是否在APU周期上)
+//
+// 注意: CPU执行完一条指令后才按指令的周期数驱动总线,写入发生时APU还停在指令的第一个周期,
+// 所以延迟与APU周期的奇偶都从指令开始计算,而不是从写入的周期计算。
+// 因此新的模式会提前(指令周期数 - 1)个周期生效,例如STA $4017提前3个周期
+
+/// 帧计数器每一步发生的CPU周期,最后一项为序列的长度
+struct Timing {
+    four_step: [u32; 5],
+    five_step: [u32; 6],
+}
+
+static NTSC_TIMING: Timing = Timing {
+    four_step: [7457, 14913, 22371, 29829, 29830],
+    five_step: [7457, 14913, 22371, 29829, 37281, 37282],
+};
+
+static PAL_TIMING: Timing = Timing {
+    four_step: [8313, 16627, 24939, 33253, 33254],
+    five_step: [8313, 16627, 24939, 33253, 41565, 41566],
+};
+
+/// 帧计数器的一步产生的时钟
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+pub struct FrameCounter {
+    timing: &'static Timing,
+    five_step: bool,
+    irq_inhibit: bool,
+    /// 读取$4015时清除
+    pub irq: Cell<bool>,
+    cycles: u32,
+    /// 当前是否为APU周期(每2个CPU周期1次)
+    apu_cycle: bool,
+    /// 等待生效的$4017写入与剩余的周期数
+    pending: Option<(u8, u8)>,
+}
+
+snapshot!(
+    FrameCounter,
+    five_step,
+    irq_inhibit,
+    irq,
+    cycles,
+    apu_cycle,
+    pending
+);
+
+impl FrameCounter {
+    pub fn new(region: Region) -> Self {
+        let timing = match region {
+            Region::Pal => &PAL_TIMING,
+            Region::Ntsc | Region::Dendy => &NTSC_TIMING,
+        };
+        FrameCounter {
+            timing,
+            five_step: false,
+            irq_inhibit: false,
+            irq: Cell::new(false),
+            cycles: 0,
+            apu_cycle: false,
+            pending: None,
+        }
+    }
+
+    /// 写入$4017,IRQ禁止立即生效,模式与计数器的重置延迟3或4个周期(从指令开始计算,见文件开头)
+    pub fn write(&mut self, data: u8) {
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq.set(false);
+        }
+        let delay = if self.apu_cycle { 3 } else { 4 };
+        self.pending = Some((data, delay));
+    }
+
+    /// 每个CPU周期调用一次,写入的模式生效时5步模式会立即产生一次四分之一帧与半帧时钟
+    pub fn clock(&mut self) -> FrameClock {
+        self.apu_cycle = !self.apu_cycle;
+        if let Some((data, delay)) = self.pending {
+            if delay > 1 {
+                self.pending = Some((data, delay - 1));
+            } else {
+                self.pending = None;
+                self.five_step = data & 0b1000_0000 != 0;
+                self.cycles = 0;
+                return FrameClock {
+                    quarter: self.five_step,
+                    half: self.five_step,
+                };
+            }
+        }
+        self.cycles += 1;
+        let mut clock = FrameClock::default();
+        if self.five_step {
+            let steps = &self.timing.five_step;
+            match self.cycles {
+                c if c == steps[0] || c == steps[2] => clock.quarter = true,
+                c if c == steps[1] || c == steps[4] => {
+                    clock.quarter = true;
+                    clock.half = true;
+                }
+                c if c == steps[5] => self.cycles = 0,
+                _ => {}
+            }
+        } else {
+            let steps = &self.timing.four_step;
+            if (steps[3] - 1..=steps[4]).contains(&self.cycles) && !self.irq_inhibit {
+                self.irq.set(true);
+            }
+            match self.cycles {
+                c if c == steps[0] || c == steps[2] => clock.quarter = true,
+                c if c == steps[1] || c == steps[3] => {
+                    clock.quarter = true;
+                    clock.half = true;
+                }
+                c if c == steps[4] => self.cycles = 0,
+                _ => {}
+            }
+        }
+        clock
+    }
+}
+
+#[cfg(test)]
+fn count_clocks(counter: &mut FrameCounter, cycles: u32) -> (usize, usize) {
+    let (mut quarter, mut half) = (0, 0);
+    for _ in 0..cycles {
+        let clock = counter.clock();
+        quarter += clock.quarter as usize;
+        half += clock.half as usize;
+    }
+    (quarter, half)
+}
+
+#[test]
+fn test_four_step_mode() {
+    let mut counter = FrameCounter::new(Region::Ntsc);
+    assert_eq!(count_clocks(&mut counter, 29827), (3, 1));
+    assert!(!counter.irq.get());
+    // IRQ在29828、29829与29830三个周期都会被设置
+    for _ in 29828..=29830 {
+        counter.irq.set(false);
+        counter.clock();
+        assert!(counter.irq.get());
+    }
+    counter.irq.set(false);
+    counter.clock();
+    assert!(!counter.irq.get());
+
+    counter.write(0b0100_0000);
+    assert!(!counter.irq.get());
+    assert_eq!(count_clocks(&mut counter, 29830 * 2 + 4), (8, 4));
+    assert!(!counter.irq.get());
+}
+
+#[test]
+fn test_five_step_mode() {
+    let mut counter = FrameCounter::new(Region::Pal);
+    // 写入在APU周期上时3个周期后生效,否则4个周期
+    counter.write(0b1000_0000);
+    assert_eq!(count_clocks(&mut counter, 3), (0, 0));
+    assert_eq!(count_clocks(&mut counter, 1), (1, 1));
+    counter.clock();
+    counter.write(0b1000_0000);
+    assert_eq!(count_clocks(&mut counter, 2), (0, 0));
+    assert_eq!(count_clocks(&mut counter, 1), (1, 1));
+    assert_eq!(count_clocks(&mut counter, 41566), (4, 2));
+    assert!(!counter.irq.get());
+}
@@ -0,0 +1,73 @@
+use crate::snapshot;
+
+/// 写入的5位序号对应的长度值
+#[rustfmt::skip]
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// 长度计数器
+/// 每个半帧被时钟驱动一次,减到0时通道静音
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LengthCounter {
+    /// 由$4015控制,禁用时计数器保持为0
+    enabled: bool,
+    /// 暂停计数
+    pub halt: bool,
+    counter: u8,
+}
+
+snapshot!(LengthCounter, enabled, halt, counter);
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// 写入寄存器的 LLLL L--- 位装载计数值
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    /// 半帧时钟
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[test]
+fn test_length_counter() {
+    let mut length = LengthCounter::default();
+    // 禁用时不能装载
+    length.load(0b0000_1000);
+    assert!(!length.is_active());
+
+    length.set_enabled(true);
+    length.load(0b0001_1000);
+    for _ in 0..2 {
+        assert!(length.is_active());
+        length.clock();
+    }
+    assert!(!length.is_active());
+
+    length.load(0b0000_0000);
+    length.halt = true;
+    for _ in 0..20 {
+        length.clock();
+    }
+    assert!(length.is_active());
+    length.set_enabled(false);
+    assert!(!length.is_active());
+}
@@ -0,0 +1,48 @@
+// APU各通道的输出通过电阻网络混合,输出与输入不是线性关系
+//
+// pulse_out = 95.52 / (8128 / (pulse1 + pulse2) + 100)
+// tnd_out   = 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+//
+// 两个公式分别只有31种和203种输入,使用查找表计算
+
+/// 非线性混音器
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer {
+            pulse_table,
+            tnd_table,
+        }
+    }
+
+    /// 混合各通道的输出,返回值范围为0~1
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse = self.pulse_table[(pulse1 + pulse2) as usize];
+        let tnd = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+        pulse + tnd
+    }
+}
+
+#[test]
+fn test_mixer() {
+    let mixer = Mixer::new();
+    assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+    // 全部通道最大输出时约为1
+    let max = mixer.mix(15, 15, 15, 15, 127);
+    assert!((max - 1.0).abs() < 0.01);
+    // 非线性:两个方波同时输出小于单独输出之和
+    let single = mixer.mix(15, 0, 0, 0, 0);
+    assert!(mixer.mix(15, 15, 0, 0, 0) < single * 2.0);
+}
@@ -0,0 +1,425 @@
+use crate::{
+    addressable::*,
+    meta::Region,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+mod blip;
+mod dmc;
+mod envelope;
+mod expansion;
+mod filter;
+mod frame_counter;
+mod length;
+mod mixer;
+mod noise;
+mod pulse;
+mod stream;
+mod triangle;
+
+use dmc::Dmc;
+pub use dmc::{DMA_STALL_CYCLES, OAM_DMA_OVERLAP_STALL_CYCLES};
+pub use expansion::{ExpansionAudio, Fds, Mmc5, Sunsoft5b, Vrc6, Vrc7, N163};
+use frame_counter::{FrameClock, FrameCounter};
+use mixer::Mixer;
+use noise::Noise;
+use pulse::Pulse;
+use stream::AudioStream;
+pub use stream::Channel;
+use triangle::Triangle;
+
+// APU的寄存器(相对$4000的偏移)
+// $4000~$4003 方波1
+// $4004~$4007 方波2
+// $4008~$400B 三角波
+// $400C~$400F 噪声
+// $4010~$4013 DMC
+// $4015       通道使能(写)/通道状态(读)
+// $4017       帧计数器(只写,读取时为2P手柄)
+
+/// 默认的输出采样率
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    /// 卡带上的扩展音源
+    expansion: Vec<Box<dyn ExpansionAudio>>,
+
+    region: Region,
+    sample_rate: u32,
+    mixer: Mixer,
+    /// 混合后的输出
+    output: AudioStream,
+    /// 每个通道单独的输出,按Channel::ALL的顺序排列
+    stems: Option<Vec<AudioStream>>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self::with_region(Region::default(), DEFAULT_SAMPLE_RATE)
+    }
+
+    fn with_region(region: Region, sample_rate: u32) -> Self {
+        Apu {
+            pulse1: Pulse::pulse1(),
+            pulse2: Pulse::pulse2(),
+            triangle: Triangle::default(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::new(region),
+            expansion: Vec::new(),
+            region,
+            sample_rate,
+            mixer: Mixer::new(),
+            output: AudioStream::new(region, sample_rate),
+            stems: None,
+        }
+    }
+
+    /// 连接扩展音源
+    pub fn add_expansion(&mut self, expansion: Box<dyn ExpansionAudio>) {
+        self.expansion.push(expansion);
+    }
+
+    fn frame_clock(&mut self, clock: FrameClock) {
+        if clock.quarter {
+            self.pulse1.quarter_frame();
+            self.pulse2.quarter_frame();
+            self.triangle.quarter_frame();
+            self.noise.quarter_frame();
+        }
+        if clock.half {
+            self.pulse1.half_frame();
+            self.pulse2.half_frame();
+            self.triangle.half_frame();
+            self.noise.half_frame();
+        }
+    }
+
+    /// 混合各通道当前的输出
+    fn mix(&self) -> f32 {
+        self.mixer.mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ) + self.expansion_output()
+    }
+
+    fn expansion_output(&self) -> f32 {
+        self.expansion.iter().map(|chip| chip.output()).sum()
+    }
+
+    /// 单个通道经过混音器后的输出
+    fn channel_output(&self, channel: Channel) -> f32 {
+        match channel {
+            Channel::Pulse1 => self.mixer.mix(self.pulse1.output(), 0, 0, 0, 0),
+            Channel::Pulse2 => self.mixer.mix(0, self.pulse2.output(), 0, 0, 0),
+            Channel::Triangle => self.mixer.mix(0, 0, self.triangle.output(), 0, 0),
+            Channel::Noise => self.mixer.mix(0, 0, 0, self.noise.output(), 0),
+            Channel::Dmc => self.mixer.mix(0, 0, 0, 0, self.dmc.output()),
+            Channel::Expansion => self.expansion_output(),
+        }
+    }
+}
+
+pub trait IApu: Addressable {
+    /// 推进若干个CPU周期
+    fn tick(&mut self, cycles: u8);
+    /// 设置电视制式
+    fn set_region(&mut self, region: Region);
+    /// DMC需要读取采样时返回其地址
+    fn poll_dma_request(&self) -> Option<u16>;
+    /// 总线读取采样后交给DMC
+    fn fill_dma(&mut self, data: u8);
+    /// 帧计数器或DMC的IRQ信号
+    fn irq(&self) -> bool;
+    /// 可以读取的音频采样数
+    fn samples_available(&self) -> usize;
+    /// 读取经过重采样与滤波的音频采样(-1~1),返回读取的数量
+    fn read_samples(&mut self, output: &mut [f32]) -> usize;
+    /// 设置输出采样率
+    fn set_sample_rate(&mut self, sample_rate: u32);
+    /// 微调重采样的比例,大于1时每帧产生更多的采样,用于音画同步
+    fn set_rate_adjustment(&mut self, ratio: f64);
+    /// 开启各通道单独的输出
+    fn enable_stems(&mut self);
+    /// 读取单个通道的音频采样,未开启时返回0
+    fn read_stem_samples(&mut self, channel: Channel, output: &mut [f32]) -> usize;
+    /// 写入$4020~$FFFF时交给扩展音源
+    fn write_expansion(&mut self, addr: u16, data: u8);
+    /// 读取扩展音源的寄存器,不属于扩展音源的地址返回None
+    fn read_expansion(&self, addr: u16) -> Option<u8>;
+}
+
+impl IApu for Apu {
+    fn tick(&mut self, cycles: u8) {
+        for cycle in 0..cycles {
+            self.pulse1.clock();
+            self.pulse2.clock();
+            self.triangle.clock();
+            self.noise.clock();
+            self.dmc.clock();
+            for chip in &mut self.expansion {
+                chip.clock();
+            }
+            let clock = self.frame_counter.clock();
+            self.frame_clock(clock);
+
+            let amplitude = self.mix();
+            self.output.update(cycle as u32, amplitude);
+            if let Some(mut stems) = self.stems.take() {
+                for (stem, channel) in stems.iter_mut().zip(Channel::ALL) {
+                    stem.update(cycle as u32, self.channel_output(channel));
+                }
+                self.stems = Some(stems);
+            }
+        }
+        self.output.end_frame(cycles as u32);
+        for stem in self.stems.iter_mut().flatten() {
+            stem.end_frame(cycles as u32);
+        }
+    }
+
+    fn set_region(&mut self, region: Region) {
+        let stems = self.stems.is_some();
+        let expansion = std::mem::take(&mut self.expansion);
+        *self = Self::with_region(region, self.sample_rate);
+        self.expansion = expansion;
+        if stems {
+            self.enable_stems();
+        }
+    }
+
+    fn poll_dma_request(&self) -> Option<u16> {
+        self.dmc.poll_dma_request()
+    }
+
+    fn fill_dma(&mut self, data: u8) {
+        self.dmc.fill_sample_buffer(data);
+    }
+
+    fn irq(&self) -> bool {
+        self.frame_counter.irq.get() || self.dmc.irq
+    }
+
+    fn samples_available(&self) -> usize {
+        self.output.samples_available()
+    }
+
+    fn read_samples(&mut self, output: &mut [f32]) -> usize {
+        self.output.read_samples(output)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.output = AudioStream::new(self.region, sample_rate);
+        if self.stems.is_some() {
+            self.enable_stems();
+        }
+    }
+
+    fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.output.set_rate_adjustment(ratio);
+        for stem in self.stems.iter_mut().flatten() {
+            stem.set_rate_adjustment(ratio);
+        }
+    }
+
+    fn enable_stems(&mut self) {
+        let stems = Channel::ALL
+            .iter()
+            .map(|_| {
+                let mut stem = AudioStream::new(self.region, self.sample_rate);
+                stem.sync_time(&self.output);
+                stem
+            })
+            .collect();
+        self.stems = Some(stems);
+    }
+
+    fn read_stem_samples(&mut self, channel: Channel, output: &mut [f32]) -> usize {
+        let index = Channel::ALL.iter().position(|c| *c == channel).unwrap();
+        match &mut self.stems {
+            Some(stems) => stems[index].read_samples(output),
+            None => 0,
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, data: u8) {
+        for chip in &mut self.expansion {
+            chip.write(addr, data);
+        }
+    }
+
+    fn read_expansion(&self, addr: u16) -> Option<u8> {
+        self.expansion.iter().find_map(|chip| chip.read(addr))
+    }
+}
+
+impl Readable for Apu {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // IF-D NT21  DMC的IRQ, 帧IRQ, DMC剩余字节, 各通道长度计数器
+            0x15 => {
+                let status = self.pulse1.length.is_active() as u8
+                    | (self.pulse2.length.is_active() as u8) << 1
+                    | (self.triangle.length.is_active() as u8) << 2
+                    | (self.noise.length.is_active() as u8) << 3
+                    | (self.dmc.is_active() as u8) << 4
+                    | (self.frame_counter.irq.get() as u8) << 6
+                    | (self.dmc.irq as u8) << 7;
+                // 读取时清除帧IRQ
+                self.frame_counter.irq.set(false);
+                status
+            }
+            // 其余寄存器只写
+            _ => 0,
+        }
+    }
+}
+
+impl Writable for Apu {
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x00..=0x03 => self.pulse1.write(addr, data),
+            0x04..=0x07 => self.pulse2.write(addr - 0x04, data),
+            0x08..=0x0B => self.triangle.write(addr - 0x08, data),
+            0x0C..=0x0F => self.noise.write(addr - 0x0C, data),
+            0x10..=0x13 => self.dmc.write(addr - 0x10, data),
+            0x15 => {
+                self.pulse1.length.set_enabled(data & 0b0_0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0_0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0_0100 != 0);
+                self.noise.length.set_enabled(data & 0b0_1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x17 => self.frame_counter.write(data),
+            _ => {}
+        }
+    }
+}
+
+/// 重采样与滤波属于主机的音频输出,不保存;扩展音源按连接的顺序保存
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        self.frame_counter.save(w);
+        for chip in &self.expansion {
+            chip.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_counter.load(r)?;
+        for chip in &mut self.expansion {
+            chip.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl Addressable for Apu {}
+
+#[test]
+fn test_channel_status() {
+    let mut apu = Apu::new();
+    apu.write(0x03, 0b0000_1000);
+    assert_eq!(apu.read(0x15), 0);
+    apu.write(0x15, 0b0_1111);
+    for addr in [0x03, 0x07, 0x0B, 0x0F] {
+        apu.write(addr, 0b0000_1000);
+    }
+    assert_eq!(apu.read(0x15), 0b0_1111);
+    apu.write(0x15, 0b0_1010);
+    assert_eq!(apu.read(0x15), 0b0_1010);
+}
+
+#[test]
+fn test_frame_irq_status() {
+    let mut apu = Apu::new();
+    apu.tick(255);
+    while !apu.irq() {
+        apu.tick(1);
+    }
+    assert_eq!(apu.read(0x15), 0b0100_0000);
+    assert!(!apu.irq());
+    assert_eq!(apu.read(0x15), 0);
+}
+
+#[test]
+fn test_audio_samples() {
+    let mut apu = Apu::new();
+    apu.set_sample_rate(48000);
+    // 方波1: 占空比50%, 固定音量15, 约440Hz
+    apu.write(0x15, 0b0_0001);
+    apu.write(0x00, 0b1011_1111);
+    apu.write(0x02, 0xFD);
+    apu.write(0x03, 0b0000_1000);
+    for _ in 0..1_789_773 / 60 {
+        apu.tick(1);
+    }
+    let available = apu.samples_available();
+    // 帧末尾不足一个采样的部分留到下一帧
+    assert!((799..=800).contains(&available));
+    let mut output = vec![0.0; available];
+    assert_eq!(apu.read_samples(&mut output), available);
+    assert_eq!(apu.samples_available(), 0);
+    // 经过高通滤波后在0附近摆动
+    assert!(output.iter().any(|s| *s > 0.05));
+    assert!(output.iter().any(|s| *s < -0.05));
+    assert!(output.iter().all(|s| s.abs() <= 1.0));
+}
+
+#[test]
+fn test_rate_adjustment() {
+    let mut apu = Apu::new();
+    apu.set_rate_adjustment(1.01);
+    // 约0.5秒
+    for _ in 0..3509 {
+        apu.tick(255);
+    }
+    let expected = (3509.0 * 255.0) * 44100.0 * 1.01 / 1_789_773.0;
+    assert!((apu.samples_available() as f64 - expected).abs() < 1.0);
+}
+
+#[test]
+fn test_stems() {
+    let mut apu = Apu::new();
+    apu.enable_stems();
+    // 只开启三角波
+    apu.write(0x15, 0b0_0100);
+    apu.write(0x08, 0x7F);
+    apu.write(0x0A, 0x80);
+    apu.write(0x0B, 0b0000_1000);
+    for _ in 0..1_789_773 / 60 / 100 {
+        apu.tick(100);
+    }
+    let mut mixed = vec![0.0; apu.samples_available()];
+    let count = apu.read_samples(&mut mixed);
+    assert!(mixed.iter().any(|s| *s != 0.0));
+    for channel in Channel::ALL {
+        let mut stem = vec![0.0; count];
+        assert_eq!(apu.read_stem_samples(channel, &mut stem), count);
+        if channel == Channel::Triangle {
+            assert_eq!(stem, mixed);
+        } else {
+            assert!(stem.iter().all(|s| *s == 0.0));
+        }
+    }
+}
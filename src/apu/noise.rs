@@ -0,0 +1,143 @@
+use super::{envelope::Envelope, length::LengthCounter};
+use crate::{meta::Region, snapshot};
+
+// 噪声通道的寄存器
+//
+// $400C --LC VVVV  长度计数器暂停/包络循环, 固定音量, 音量/包络周期
+// $400E M--- PPPP  模式, 周期序号
+// $400F LLLL L---  长度计数器序号
+
+/// NTSC的定时器周期(以CPU周期为单位)
+static NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+/// PAL的定时器周期(以CPU周期为单位)
+static PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// 噪声通道
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    periods: &'static [u16; 16],
+    /// 短模式(93步)使用第6位作为反馈,否则使用第1位
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    /// 15位的线性反馈移位寄存器
+    shift_register: u16,
+    envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+// 周期表由制式决定,不需要保存
+snapshot!(
+    Noise,
+    short_mode,
+    timer_period,
+    timer,
+    shift_register,
+    envelope,
+    length
+);
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        let periods = match region {
+            Region::Pal => &PAL_PERIODS,
+            Region::Ntsc | Region::Dendy => &NTSC_PERIODS,
+        };
+        Noise {
+            periods,
+            short_mode: false,
+            timer_period: periods[0],
+            timer: 0,
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    /// 写入寄存器,addr为0~3
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => {
+                self.length.halt = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.timer_period = self.periods[(data & 0b1111) as usize];
+            }
+            3 => {
+                self.length.load(data);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// 每个CPU周期调用一次
+    pub fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// 四分之一帧:包络
+    pub fn quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// 半帧:长度计数器
+    pub fn half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// 当前输出(0~15)
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+fn lfsr_period(short_mode: bool) -> usize {
+    let mut noise = Noise::new(Region::Ntsc);
+    noise.write(2, (short_mode as u8) << 7);
+    let mut steps = 0;
+    loop {
+        for _ in 0..4 {
+            noise.clock();
+        }
+        steps += 1;
+        if noise.shift_register == 1 {
+            return steps;
+        }
+    }
+}
+
+#[test]
+fn test_noise_lfsr_modes() {
+    assert_eq!(lfsr_period(false), 32767);
+    assert_eq!(lfsr_period(true), 93);
+}
+
+#[test]
+fn test_noise_region_periods() {
+    let mut noise = Noise::new(Region::Pal);
+    noise.write(2, 0x0F);
+    assert_eq!(noise.timer_period, 3778);
+    let mut noise = Noise::new(Region::Dendy);
+    noise.write(2, 0x0F);
+    assert_eq!(noise.timer_period, 4068);
+}
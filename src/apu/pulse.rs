@@ -0,0 +1,229 @@
+use super::{envelope::Envelope, length::LengthCounter};
+use crate::snapshot;
+
+// 方波通道的寄存器($4000~$4003为方波1, $4004~$4007为方波2)
+//
+// $4000 DDLC VVVV  占空比, 长度计数器暂停/包络循环, 固定音量, 音量/包络周期
+// $4001 EPPP NSSS  扫描单元: 使能, 周期, 取反, 移位
+// $4002 TTTT TTTT  定时器低8位
+// $4003 LLLL LTTT  长度计数器序号, 定时器高3位
+
+/// 4种占空比的波形序列
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// 扫描单元,周期性地调整方波的定时器周期
+#[derive(Debug, Default, Clone, Copy)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+    /// 方波1取反时使用反码(多减1),方波2使用补码
+    ones_complement: bool,
+}
+
+snapshot!(Sweep, enabled, period, negate, shift, reload, divider);
+
+impl Sweep {
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b1000_0000 != 0;
+        self.period = (data >> 4) & 0b111;
+        self.negate = data & 0b0000_1000 != 0;
+        self.shift = data & 0b111;
+        self.reload = true;
+    }
+
+    /// 计算调整后的目标周期
+    fn target_period(&self, timer_period: u16) -> u16 {
+        let change = timer_period >> self.shift;
+        if self.negate {
+            timer_period.saturating_sub(change + self.ones_complement as u16)
+        } else {
+            timer_period + change
+        }
+    }
+
+    /// 周期过小或目标周期溢出时通道静音,与扫描单元是否使能无关
+    fn is_muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target_period(timer_period) > 0x7FF
+    }
+}
+
+/// 方波通道
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pulse {
+    duty: u8,
+    /// 占空比序列的位置
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    /// 定时器每2个CPU周期(1个APU周期)被时钟驱动一次
+    odd_cycle: bool,
+    envelope: Envelope,
+    sweep: Sweep,
+    pub length: LengthCounter,
+    /// MMC5的方波没有扫描单元,不会因为周期而静音
+    no_sweep: bool,
+}
+
+snapshot!(
+    Pulse,
+    duty,
+    sequence,
+    timer_period,
+    timer,
+    odd_cycle,
+    envelope,
+    sweep,
+    length
+);
+
+impl Pulse {
+    /// 方波1
+    pub fn pulse1() -> Self {
+        let mut pulse = Pulse::default();
+        pulse.sweep.ones_complement = true;
+        pulse
+    }
+
+    /// 方波2
+    pub fn pulse2() -> Self {
+        Pulse::default()
+    }
+
+    /// MMC5的方波
+    pub fn mmc5() -> Self {
+        Pulse {
+            no_sweep: true,
+            ..Pulse::default()
+        }
+    }
+
+    /// 写入寄存器,addr为0~3
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0b0010_0000 != 0;
+                self.envelope.write(data);
+            }
+            1 => self.sweep.write(data),
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data);
+                self.sequence = 0;
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// 每个CPU周期调用一次
+    pub fn clock(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// 四分之一帧:包络
+    pub fn quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// 半帧:长度计数器与扫描单元
+    pub fn half_frame(&mut self) {
+        self.length.clock();
+        let sweep = &mut self.sweep;
+        if sweep.divider == 0
+            && sweep.enabled
+            && sweep.shift > 0
+            && !sweep.is_muting(self.timer_period)
+        {
+            self.timer_period = sweep.target_period(self.timer_period);
+        }
+        if sweep.divider == 0 || sweep.reload {
+            sweep.divider = sweep.period;
+            sweep.reload = false;
+        } else {
+            sweep.divider -= 1;
+        }
+    }
+
+    /// 当前输出(0~15)
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active()
+            || (!self.no_sweep && self.sweep.is_muting(self.timer_period))
+            || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+fn playing_pulse(pulse: &mut Pulse, duty: u8, period: u16) {
+    pulse.length.set_enabled(true);
+    pulse.write(0, duty << 6 | 0b0001_1111);
+    pulse.write(2, period as u8);
+    pulse.write(3, (period >> 8) as u8);
+}
+
+#[test]
+fn test_pulse_duty() {
+    let mut pulse = Pulse::pulse2();
+    playing_pulse(&mut pulse, 2, 8);
+    // 每个步进需要(周期+1)*2个CPU周期
+    let mut wave = Vec::new();
+    for _ in 0..8 {
+        wave.push(pulse.output());
+        for _ in 0..18 {
+            pulse.clock();
+        }
+    }
+    assert_eq!(wave, [0, 15, 15, 15, 15, 0, 0, 0]);
+}
+
+#[test]
+fn test_sweep_ones_complement() {
+    let mut pulse1 = Pulse::pulse1();
+    let mut pulse2 = Pulse::pulse2();
+    for pulse in [&mut pulse1, &mut pulse2] {
+        playing_pulse(pulse, 2, 0x100);
+        // 使能, 周期0, 取反, 移位1
+        pulse.write(1, 0b1000_1001);
+        pulse.half_frame();
+        pulse.half_frame();
+    }
+    assert_eq!(pulse1.timer_period, 0x3F);
+    assert_eq!(pulse2.timer_period, 0x40);
+}
+
+#[test]
+fn test_sweep_muting() {
+    let mut pulse = Pulse::pulse2();
+    playing_pulse(&mut pulse, 3, 7);
+    assert_eq!(pulse.output(), 0);
+    // 目标周期溢出时即使扫描单元未使能也会静音
+    playing_pulse(&mut pulse, 3, 0x600);
+    pulse.write(1, 0b0000_0001);
+    assert_eq!(pulse.output(), 0);
+    pulse.write(1, 0b0000_0010);
+    assert_eq!(pulse.output(), 15);
+}
@@ -0,0 +1,95 @@
+use super::{blip::BlipBuffer, filter::Filter};
+use crate::meta::Region;
+
+/// 音频通道
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+    /// 卡带上的扩展音源
+    Expansion,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::Dmc,
+        Channel::Expansion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+            Channel::Expansion => "expansion",
+        }
+    }
+}
+
+/// 一路音频输出:记录电平变化,经过带限重采样与主机的滤波器后输出
+pub struct AudioStream {
+    clock_rate: f64,
+    sample_rate: u32,
+    /// 上一次的电平
+    amplitude: f32,
+    blip: BlipBuffer,
+    filters: [Filter; 3],
+}
+
+impl AudioStream {
+    pub fn new(region: Region, sample_rate: u32) -> Self {
+        let clock_rate = region.cpu_clock_rate();
+        AudioStream {
+            clock_rate,
+            sample_rate,
+            amplitude: 0.0,
+            blip: BlipBuffer::new(clock_rate, sample_rate),
+            filters: Filter::console_chain(sample_rate),
+        }
+    }
+
+    /// 与另一路输出对齐
+    pub fn sync_time(&mut self, other: &AudioStream) {
+        self.blip.sync_time(&other.blip);
+    }
+
+    /// 当前帧的第cycle个周期的电平
+    pub fn update(&mut self, cycle: u32, amplitude: f32) {
+        if amplitude != self.amplitude {
+            self.blip.add_delta(cycle, amplitude - self.amplitude);
+            self.amplitude = amplitude;
+        }
+    }
+
+    pub fn end_frame(&mut self, cycles: u32) {
+        self.blip.end_frame(cycles);
+    }
+
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.blip
+            .set_rates(self.clock_rate, self.sample_rate as f64 * ratio);
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.blip.samples_available()
+    }
+
+    pub fn read_samples(&mut self, output: &mut [f32]) -> usize {
+        let count = self.blip.read_samples(output);
+        for sample in output.iter_mut().take(count) {
+            for filter in self.filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+        count
+    }
+}
@@ -0,0 +1,121 @@
+use super::length::LengthCounter;
+use crate::snapshot;
+
+// 三角波通道的寄存器
+//
+// $4008 CRRR RRRR  长度计数器暂停/线性计数器控制, 线性计数器重载值
+// $400A TTTT TTTT  定时器低8位
+// $400B LLLL LTTT  长度计数器序号, 定时器高3位
+
+/// 32步的三角波序列
+#[rustfmt::skip]
+static SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// 三角波通道
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Triangle {
+    /// 线性计数器控制,同时作为长度计数器的暂停标志
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    sequence: u8,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+}
+
+snapshot!(
+    Triangle,
+    control,
+    linear_reload_value,
+    linear_counter,
+    linear_reload,
+    sequence,
+    timer_period,
+    timer,
+    length
+);
+
+impl Triangle {
+    /// 写入寄存器,addr为0~3
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0b0111_1111;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// 每个CPU周期调用一次,三角波的定时器以CPU频率运行
+    pub fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            // 两个计数器都不为0时序列才前进
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.sequence = (self.sequence + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// 四分之一帧:线性计数器
+    pub fn quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// 半帧:长度计数器
+    pub fn half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// 当前输出(0~15),计数器归零时保持最后的输出而不是静音
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence as usize]
+    }
+}
+
+#[test]
+fn test_triangle_linear_counter() {
+    let mut triangle = Triangle::default();
+    triangle.length.set_enabled(true);
+    triangle.write(0, 2);
+    triangle.write(2, 0);
+    triangle.write(3, 0b0000_1000);
+    assert_eq!(triangle.output(), 15);
+    // 线性计数器尚未装载,序列不前进
+    triangle.clock();
+    assert_eq!(triangle.output(), 15);
+
+    triangle.quarter_frame();
+    for step in 1..=16 {
+        triangle.clock();
+        assert_eq!(triangle.output(), SEQUENCE[step % 32]);
+    }
+    triangle.quarter_frame();
+    triangle.quarter_frame();
+    triangle.clock();
+    triangle.clock();
+    assert_eq!(triangle.output(), SEQUENCE[16]);
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    AudioSubsystem,
+};
+
+use crate::bus::CpuBus;
+
+/// 期望的输出采样率,实际采样率以设备为准
+const SAMPLE_RATE: i32 = 48000;
+/// 设备每次回调的采样数
+const DEVICE_SAMPLES: u16 = 512;
+/// 动态速率控制允许的最大偏差
+const MAX_RATE_DELTA: f64 = 0.005;
+/// 默认的音频延迟(毫秒)
+pub const DEFAULT_LATENCY_MS: u32 = 60;
+
+/// SDL音频输出
+/// 每帧从APU取出全部采样放入队列,并根据队列长度微调APU的重采样比例,
+/// 使队列长度稳定在目标延迟附近(动态速率控制),
+/// 队列长度超过目标延迟时等待,模拟的速度就由音频时钟决定
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    /// 目标延迟对应的采样数
+    target: usize,
+    buffer: Vec<f32>,
+    paused: bool,
+}
+
+impl AudioOutput {
+    pub fn open(audio: &AudioSubsystem, latency_ms: u32) -> Result<AudioOutput, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(DEVICE_SAMPLES),
+        };
+        let queue = audio.open_queue::<f32, _>(None, &desired)?;
+        let target = queue.spec().freq as usize * latency_ms as usize / 1000;
+        queue.resume();
+        Ok(AudioOutput {
+            queue,
+            target: target.max(DEVICE_SAMPLES as usize),
+            buffer: Vec::new(),
+            paused: false,
+        })
+    }
+
+    /// 设备实际的采样率
+    pub fn sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
+    }
+
+    /// 队列中尚未播放的采样数
+    fn queued(&self) -> usize {
+        self.queue.size() as usize / std::mem::size_of::<f32>()
+    }
+
+    /// 取出APU的采样,返回采样数
+    fn drain(&mut self, bus: &mut dyn CpuBus) -> usize {
+        self.buffer.resize(bus.audio_samples_available(), 0.0);
+        bus.read_audio_samples(&mut self.buffer)
+    }
+
+    /// 将一帧的采样放入队列,并根据队列长度调整重采样比例
+    pub fn push_frame(&mut self, bus: &mut dyn CpuBus) {
+        let count = self.drain(bus);
+        if let Err(error) = self.queue.queue_audio(&self.buffer[..count]) {
+            eprintln!("{}", error);
+        }
+        // 队列比目标短时多产生一些采样,比目标长时少产生一些
+        let fill = self.queued() as f64 / self.target as f64;
+        let ratio = 1.0 + MAX_RATE_DELTA * (1.0 - fill).clamp(-1.0, 1.0);
+        bus.set_audio_rate_adjustment(ratio);
+    }
+
+    /// 等待队列降到目标延迟以下
+    pub fn wait(&self) {
+        while self.queued() > self.target {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// 快进时丢弃采样,队列中只保留目标延迟内的部分,避免声音越来越滞后
+    pub fn skip_frame(&mut self, bus: &mut dyn CpuBus) {
+        if self.queued() < self.target {
+            self.push_frame(bus);
+        } else {
+            self.drain(bus);
+        }
+    }
+
+    /// 暂停时停止播放并清空队列,恢复时从空队列开始重新缓冲
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused == self.paused {
+            return;
+        }
+        self.paused = paused;
+        self.queue.clear();
+        if paused {
+            self.queue.pause();
+        } else {
+            self.queue.resume();
+        }
+    }
+}
@@ -2,7 +2,12 @@ use std::cell::RefCell;
 
 use crate::{
     addressable::{Addressable, Readable, Writable},
+    apu::{Channel, IApu, DMA_STALL_CYCLES, OAM_DMA_OVERLAP_STALL_CYCLES},
     memory::Memory,
+    meta::Region,
+    peripheral::{FourScore, InputDevice, Joypad, Port},
+    ppu::IPpu,
+    state::{Snapshot, StateReader, StateWriter},
 };
 
 //  _______________ $10000  _______________
@@ -45,21 +50,89 @@ use crate::{
 pub struct Bus {
     ram: Box<dyn Addressable>,
     rom: Box<dyn Addressable>,
-    ppu: RefCell<Box<dyn Addressable>>,
+    /// 播放NSF时没有PPU
+    ppu: Option<RefCell<Box<dyn IPpu>>>,
     sram: Box<dyn Addressable>,
-    apu: Box<dyn Addressable>,
-    joypad_p1: Option<Box<dyn Addressable>>,
-    joypad_p2: Option<Box<dyn Addressable>>,
+    apu: Box<dyn IApu>,
+    /// $4020~$5FFF的扩展设备
+    expansion: Option<Box<dyn Addressable>>,
+    /// 1P与2P端口上的输入设备
+    input_p1: Option<Box<dyn InputDevice>>,
+    input_p2: Option<Box<dyn InputDevice>>,
+    /// CPU周期计数
+    cycles: usize,
+    region: Region,
+    /// 尚未推进的PPU点数(以1/5点为单位),用于PAL的3.2倍时钟比
+    ppu_remainder: u32,
+    /// PPU完成了一帧的渲染
+    frame_ready: bool,
+    /// 写入$4014后等待执行的OAM DMA
+    oam_dma_pending: bool,
+    /// OAM DMA使CPU暂停的周期数,在写入的周期确定
+    oam_dma_stall: u16,
+    /// 正在进行的OAM DMA剩余的周期数
+    oam_dma_remaining: u16,
+    /// DMC读取采样时使CPU暂停的周期数
+    dmc_dma_stall: u16,
+}
+
+/// CPU所连接的总线,除读写外还负责驱动其他设备的时钟
+pub trait CpuBus: Addressable {
+    /// CPU执行若干周期后,其他设备随之推进
+    fn tick(&mut self, cycles: u8);
+    /// 轮询NMI中断
+    fn poll_nmi_status(&mut self) -> Option<u8>;
+    /// IRQ信号(电平触发)
+    fn poll_irq_status(&self) -> bool;
+    /// 一帧渲染完成时返回PPU的帧缓冲
+    fn poll_frame(&mut self) -> Option<&[u16]>;
+    /// DMA占用总线时CPU需要暂停的周期数
+    fn poll_dma_stall(&mut self) -> u16;
+    /// 电视制式
+    fn region(&self) -> Region;
+    /// 可以读取的音频采样数
+    fn audio_samples_available(&self) -> usize;
+    /// 读取APU输出的音频采样,返回读取的数量
+    fn read_audio_samples(&mut self, output: &mut [f32]) -> usize;
+    /// 设置音频输出的采样率
+    fn set_audio_sample_rate(&mut self, sample_rate: u32);
+    /// 微调音频重采样的比例,用于音画同步
+    fn set_audio_rate_adjustment(&mut self, ratio: f64);
+    /// 开启APU各通道单独的输出
+    fn enable_audio_stems(&mut self);
+    /// 读取单个通道的音频采样
+    fn read_audio_stem(&mut self, channel: Channel, output: &mut [f32]) -> usize;
+    /// 1P或2P端口上连接的输入设备
+    fn input_device(&mut self, port: Port) -> Option<&mut dyn InputDevice>;
+    /// 玩家的手柄,3P与4P需要连接四人适配器
+    fn joypad(&mut self, player: Port) -> Option<&mut Joypad> {
+        let (port, index) = match player {
+            Port::P1 => (Port::P1, 0),
+            Port::P2 => (Port::P2, 0),
+            Port::P3 => (Port::P1, 1),
+            Port::P4 => (Port::P2, 1),
+        };
+        let device = self.input_device(port)?.as_any_mut();
+        if device.is::<FourScore>() {
+            return device.downcast_mut::<FourScore>()?.joypad(index);
+        }
+        match index {
+            0 => device.downcast_mut(),
+            _ => None,
+        }
+    }
 }
 
 pub struct BusBuilder {
     ram: Option<Box<dyn Addressable>>,
     rom: Option<Box<dyn Addressable>>,
-    ppu: Option<Box<dyn Addressable>>,
+    ppu: Option<Box<dyn IPpu>>,
     sram: Option<Box<dyn Addressable>>,
-    apu: Option<Box<dyn Addressable>>,
-    joypad_p1: Option<Box<dyn Addressable>>,
-    joypad_p2: Option<Box<dyn Addressable>>,
+    apu: Option<Box<dyn IApu>>,
+    expansion: Option<Box<dyn Addressable>>,
+    input_p1: Option<Box<dyn InputDevice>>,
+    input_p2: Option<Box<dyn InputDevice>>,
+    region: Region,
 }
 impl BusBuilder {
     pub fn new() -> Self {
@@ -69,8 +142,10 @@ impl BusBuilder {
             ppu: None,
             sram: None,
             apu: None,
-            joypad_p1: None,
-            joypad_p2: None,
+            expansion: None,
+            input_p1: None,
+            input_p2: None,
+            region: Region::default(),
         }
     }
     pub fn ram(mut self, ram: Box<dyn Addressable>) -> Self {
@@ -81,7 +156,7 @@ impl BusBuilder {
         self.rom = Some(rom);
         self
     }
-    pub fn ppu(mut self, ppu: Box<dyn Addressable>) -> Self {
+    pub fn ppu(mut self, ppu: Box<dyn IPpu>) -> Self {
         self.ppu = Some(ppu);
         self
     }
@@ -89,10 +164,26 @@ impl BusBuilder {
         self.sram = Some(sram);
         self
     }
-    pub fn apu(mut self, apu: Box<dyn Addressable>) -> Self {
+    pub fn apu(mut self, apu: Box<dyn IApu>) -> Self {
         self.apu = Some(apu);
         self
     }
+    pub fn expansion(mut self, expansion: Box<dyn Addressable>) -> Self {
+        self.expansion = Some(expansion);
+        self
+    }
+    pub fn input_p1(mut self, device: Box<dyn InputDevice>) -> Self {
+        self.input_p1 = Some(device);
+        self
+    }
+    pub fn input_p2(mut self, device: Box<dyn InputDevice>) -> Self {
+        self.input_p2 = Some(device);
+        self
+    }
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
     pub fn build(mut self) -> Result<Bus, String> {
         if let None = self.ram {
             return Err("No ram".to_string());
@@ -102,31 +193,40 @@ impl BusBuilder {
             return Err("No rom".to_string());
         }
 
-        if let None = self.ppu {
-            return Err("No ppu".to_string());
-        }
-
         if let None = self.apu {
             return Err("No apu".to_string());
         }
 
         if let None = self.sram {
-            self.sram = Some(Box::new(Memory::new(0x1FFF)))
+            self.sram = Some(Box::new(Memory::new(0x2000)))
         }
 
         let ram = self.ram.unwrap();
         let rom = self.rom.unwrap();
-        let ppu = self.ppu.unwrap();
+        let ppu = self.ppu.map(|mut ppu| {
+            ppu.set_region(self.region);
+            RefCell::new(ppu)
+        });
         let sram = self.sram.unwrap();
-        let apu = self.apu.unwrap();
+        let mut apu = self.apu.unwrap();
+        apu.set_region(self.region);
         Ok(Bus {
             ram,
             rom,
-            ppu: RefCell::new(ppu),
+            ppu,
             sram,
             apu,
-            joypad_p1: self.joypad_p1,
-            joypad_p2: self.joypad_p2,
+            expansion: self.expansion,
+            input_p1: self.input_p1,
+            input_p2: self.input_p2,
+            cycles: 0,
+            region: self.region,
+            ppu_remainder: 0,
+            frame_ready: false,
+            oam_dma_pending: false,
+            oam_dma_stall: 0,
+            oam_dma_remaining: 0,
+            dmc_dma_stall: 0,
         })
     }
 }
@@ -137,8 +237,10 @@ enum Device {
     Ppu(u16),
     Sram(u16),
     Apu(u16),
-    JoypadP1(u16),
-    JoypadP2(u16),
+    OamDma,
+    Expansion(u16),
+    InputP1,
+    InputP2,
     Unknown,
 }
 
@@ -146,11 +248,13 @@ fn address_translation(addr: u16) -> Device {
     match addr {
         0x0000..=0x1FFF => Device::Ram((addr - 0x0000) & 0x07FF),
         0x2000..=0x3FFF => Device::Ppu((addr - 0x2000) & 0x0007),
-        0x4000..=0x4015 => Device::Apu(addr - 0x4000),
-        0x4016 => Device::JoypadP1(addr),
-        0x4017 => Device::JoypadP2(addr),
-        0x4018..=0x5FFF => {
-            // 暂未实现的设备
+        0x4000..=0x4013 | 0x4015 => Device::Apu(addr - 0x4000),
+        0x4014 => Device::OamDma,
+        0x4016 => Device::InputP1,
+        0x4017 => Device::InputP2,
+        0x4020..=0x5FFF => Device::Expansion(addr),
+        0x4018..=0x401F => {
+            // APU与IO的测试寄存器
             println!("Ignoring mem access at 0x{:04X}", addr);
             Device::Unknown
         }
@@ -163,11 +267,17 @@ impl Readable for Bus {
         match address_translation(addr) {
             Device::Ram(addr) => self.ram.read(addr),
             Device::Rom(addr) => self.rom.read(addr),
-            Device::Ppu(addr) => self.ppu.borrow_mut().read(addr),
+            Device::Ppu(addr) => self.ppu.as_ref().map_or(0, |p| p.borrow_mut().read(addr)),
             Device::Sram(addr) => self.sram.read(addr),
             Device::Apu(addr) => self.apu.read(addr),
-            Device::JoypadP1(addr) => self.joypad_p1.as_ref().map_or(0, |p| p.read(addr)),
-            Device::JoypadP2(addr) => self.joypad_p2.as_ref().map_or(0, |p| p.read(addr)),
+            // $4014只写
+            Device::OamDma => 0,
+            Device::Expansion(addr) => self
+                .apu
+                .read_expansion(addr)
+                .unwrap_or_else(|| self.expansion.as_ref().map_or(0, |e| e.read(addr))),
+            Device::InputP1 => self.read_input(&self.input_p1),
+            Device::InputP2 => self.read_input(&self.input_p2),
             Device::Unknown => 0,
         }
     }
@@ -175,24 +285,339 @@ impl Readable for Bus {
 
 impl Writable for Bus {
     fn write(&mut self, addr: u16, data: u8) {
+        // 扩展音源的寄存器可能与卡带的其他寄存器重叠
+        if addr >= 0x4020 {
+            self.apu.write_expansion(addr, data);
+        }
         match address_translation(addr) {
             Device::Ram(addr) => self.ram.write(addr, data),
             Device::Rom(addr) => self.rom.write(addr, data),
-            Device::Ppu(addr) => self.ppu.borrow_mut().write(addr, data),
+            Device::Ppu(addr) => {
+                if let Some(ppu) = &self.ppu {
+                    ppu.borrow_mut().write(addr, data);
+                }
+            }
             Device::Sram(addr) => self.sram.write(addr, data),
             Device::Apu(addr) => self.apu.write(addr, data),
-            Device::JoypadP1(addr) => {
-                if let Some(joypad) = &mut self.joypad_p1 {
-                    joypad.write(addr, data);
+            Device::OamDma => self.oam_dma(data),
+            Device::Expansion(addr) => {
+                if let Some(expansion) = &mut self.expansion {
+                    expansion.write(addr, data);
                 }
             }
-            Device::JoypadP2(addr) => {
-                if let Some(joypad) = &mut self.joypad_p2 {
-                    joypad.write(addr, data);
+            // OUT0~OUT2同时连接到两个端口,手柄的strobe使用OUT0
+            Device::InputP1 => {
+                for device in [&mut self.input_p1, &mut self.input_p2]
+                    .into_iter()
+                    .flatten()
+                {
+                    device.write(data & 0b111);
                 }
             }
+            // $4017的读取来自2P端口,写入则是APU的帧计数器
+            Device::InputP2 => self.apu.write(0x17, data),
             Device::Unknown => {}
         }
     }
 }
+/// 每个设备保存在单独的段中,没有连接的设备不保存
+impl Snapshot for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        w.section(b"BUS ", |w| {
+            self.cycles.save(w);
+            self.ppu_remainder.save(w);
+            self.frame_ready.save(w);
+            self.oam_dma_pending.save(w);
+            self.dmc_dma_stall.save(w);
+            self.oam_dma_stall.save(w);
+            self.oam_dma_remaining.save(w);
+        });
+        w.section(b"RAM ", |w| self.ram.save(w));
+        w.section(b"SRAM", |w| self.sram.save(w));
+        w.section(b"MAPR", |w| self.rom.save(w));
+        if let Some(ppu) = &self.ppu {
+            w.section(b"PPU ", |w| ppu.borrow().save(w));
+        }
+        w.section(b"APU ", |w| self.apu.save(w));
+        if let Some(expansion) = &self.expansion {
+            w.section(b"EXP ", |w| expansion.save(w));
+        }
+        if let Some(device) = &self.input_p1 {
+            w.section(b"INP1", |w| device.save(w));
+        }
+        if let Some(device) = &self.input_p2 {
+            w.section(b"INP2", |w| device.save(w));
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        // 先检查是否是同一个游戏
+        r.section(b"MAPR", |r| self.rom.load(r))?;
+        r.section(b"BUS ", |r| {
+            self.cycles.load(r)?;
+            self.ppu_remainder.load(r)?;
+            self.frame_ready.load(r)?;
+            self.oam_dma_pending.load(r)?;
+            self.dmc_dma_stall.load(r)?;
+            self.oam_dma_stall.load(r)?;
+            self.oam_dma_remaining.load(r)
+        })?;
+        r.section(b"RAM ", |r| self.ram.load(r))?;
+        r.section(b"SRAM", |r| self.sram.load(r))?;
+        if let Some(ppu) = &mut self.ppu {
+            r.section(b"PPU ", |r| ppu.get_mut().load(r))?;
+        }
+        r.section(b"APU ", |r| self.apu.load(r))?;
+        if let Some(expansion) = &mut self.expansion {
+            r.section(b"EXP ", |r| expansion.load(r))?;
+        }
+        if let Some(device) = &mut self.input_p1 {
+            r.section(b"INP1", |r| device.load(r))?;
+        }
+        if let Some(device) = &mut self.input_p2 {
+            r.section(b"INP2", |r| device.load(r))?;
+        }
+        Ok(())
+    }
+}
+
 impl Addressable for Bus {}
+
+/// $4016/$4017的D5~D7没有连接,保留着总线上一次传输的值,即地址的高字节
+const INPUT_OPEN_BUS: u8 = 0x40;
+
+impl Bus {
+    /// 读取输入设备的D0~D4,其余位为开放总线
+    fn read_input(&self, device: &Option<Box<dyn InputDevice>>) -> u8 {
+        let ppu = self.ppu.as_ref().map(|ppu| ppu.borrow());
+        let data = device.as_ref().map_or(0, |device| {
+            device.read(ppu.as_deref().map(|ppu| ppu.as_ref()))
+        });
+        data & 0b1_1111 | INPUT_OPEN_BUS
+    }
+
+    /// 将CPU内存中$XX00~$XXFF的256字节复制到OAM
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        let mut data = [0; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.read(start + i as u16);
+        }
+        if let Some(ppu) = &mut self.ppu {
+            ppu.get_mut().write_oam_dma(&data);
+        }
+        self.oam_dma_pending = true;
+    }
+}
+
+impl CpuBus for Bus {
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+        self.oam_dma_remaining = self.oam_dma_remaining.saturating_sub(cycles as u16);
+        // 写入$4014的是指令的最后一个周期,DMA从下一个周期开始:
+        // 1个等待周期加上256次读写,开始于奇数周期时需要额外1个对齐周期
+        if std::mem::take(&mut self.oam_dma_pending) {
+            self.oam_dma_stall = 513 + (self.cycles & 1) as u16;
+            self.oam_dma_remaining = self.oam_dma_stall;
+        }
+        // NTSC与Dendy的PPU时钟频率是CPU的3倍,PAL为3.2倍
+        if let Some(ppu) = &mut self.ppu {
+            let dots = cycles as u32 * self.region.ppu_dots_per_cpu_cycle_x5() + self.ppu_remainder;
+            self.ppu_remainder = dots % 5;
+            if ppu.get_mut().tick((dots / 5) as u8) {
+                self.frame_ready = true;
+            }
+        }
+        self.apu.tick(cycles);
+        if let Some(addr) = self.apu.poll_dma_request() {
+            let data = self.read(addr);
+            self.apu.fill_dma(data);
+            self.dmc_dma_stall += if self.oam_dma_remaining > 0 {
+                OAM_DMA_OVERLAP_STALL_CYCLES
+            } else {
+                DMA_STALL_CYCLES
+            };
+        }
+    }
+
+    fn poll_irq_status(&self) -> bool {
+        self.apu.irq()
+    }
+
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        self.ppu.as_mut()?.get_mut().poll_nmi_interrupt()
+    }
+
+    fn poll_dma_stall(&mut self) -> u16 {
+        std::mem::take(&mut self.dmc_dma_stall) + std::mem::take(&mut self.oam_dma_stall)
+    }
+
+    fn region(&self) -> Region {
+        self.region
+    }
+
+    fn audio_samples_available(&self) -> usize {
+        self.apu.samples_available()
+    }
+
+    fn read_audio_samples(&mut self, output: &mut [f32]) -> usize {
+        self.apu.read_samples(output)
+    }
+
+    fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    fn set_audio_rate_adjustment(&mut self, ratio: f64) {
+        self.apu.set_rate_adjustment(ratio);
+    }
+
+    fn enable_audio_stems(&mut self) {
+        self.apu.enable_stems();
+    }
+
+    fn read_audio_stem(&mut self, channel: Channel, output: &mut [f32]) -> usize {
+        self.apu.read_stem_samples(channel, output)
+    }
+
+    fn input_device(&mut self, port: Port) -> Option<&mut dyn InputDevice> {
+        let device = match port {
+            Port::P1 => self.input_p1.as_mut(),
+            Port::P2 => self.input_p2.as_mut(),
+            Port::P3 | Port::P4 => None,
+        };
+        Some(device?.as_mut())
+    }
+
+    fn poll_frame(&mut self) -> Option<&[u16]> {
+        if !self.frame_ready {
+            return None;
+        }
+        self.frame_ready = false;
+        Some(self.ppu.as_mut()?.get_mut().frame())
+    }
+}
+
+#[test]
+fn test_oam_dma() {
+    use crate::{apu::Apu, ppu::Ppu, rom::test::test_rom};
+    let mut bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .build()
+        .unwrap();
+    for i in 0..=255u8 {
+        bus.write(0x0200 + i as u16, i);
+    }
+    // OAMADDR非0时从该位置开始写入并回绕
+    bus.write(0x2003, 0x10);
+    bus.write(0x4014, 0x02);
+    bus.write(0x2003, 0x10);
+    assert_eq!(bus.read(0x2004), 0x00);
+    bus.write(0x2003, 0x0F);
+    assert_eq!(bus.read(0x2004), 0xFF);
+
+    // STA $4014在第4个周期写入,DMA从第4个周期(偶数)开始
+    bus.tick(4);
+    assert_eq!(bus.poll_dma_stall(), 513);
+    assert_eq!(bus.poll_dma_stall(), 0);
+    bus.tick(1);
+    bus.write(0x4014, 0x02);
+    bus.tick(4);
+    assert_eq!(bus.poll_dma_stall(), 514);
+}
+
+#[test]
+fn test_pal_clock_ratio() {
+    use crate::{apu::Apu, ppu::Ppu, rom::test::test_rom};
+    let mut bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .region(Region::Pal)
+        .build()
+        .unwrap();
+    // PAL每帧312*341点,即33247.5个CPU周期
+    let mut cycles = 0;
+    while bus.poll_frame().is_none() {
+        bus.tick(1);
+        cycles += 1;
+    }
+    assert_eq!(cycles, 33248);
+}
+
+#[test]
+fn test_dmc_dma_stall() {
+    use crate::{apu::Apu, ppu::Ppu, rom::test::test_rom};
+    let mut bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .build()
+        .unwrap();
+    // 禁止帧IRQ,开启DMC的IRQ并播放1字节的采样
+    bus.write(0x4017, 0x40);
+    bus.write(0x4010, 0x80);
+    bus.write(0x4013, 0);
+    bus.write(0x4015, 0x10);
+    assert_eq!(bus.read(0x4015) & 0x10, 0x10);
+    bus.tick(1);
+    assert_eq!(bus.poll_dma_stall(), DMA_STALL_CYCLES);
+    assert_eq!(bus.poll_dma_stall(), 0);
+    assert!(bus.poll_irq_status());
+    assert_eq!(bus.read(0x4015), 0x80);
+}
+
+#[test]
+fn test_dmc_dma_during_oam_dma() {
+    use crate::{apu::Apu, ppu::Ppu, rom::test::test_rom};
+    let mut bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .build()
+        .unwrap();
+    bus.write(0x4017, 0x40);
+    bus.write(0x4010, 0x80);
+    bus.write(0x4013, 0);
+    // 同一个周期开始OAM DMA与DMC的读取
+    bus.write(0x4014, 0x02);
+    bus.write(0x4015, 0x10);
+    bus.tick(1);
+    let stall = bus.poll_dma_stall();
+    // DMC的读取发生在OAM DMA之中,只额外暂停2个周期
+    for _ in 0..stall {
+        bus.tick(1);
+    }
+    assert_eq!(
+        stall + bus.poll_dma_stall(),
+        514 + OAM_DMA_OVERLAP_STALL_CYCLES
+    );
+}
+
+#[test]
+fn test_joypad_strobe() {
+    use crate::{apu::Apu, peripheral::Button, rom::test::test_rom};
+    let mut bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .apu(Box::new(Apu::new()))
+        .input_p1(Box::new(Joypad::new()))
+        .input_p2(Box::new(Joypad::new()))
+        .build()
+        .unwrap();
+    bus.joypad(Port::P1).unwrap().set_button(Button::A, true);
+    bus.joypad(Port::P2).unwrap().set_button(Button::B, true);
+    // 写入$4016同时重置两个手柄
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    assert_eq!(bus.read(0x4016), 0x41);
+    assert_eq!(bus.read(0x4016), 0x40);
+    assert_eq!(bus.read(0x4017), 0x40);
+    assert_eq!(bus.read(0x4017), 0x41);
+}
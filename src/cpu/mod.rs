@@ -2,7 +2,10 @@ mod opcode;
 mod register;
 mod status;
 
-use crate::addressable::Addressable;
+use crate::{
+    bus::CpuBus,
+    state::{self, Snapshot, StateReader, StateWriter},
+};
 use register::Register;
 use status::StatusFlagRegister;
 
@@ -34,7 +37,9 @@ pub enum AddressingMode {
 
 pub struct CPU {
     pub register: Register,
-    pub bus: Box<dyn Addressable>,
+    pub bus: Box<dyn CpuBus>,
+    /// 已执行的CPU周期数
+    pub cycles: usize,
 }
 /// 触发CPU外部中断
 impl CPU {
@@ -42,9 +47,32 @@ impl CPU {
     pub fn reset(&mut self) {
         self.register = Register::default();
         self.register.pc = self.read_u16(0xFFFC);
+        // 复位过程需要7个周期
+        self.cycles = 0;
+        self.tick(7);
+    }
+    /// CPU的irq引脚触发,APU的帧计数器与DMC会产生
+    pub fn irq(&mut self) {
+        self.stack_push_u16(self.register.pc);
+        let mut status = self.register.status;
+        status.break_command = false;
+        status.unused = true;
+        self.stack_push(status.into());
+        self.register.status.interrupt_disable = true;
+        self.register.pc = self.read_u16(0xFFFE);
+        self.tick(7);
+    }
+    /// CPU的nmi引脚触发,PPU在垂直消隐开始时产生
+    pub fn nmi(&mut self) {
+        self.stack_push_u16(self.register.pc);
+        let mut status = self.register.status;
+        status.break_command = false;
+        status.unused = true;
+        self.stack_push(status.into());
+        self.register.status.interrupt_disable = true;
+        self.register.pc = self.read_u16(0xFFFA);
+        self.tick(7);
     }
-    /// CPU的irq引脚触发
-    pub fn irq(&mut self) {}
 }
 
 impl CPU {
@@ -54,9 +82,32 @@ impl CPU {
         }
     }
 
+    /// 像JSR一样调用子程序并执行到它返回为止,用于播放NSF
+    /// 执行超过max_cycles个周期仍未返回时放弃,返回false
+    pub fn call_subroutine(&mut self, addr: u16, max_cycles: usize) -> bool {
+        // 返回地址不会被执行,只用于判断子程序是否已经返回
+        const RETURN_ADDRESS: u16 = 0x4100;
+        let sp = self.register.sp;
+        self.stack_push_u16(RETURN_ADDRESS - 1);
+        self.register.pc = addr;
+        let start = self.cycles;
+        while self.register.pc != RETURN_ADDRESS || self.register.sp != sp {
+            if self.cycles - start > max_cycles || !self.run_one_instruction() {
+                self.register.sp = sp;
+                return false;
+            }
+        }
+        true
+    }
+
     /// 返回值为false表示程序结束
-    fn run_one_instruction(&mut self) -> bool {
+    pub fn run_one_instruction(&mut self) -> bool {
         use opcode::get_opcode_by_code;
+        if self.bus.poll_nmi_status().is_some() {
+            self.nmi();
+        } else if self.bus.poll_irq_status() && !self.register.status.interrupt_disable {
+            self.irq();
+        }
         let code = self.read(self.register.pc);
         self.register.pc += 1;
         let old_pc = self.register.pc;
@@ -160,8 +211,19 @@ impl CPU {
         if old_pc == self.register.pc {
             self.register.pc += (opcode.length - 1) as u16;
         }
+        self.tick(opcode.cycles);
+        // DMA期间CPU暂停,其他设备照常运行
+        for _ in 0..self.bus.poll_dma_stall() {
+            self.tick(1);
+        }
         true
     }
+
+    /// 推进CPU周期,同时驱动总线上的其他设备
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+        self.bus.tick(cycles);
+    }
 }
 
 /// 非官方指令
@@ -321,14 +383,48 @@ impl CPU {
 }
 
 impl CPU {
-    pub fn new(bus: Box<dyn Addressable>) -> Self {
+    pub fn new(bus: Box<dyn CpuBus>) -> Self {
         CPU {
             register: Register::default(),
             bus,
+            cycles: 0,
         }
     }
 }
 
+impl CPU {
+    /// 保存整台机器的状态
+    pub fn save_state(&self) -> Vec<u8> {
+        state::encode(self)
+    }
+
+    /// 读取存档,失败时机器保持原来的状态
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let backup = self.save_state();
+        state::decode(data, self).inspect_err(|_| {
+            state::decode(&backup, self).unwrap();
+        })
+    }
+}
+
+impl Snapshot for CPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.section(b"CPU ", |w| {
+            self.register.save(w);
+            self.cycles.save(w);
+        });
+        self.bus.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.section(b"CPU ", |r| {
+            self.register.load(r)?;
+            self.cycles.load(r)
+        })?;
+        self.bus.load(r)
+    }
+}
+
 /// 两字节打包成u16
 fn pack_u16(high: u8, low: u8) -> u16 {
     let high = high as u16;
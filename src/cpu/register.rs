@@ -1,4 +1,5 @@
 use super::status::StatusFlagRegister;
+use crate::snapshot;
 
 pub struct Register {
     /// 寄存器A
@@ -15,6 +16,8 @@ pub struct Register {
     pub sp: u8,
 }
 
+snapshot!(Register, a, x, y, status, pc, sp);
+
 const STACK_RESET: u8 = 0xFD;
 
 impl Default for Register {
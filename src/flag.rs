@@ -49,6 +49,19 @@ macro_rules! flag_reg {
                 result
             }
         }
+        impl $crate::state::Snapshot for $sn {
+            fn save(&self, w: &mut $crate::state::StateWriter) {
+                let bits: u8 = (*self).into();
+                $crate::state::Snapshot::save(&bits, w);
+            }
+
+            fn load(&mut self, r: &mut $crate::state::StateReader) -> Result<(), String> {
+                let mut bits = 0u8;
+                $crate::state::Snapshot::load(&mut bits, r)?;
+                *self = Self::from(bits);
+                Ok(())
+            }
+        }
         use crate::flag::FlagRegister;
         impl FlagRegister for $sn {
             fn update(&mut self, data: u8) {
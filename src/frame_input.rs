@@ -0,0 +1,262 @@
+use crate::{
+    bus::CpuBus,
+    peripheral::{Button, JoypadButton, Port},
+};
+
+/// 手柄的数量(使用四人适配器时为4)
+pub const PLAYERS: usize = 4;
+/// 默认的连发周期: 按下与释放各2帧
+const DEFAULT_TURBO_FRAMES: u8 = 2;
+
+/// 主机输入可以控制的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Control {
+    Button(Button),
+    /// 按住时以连发周期反复按下与释放
+    Turbo(Button),
+}
+
+impl Control {
+    /// 按钮名,连发按钮加上"turbo_"前缀
+    pub fn from_name(name: &str) -> Result<Control, String> {
+        let name = name.to_ascii_lowercase();
+        match name.strip_prefix("turbo_") {
+            Some(button) => Ok(Control::Turbo(Button::from_name(button)?)),
+            None => Ok(Control::Button(Button::from_name(&name)?)),
+        }
+    }
+}
+
+/// 将手柄状态转换为按钮字母,顺序与FM2相同,没有按下的按钮为'.'
+pub fn format_buttons(state: u8) -> String {
+    BUTTON_LETTERS
+        .iter()
+        .map(|(letter, button)| {
+            if state & button_mask(*button) != 0 {
+                *letter
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// 解析按钮字母,'.'与空格表示没有按下
+pub fn parse_buttons(text: &str) -> Result<u8, String> {
+    text.chars().try_fold(0, |state, c| match c {
+        '.' | ' ' => Ok(state),
+        _ => BUTTON_LETTERS
+            .iter()
+            .find(|(letter, _)| *letter == c.to_ascii_uppercase())
+            .map(|(_, button)| state | button_mask(*button))
+            .ok_or(format!("Unknown button: {}", c)),
+    })
+}
+
+/// FM2中按钮的顺序与字母
+const BUTTON_LETTERS: [(char, Button); 8] = [
+    ('R', Button::Right),
+    ('L', Button::Left),
+    ('D', Button::Down),
+    ('U', Button::Up),
+    ('T', Button::Start),
+    ('S', Button::Select),
+    ('B', Button::B),
+    ('A', Button::A),
+];
+
+/// 按钮在手柄状态中的位,与上报顺序相同
+fn button_mask(button: Button) -> u8 {
+    1 << Button::ALL.iter().position(|b| *b == button).unwrap()
+}
+
+/// 输入宏,每帧一个手柄状态
+///
+/// 文本格式每行为一帧的按钮字母(RLDUTSBA),`.`表示没有按下的按钮,
+/// 可以在后面加上`*帧数`重复多帧,`#`之后为注释,例如
+/// ```text
+/// R*10    # 向右走10帧
+/// RA*4    # 跳跃
+/// .
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    frames: Vec<u8>,
+}
+
+impl Macro {
+    pub fn load(path: &str) -> Result<Macro, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Macro, String> {
+        let mut frames = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (buttons, count) = match line.split_once('*') {
+                Some((buttons, count)) => (
+                    buttons,
+                    count
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid frame count: {}", line))?,
+                ),
+                None => (line, 1),
+            };
+            let state = parse_buttons(buttons.trim())?;
+            frames.extend(std::iter::repeat_n(state, count));
+        }
+        Ok(Macro { frames })
+    }
+}
+
+/// 正在播放的宏
+struct Playback {
+    player: usize,
+    frames: Vec<u8>,
+    position: usize,
+}
+
+/// 主机输入与手柄之间的一层,每个模拟帧计算一次各手柄的状态
+/// 连发与宏都按模拟的帧数计时,与实际的时间无关,
+/// 因此相同的主机输入总是得到相同的手柄状态,也可以被录像记录
+pub struct FrameInput {
+    /// 按住的普通按钮
+    held: [u8; PLAYERS],
+    /// 按住的连发按钮
+    turbo: [u8; PLAYERS],
+    /// 每个按钮连发时按下与释放的帧数,按上报顺序排列
+    turbo_frames: [u8; 8],
+    playbacks: Vec<Playback>,
+    /// 已经输出的帧数
+    frame: u64,
+}
+
+impl FrameInput {
+    pub fn new() -> Self {
+        FrameInput {
+            held: [0; PLAYERS],
+            turbo: [0; PLAYERS],
+            turbo_frames: [DEFAULT_TURBO_FRAMES; 8],
+            playbacks: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    /// 设置连发时按下与释放各持续的帧数
+    pub fn set_turbo_frames(&mut self, button: Button, frames: u8) {
+        let index = Button::ALL.iter().position(|b| *b == button).unwrap();
+        self.turbo_frames[index] = frames.max(1);
+    }
+
+    /// 主机输入按下或释放
+    pub fn set(&mut self, player: Port, control: Control, pressed: bool) {
+        let (state, button) = match control {
+            Control::Button(button) => (&mut self.held[player.index()], button),
+            Control::Turbo(button) => (&mut self.turbo[player.index()], button),
+        };
+        if pressed {
+            *state |= button_mask(button);
+        } else {
+            *state &= !button_mask(button);
+        }
+    }
+
+    /// 释放玩家的全部按钮
+    pub fn release_all(&mut self, player: Port) {
+        self.held[player.index()] = 0;
+        self.turbo[player.index()] = 0;
+    }
+
+    /// 从下一帧开始播放宏,播放期间玩家的主机输入被忽略
+    pub fn play_macro(&mut self, player: Port, input_macro: &Macro) {
+        let player = player.index();
+        self.playbacks.retain(|playback| playback.player != player);
+        self.playbacks.push(Playback {
+            player,
+            frames: input_macro.frames.clone(),
+            position: 0,
+        });
+    }
+
+    /// 计算下一帧各手柄的状态
+    pub fn next_frame(&mut self) -> [u8; PLAYERS] {
+        let mut states = [0; PLAYERS];
+        for (player, state) in states.iter_mut().enumerate() {
+            *state = self.held[player] | self.turbo[player] & self.turbo_mask();
+        }
+        for playback in self.playbacks.iter_mut() {
+            states[playback.player] = playback.frames[playback.position];
+            playback.position += 1;
+        }
+        self.playbacks
+            .retain(|playback| playback.position < playback.frames.len());
+        self.frame += 1;
+        states
+    }
+
+    /// 当前帧处于按下阶段的连发按钮
+    fn turbo_mask(&self) -> u8 {
+        self.turbo_frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frames)| (self.frame / **frames as u64).is_multiple_of(2))
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+}
+
+/// 将各手柄的状态写入总线上的手柄
+pub fn apply_states(states: &[u8; PLAYERS], bus: &mut dyn CpuBus) {
+    for (player, state) in Port::ALL.into_iter().zip(states) {
+        if let Some(joypad) = bus.joypad(player) {
+            joypad.set_buttons(JoypadButton::from(*state));
+        }
+    }
+}
+
+#[test]
+fn test_buttons_letters() {
+    assert_eq!(format_buttons(0), "........");
+    assert_eq!(format_buttons(0b1000_1001), "R...T..A");
+    assert_eq!(parse_buttons("........"), Ok(0));
+    assert_eq!(parse_buttons("R...T..A"), Ok(0b1000_1001));
+    assert_eq!(parse_buttons("ta r"), Ok(0b1000_1001));
+    assert!(parse_buttons("X").is_err());
+}
+
+#[test]
+fn test_turbo() {
+    let mut input = FrameInput::new();
+    input.set_turbo_frames(Button::B, 3);
+    input.set(Port::P1, Control::Turbo(Button::A), true);
+    input.set(Port::P1, Control::Turbo(Button::B), true);
+    input.set(Port::P2, Control::Button(Button::Up), true);
+    let frames: Vec<[u8; PLAYERS]> = (0..8).map(|_| input.next_frame()).collect();
+    let a: Vec<u8> = frames.iter().map(|f| f[0] & 1).collect();
+    let b: Vec<u8> = frames.iter().map(|f| f[0] >> 1 & 1).collect();
+    assert_eq!(a, [1, 1, 0, 0, 1, 1, 0, 0]);
+    assert_eq!(b, [1, 1, 1, 0, 0, 0, 1, 1]);
+    assert!(frames.iter().all(|f| f[1] == 0b0001_0000));
+
+    input.set(Port::P1, Control::Turbo(Button::A), false);
+    input.set(Port::P1, Control::Turbo(Button::B), false);
+    assert_eq!(input.next_frame()[0], 0);
+}
+
+#[test]
+fn test_macro() {
+    let input_macro = Macro::parse("R*2  # walk\nRA\n\n.\n").unwrap();
+    assert_eq!(input_macro.frames, [0x80, 0x80, 0x81, 0x00]);
+    assert!(Macro::parse("A*x").is_err());
+
+    let mut input = FrameInput::new();
+    input.set(Port::P1, Control::Button(Button::Left), true);
+    input.play_macro(Port::P1, &input_macro);
+    let states: Vec<u8> = (0..6).map(|_| input.next_frame()[0]).collect();
+    // 宏结束后恢复主机输入
+    assert_eq!(states, [0x80, 0x80, 0x81, 0x00, 0x40, 0x40]);
+}
@@ -0,0 +1,6 @@
+/// 64位FNV-1a哈希,用于识别ROM与检查录像是否同步
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
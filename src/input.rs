@@ -0,0 +1,722 @@
+use std::collections::HashMap;
+
+use sdl2::{
+    controller::{Axis, Button as PadButton, GameController},
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    mouse::MouseButton,
+    GameControllerSubsystem,
+};
+
+use crate::{
+    bus::CpuBus,
+    frame_input::{Control, FrameInput},
+    peripheral::{
+        ArkanoidPaddle, Button, FamilyKeyboard, FourScore, FourScoreMode, InputDevice, Joypad,
+        Port, PowerPad, Zapper, FAMILY_KEYBOARD_KEYS, POWER_PAD_BUTTONS,
+    },
+    ppu::Palette,
+};
+
+/// 控制器端口上连接的设备
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputSetup {
+    /// 两个标准手柄
+    Joypads,
+    /// 1P手柄, 2P光枪
+    Zapper,
+    /// NES Four Score
+    FourScore,
+    /// Famicom四人适配器
+    FamicomFourPlayer,
+    /// 1P手柄, 2P打砖块的控制器
+    Paddle,
+    /// 1P手柄, 2P Power Pad
+    PowerPad,
+    /// 1P手柄, 扩展端口Family BASIC键盘
+    FamilyKeyboard,
+}
+
+impl InputSetup {
+    pub fn from_name(name: &str) -> Result<InputSetup, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "joypad" => Ok(InputSetup::Joypads),
+            "zapper" => Ok(InputSetup::Zapper),
+            "fourscore" => Ok(InputSetup::FourScore),
+            "famicom4" => Ok(InputSetup::FamicomFourPlayer),
+            "paddle" => Ok(InputSetup::Paddle),
+            "powerpad" => Ok(InputSetup::PowerPad),
+            "keyboard" => Ok(InputSetup::FamilyKeyboard),
+            _ => Err(format!("Unknown input device: {}", name)),
+        }
+    }
+
+    /// NES 2.0头的byte 15(默认扩展设备),不支持的设备返回None
+    pub fn from_nes2(device: u8) -> Option<InputSetup> {
+        match device {
+            0x01 => Some(InputSetup::Joypads),
+            0x02 => Some(InputSetup::FourScore),
+            0x03 => Some(InputSetup::FamicomFourPlayer),
+            0x08 => Some(InputSetup::Zapper),
+            0x0B | 0x0C => Some(InputSetup::PowerPad),
+            0x0F => Some(InputSetup::Paddle),
+            0x23 => Some(InputSetup::FamilyKeyboard),
+            _ => None,
+        }
+    }
+
+    /// 创建1P与2P端口上的设备,光枪按画面的调色板感光
+    pub fn create(&self, palette: &Palette) -> (Box<dyn InputDevice>, Box<dyn InputDevice>) {
+        match self {
+            InputSetup::Joypads => (Box::new(Joypad::new()), Box::new(Joypad::new())),
+            InputSetup::Zapper => (
+                Box::new(Joypad::new()),
+                Box::new(Zapper::new(palette.clone())),
+            ),
+            InputSetup::FourScore => (
+                Box::new(FourScore::port1(FourScoreMode::Nes)),
+                Box::new(FourScore::port2(FourScoreMode::Nes)),
+            ),
+            InputSetup::FamicomFourPlayer => (
+                Box::new(FourScore::port1(FourScoreMode::Famicom)),
+                Box::new(FourScore::port2(FourScoreMode::Famicom)),
+            ),
+            InputSetup::Paddle => (Box::new(Joypad::new()), Box::new(ArkanoidPaddle::new())),
+            InputSetup::PowerPad => (Box::new(Joypad::new()), Box::new(PowerPad::new())),
+            InputSetup::FamilyKeyboard => {
+                (Box::new(Joypad::new()), Box::new(FamilyKeyboard::new()))
+            }
+        }
+    }
+}
+
+/// 键盘到手柄按钮的映射
+///
+/// 配置文件每行为一个按键设置,`#`之后为注释,例如
+/// ```text
+/// p1.a = X
+/// p2.start = Return
+/// p1.turbo_a = S
+/// ```
+/// 键名与SDL的键名相同,未设置的按钮使用默认按键,连发按钮默认没有按键
+pub struct KeyboardMapping {
+    bindings: HashMap<Keycode, (Port, Control)>,
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        let mut mapping = KeyboardMapping {
+            bindings: HashMap::new(),
+        };
+        let defaults = [
+            (Keycode::X, Port::P1, Button::A),
+            (Keycode::Z, Port::P1, Button::B),
+            (Keycode::RShift, Port::P1, Button::Select),
+            (Keycode::Return, Port::P1, Button::Start),
+            (Keycode::Up, Port::P1, Button::Up),
+            (Keycode::Down, Port::P1, Button::Down),
+            (Keycode::Left, Port::P1, Button::Left),
+            (Keycode::Right, Port::P1, Button::Right),
+            (Keycode::K, Port::P2, Button::A),
+            (Keycode::J, Port::P2, Button::B),
+            (Keycode::U, Port::P2, Button::Select),
+            (Keycode::I, Port::P2, Button::Start),
+            (Keycode::W, Port::P2, Button::Up),
+            (Keycode::S, Port::P2, Button::Down),
+            (Keycode::A, Port::P2, Button::Left),
+            (Keycode::D, Port::P2, Button::Right),
+        ];
+        for (keycode, port, button) in defaults {
+            mapping.bind(keycode, port, Control::Button(button));
+        }
+        mapping
+    }
+}
+
+impl KeyboardMapping {
+    /// 读取配置文件
+    pub fn load(path: &str) -> Result<KeyboardMapping, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&config)
+    }
+
+    pub fn parse(config: &str) -> Result<KeyboardMapping, String> {
+        let mut mapping = KeyboardMapping::default();
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (target, key) = line
+                .split_once('=')
+                .ok_or(format!("Invalid key binding: {}", line))?;
+            let (port, button) = target
+                .trim()
+                .split_once('.')
+                .ok_or(format!("Invalid key binding: {}", line))?;
+            let key = key.trim();
+            let keycode = Keycode::from_name(key).ok_or(format!("Unknown key: {}", key))?;
+            mapping.bind(keycode, Port::from_name(port)?, Control::from_name(button)?);
+        }
+        Ok(mapping)
+    }
+
+    /// 设置按钮对应的按键,取代之前的按键
+    pub fn bind(&mut self, keycode: Keycode, port: Port, control: Control) {
+        self.bindings.retain(|_, target| *target != (port, control));
+        self.bindings.insert(keycode, (port, control));
+    }
+
+    /// 按键按下或释放时更新手柄输入,返回按键是否有对应的按钮
+    pub fn handle_key(&self, keycode: Keycode, pressed: bool, input: &mut FrameInput) -> bool {
+        match self.bindings.get(&keycode) {
+            Some((port, control)) => {
+                input.set(*port, *control, pressed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 摇杆偏离中心超过该值时视为按下方向键
+const AXIS_THRESHOLD: i16 = 0x4000;
+
+/// 游戏手柄到NES手柄按钮的映射
+///
+/// 配置文件每行为一个按钮设置,例如`a = b`表示用手柄的B键作为NES的A键,
+/// 手柄按钮名与SDL的名称相同(a, b, x, y, back, start, dpup, ...),
+/// `turbo_a = y`设置连发按钮,左摇杆总是映射到方向键
+pub struct ControllerMapping {
+    buttons: HashMap<PadButton, Control>,
+}
+
+impl Default for ControllerMapping {
+    fn default() -> Self {
+        // NES手柄B在左A在右,与Xbox布局的A(下)和B(右)对应,
+        // 上方的Y和X作为连发
+        let buttons = [
+            (PadButton::B, Control::Button(Button::A)),
+            (PadButton::A, Control::Button(Button::B)),
+            (PadButton::Y, Control::Turbo(Button::A)),
+            (PadButton::X, Control::Turbo(Button::B)),
+            (PadButton::Back, Control::Button(Button::Select)),
+            (PadButton::Start, Control::Button(Button::Start)),
+            (PadButton::DPadUp, Control::Button(Button::Up)),
+            (PadButton::DPadDown, Control::Button(Button::Down)),
+            (PadButton::DPadLeft, Control::Button(Button::Left)),
+            (PadButton::DPadRight, Control::Button(Button::Right)),
+        ];
+        ControllerMapping {
+            buttons: buttons.into_iter().collect(),
+        }
+    }
+}
+
+impl ControllerMapping {
+    pub fn load(path: &str) -> Result<ControllerMapping, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&config)
+    }
+
+    pub fn parse(config: &str) -> Result<ControllerMapping, String> {
+        let mut mapping = ControllerMapping::default();
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (button, pad_button) = line
+                .split_once('=')
+                .ok_or(format!("Invalid button binding: {}", line))?;
+            let pad_button = pad_button.trim();
+            let pad_button = PadButton::from_string(pad_button)
+                .ok_or(format!("Unknown controller button: {}", pad_button))?;
+            mapping.bind(pad_button, Control::from_name(button.trim())?);
+        }
+        Ok(mapping)
+    }
+
+    /// 设置NES按钮对应的手柄按钮,取代之前的设置
+    pub fn bind(&mut self, pad_button: PadButton, control: Control) {
+        self.buttons.retain(|_, target| *target != control);
+        self.buttons.insert(pad_button, control);
+    }
+}
+
+/// 已连接的游戏手柄,按连接顺序分配到空闲的端口
+pub struct ControllerInput {
+    mapping: ControllerMapping,
+    /// SDL的instance id对应的端口
+    ports: HashMap<u32, Port>,
+}
+
+impl ControllerInput {
+    pub fn new(mapping: ControllerMapping) -> Self {
+        ControllerInput {
+            mapping,
+            ports: HashMap::new(),
+        }
+    }
+
+    /// 手柄连接时分配一个空闲的端口,没有空闲端口时返回None
+    pub fn connect(&mut self, instance_id: u32) -> Option<Port> {
+        if let Some(port) = self.ports.get(&instance_id) {
+            return Some(*port);
+        }
+        let port = Port::ALL
+            .into_iter()
+            .find(|port| !self.ports.values().any(|p| p == port))?;
+        self.ports.insert(instance_id, port);
+        Some(port)
+    }
+
+    /// 手柄断开时释放端口上的全部按钮
+    pub fn disconnect(&mut self, instance_id: u32, input: &mut FrameInput) {
+        if let Some(port) = self.ports.remove(&instance_id) {
+            input.release_all(port);
+        }
+    }
+
+    /// 交换1P与2P的手柄
+    pub fn swap_ports(&mut self) {
+        for port in self.ports.values_mut() {
+            *port = match *port {
+                Port::P1 => Port::P2,
+                Port::P2 => Port::P1,
+                other => other,
+            };
+        }
+    }
+
+    /// 处理手柄的按钮与摇杆事件,返回事件是否来自已连接的手柄
+    pub fn handle_event(&self, event: &Event, input: &mut FrameInput) -> bool {
+        let (which, changes) = match event {
+            Event::ControllerButtonDown { which, button, .. } => {
+                (which, self.button_changes(*button, true))
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                (which, self.button_changes(*button, false))
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => (which, Self::axis_changes(*axis, *value)),
+            _ => return false,
+        };
+        let port = match self.ports.get(which) {
+            Some(port) => *port,
+            None => return false,
+        };
+        for (control, pressed) in changes {
+            input.set(port, control, pressed);
+        }
+        true
+    }
+
+    fn button_changes(&self, pad_button: PadButton, pressed: bool) -> Vec<(Control, bool)> {
+        match self.mapping.buttons.get(&pad_button) {
+            Some(control) => vec![(*control, pressed)],
+            None => Vec::new(),
+        }
+    }
+
+    /// 摇杆回到中心时同时释放两个方向
+    fn axis_changes(axis: Axis, value: i16) -> Vec<(Control, bool)> {
+        let (negative, positive) = match axis {
+            Axis::LeftX => (Button::Left, Button::Right),
+            Axis::LeftY => (Button::Up, Button::Down),
+            _ => return Vec::new(),
+        };
+        vec![
+            (Control::Button(negative), value < -AXIS_THRESHOLD),
+            (Control::Button(positive), value > AXIS_THRESHOLD),
+        ]
+    }
+}
+
+/// 打开的SDL游戏手柄,支持热插拔
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    /// 需要保持打开才能收到事件
+    opened: HashMap<u32, GameController>,
+    pub input: ControllerInput,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem, mapping: ControllerMapping) -> Self {
+        Controllers {
+            subsystem,
+            opened: HashMap::new(),
+            input: ControllerInput::new(mapping),
+        }
+    }
+
+    /// 处理手柄的连接、断开与输入事件,返回事件是否已处理
+    pub fn handle_event(&mut self, event: &Event, input: &mut FrameInput) -> bool {
+        match event {
+            // 已经连接的手柄在启动时同样会产生该事件,which为设备序号
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.subsystem.open(*which) {
+                    Ok(controller) => {
+                        let id = controller.instance_id();
+                        match self.input.connect(id) {
+                            Some(port) => {
+                                eprintln!("{} connected to {:?}", controller.name(), port)
+                            }
+                            None => eprintln!("{} connected, no free port", controller.name()),
+                        }
+                        self.opened.insert(id, controller);
+                    }
+                    Err(error) => eprintln!("Failed to open controller: {}", error),
+                }
+                true
+            }
+            // which为instance id
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.input.disconnect(*which, input);
+                self.opened.remove(which);
+                true
+            }
+            _ => self.input.handle_event(event, input),
+        }
+    }
+}
+
+/// 连接在1P或2P端口上的某种设备
+fn device<T: InputDevice + 'static>(bus: &mut dyn CpuBus) -> Option<&mut T> {
+    let port = [Port::P1, Port::P2].into_iter().find(|port| {
+        bus.input_device(*port)
+            .is_some_and(|device| device.as_any_mut().is::<T>())
+    })?;
+    bus.input_device(port)?.as_any_mut().downcast_mut()
+}
+
+/// 鼠标控制光枪或打砖块的控制器,移动鼠标瞄准,左键扣下扳机或按下按钮,
+/// scale为窗口相对NES画面的放大倍数,返回事件是否已处理
+pub fn handle_mouse(event: &Event, scale: i32, bus: &mut dyn CpuBus) -> bool {
+    if device::<ArkanoidPaddle>(bus).is_some() {
+        return handle_paddle_mouse(event, scale, bus);
+    }
+    let zapper = match device::<Zapper>(bus) {
+        Some(zapper) => zapper,
+        None => return false,
+    };
+    match event {
+        Event::MouseMotion { x, y, .. } if *x >= 0 && *y >= 0 => {
+            zapper.set_aim(Some(((x / scale) as usize, (y / scale) as usize)));
+        }
+        Event::Window {
+            win_event: WindowEvent::Leave,
+            ..
+        } => zapper.set_aim(None),
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => zapper.set_trigger(true),
+        Event::MouseButtonUp {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => zapper.set_trigger(false),
+        _ => return false,
+    }
+    true
+}
+
+fn handle_paddle_mouse(event: &Event, scale: i32, bus: &mut dyn CpuBus) -> bool {
+    let paddle = match device::<ArkanoidPaddle>(bus) {
+        Some(paddle) => paddle,
+        None => return false,
+    };
+    match event {
+        Event::MouseMotion { x, .. } => paddle.aim((x.max(&0) / scale) as usize),
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => paddle.set_button(true),
+        Event::MouseButtonUp {
+            mouse_btn: MouseButton::Left,
+            ..
+        } => paddle.set_button(false),
+        _ => return false,
+    }
+    true
+}
+
+/// 模拟器的热键,不会交给Power Pad或Family BASIC键盘
+pub const HOTKEYS: [Keycode; 11] = [
+    Keycode::Escape,
+    Keycode::F2,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::Backspace,
+    Keycode::F9,
+    Keycode::F10,
+    Keycode::F11,
+    Keycode::Tab,
+];
+
+/// Power Pad的按键,按钮排列与键盘左侧的3行对应,
+/// 从数字行开始以避开1P手柄的Z与X
+const POWER_PAD_KEYS: [Keycode; POWER_PAD_BUTTONS] = [
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Q,
+    Keycode::W,
+    Keycode::E,
+    Keycode::R,
+    Keycode::A,
+    Keycode::S,
+    Keycode::D,
+    Keycode::F,
+];
+
+/// Family BASIC键盘矩阵对应的按键,键盘上没有的键使用位置相近的键代替,
+/// F1~F8与ESC是模拟器的热键,分别对应小键盘的1~8与PageUp
+#[rustfmt::skip]
+const FAMILY_KEYBOARD_KEYS_MAP: [Keycode; FAMILY_KEYBOARD_KEYS] = [
+    // F8, RETURN, [, ], カナ, 右SHIFT, ¥, STOP
+    Keycode::Kp8, Keycode::Return, Keycode::LeftBracket, Keycode::RightBracket,
+    Keycode::RAlt, Keycode::RShift, Keycode::Backslash, Keycode::End,
+    // F7, @, :, ;, _, /, -, ^
+    Keycode::Kp7, Keycode::Backquote, Keycode::Quote, Keycode::Semicolon,
+    Keycode::RCtrl, Keycode::Slash, Keycode::Minus, Keycode::Equals,
+    Keycode::Kp6, Keycode::O, Keycode::L, Keycode::K,
+    Keycode::Period, Keycode::Comma, Keycode::P, Keycode::Num0,
+    Keycode::Kp5, Keycode::I, Keycode::U, Keycode::J,
+    Keycode::M, Keycode::N, Keycode::Num9, Keycode::Num8,
+    Keycode::Kp4, Keycode::Y, Keycode::G, Keycode::H,
+    Keycode::B, Keycode::V, Keycode::Num7, Keycode::Num6,
+    Keycode::Kp3, Keycode::T, Keycode::R, Keycode::D,
+    Keycode::F, Keycode::C, Keycode::Num5, Keycode::Num4,
+    Keycode::Kp2, Keycode::W, Keycode::S, Keycode::A,
+    Keycode::X, Keycode::Z, Keycode::E, Keycode::Num3,
+    // F1, ESC, Q, CTR, 左SHIFT, GRPH, 1, 2
+    Keycode::Kp1, Keycode::PageUp, Keycode::Q, Keycode::LCtrl,
+    Keycode::LShift, Keycode::LAlt, Keycode::Num1, Keycode::Num2,
+    // CLR HOME, ↑, →, ←, ↓, SPACE, DEL, INS
+    Keycode::Home, Keycode::Up, Keycode::Right, Keycode::Left,
+    Keycode::Down, Keycode::Space, Keycode::Delete, Keycode::Insert,
+];
+
+/// 键盘操作Power Pad或Family BASIC键盘,连接这些设备时优先于手柄的按键设置,
+/// 返回按键是否已处理,热键总是交给模拟器
+pub fn handle_peripheral_key(keycode: Keycode, pressed: bool, bus: &mut dyn CpuBus) -> bool {
+    if HOTKEYS.contains(&keycode) {
+        return false;
+    }
+    if let Some(keyboard) = device::<FamilyKeyboard>(bus) {
+        match FAMILY_KEYBOARD_KEYS_MAP
+            .iter()
+            .position(|key| *key == keycode)
+        {
+            Some(key) => keyboard.set_key(key, pressed),
+            None => return false,
+        }
+        return true;
+    }
+    if let Some(pad) = device::<PowerPad>(bus) {
+        match POWER_PAD_KEYS.iter().position(|key| *key == keycode) {
+            Some(button) => pad.set_button(button + 1, pressed),
+            None => return false,
+        }
+        return true;
+    }
+    false
+}
+
+#[test]
+fn test_keyboard_mapping() {
+    let mut bus = test_bus();
+    let mut input = FrameInput::new();
+    let mut mapping = KeyboardMapping::default();
+    mapping.bind(Keycode::Space, Port::P1, Control::Button(Button::A));
+    mapping.bind(Keycode::Q, Port::P1, Control::Turbo(Button::B));
+
+    assert!(mapping.handle_key(Keycode::Space, true, &mut input));
+    // X不再对应A
+    assert!(!mapping.handle_key(Keycode::X, true, &mut input));
+    assert!(mapping.handle_key(Keycode::W, true, &mut input));
+    assert!(mapping.handle_key(Keycode::Q, true, &mut input));
+    apply_input(&mut input, &mut bus);
+    let buttons = bus.joypad(Port::P1).unwrap().buttons();
+    assert!(buttons.button_a && buttons.button_b);
+    assert!(bus.joypad(Port::P2).unwrap().buttons().up);
+
+    mapping.handle_key(Keycode::Space, false, &mut input);
+    apply_input(&mut input, &mut bus);
+    assert!(!bus.joypad(Port::P1).unwrap().buttons().button_a);
+}
+
+#[cfg(test)]
+fn test_bus_with(setup: InputSetup) -> crate::bus::Bus {
+    use crate::{apu::Apu, bus::BusBuilder, memory::Memory, rom::test::test_rom};
+    let (p1, p2) = setup.create(&Palette::default());
+    BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom()))
+        .apu(Box::new(Apu::new()))
+        .input_p1(p1)
+        .input_p2(p2)
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+fn test_bus() -> crate::bus::Bus {
+    test_bus_with(InputSetup::Joypads)
+}
+
+#[cfg(test)]
+fn apply_input(input: &mut FrameInput, bus: &mut dyn CpuBus) {
+    crate::frame_input::apply_states(&input.next_frame(), bus);
+}
+
+#[test]
+fn test_controller_input() {
+    let mut bus = test_bus();
+    let mut frame_input = FrameInput::new();
+    let mut input = ControllerInput::new(ControllerMapping::default());
+    let button = |which, button| Event::ControllerButtonDown {
+        timestamp: 0,
+        which,
+        button,
+    };
+    // 未连接的手柄
+    assert!(!input.handle_event(&button(7, PadButton::B), &mut frame_input));
+
+    assert_eq!(input.connect(7), Some(Port::P1));
+    assert_eq!(input.connect(9), Some(Port::P2));
+    assert_eq!(input.connect(10), Some(Port::P3));
+    assert_eq!(input.connect(12), Some(Port::P4));
+    assert_eq!(input.connect(11), None);
+    assert!(input.handle_event(&button(7, PadButton::B), &mut frame_input));
+    assert!(input.handle_event(&button(9, PadButton::Start), &mut frame_input));
+    apply_input(&mut frame_input, &mut bus);
+    assert!(bus.joypad(Port::P1).unwrap().buttons().button_a);
+    assert!(bus.joypad(Port::P2).unwrap().buttons().start);
+
+    let axis = |value| Event::ControllerAxisMotion {
+        timestamp: 0,
+        which: 7,
+        axis: Axis::LeftX,
+        value,
+    };
+    input.handle_event(&axis(-30000), &mut frame_input);
+    apply_input(&mut frame_input, &mut bus);
+    assert!(bus.joypad(Port::P1).unwrap().buttons().left);
+    input.handle_event(&axis(100), &mut frame_input);
+    apply_input(&mut frame_input, &mut bus);
+    let buttons = bus.joypad(Port::P1).unwrap().buttons();
+    assert!(!buttons.left && !buttons.right);
+
+    // 断开后释放按钮,新的手柄使用空出的端口
+    input.disconnect(9, &mut frame_input);
+    apply_input(&mut frame_input, &mut bus);
+    assert!(!bus.joypad(Port::P2).unwrap().buttons().start);
+    assert_eq!(input.connect(11), Some(Port::P2));
+    input.swap_ports();
+    input.handle_event(&button(11, PadButton::Back), &mut frame_input);
+    apply_input(&mut frame_input, &mut bus);
+    assert!(bus.joypad(Port::P1).unwrap().buttons().select);
+}
+
+#[test]
+fn test_controller_remap() {
+    let mut mapping = ControllerMapping::default();
+    mapping.bind(PadButton::RightShoulder, Control::Button(Button::A));
+    let mut input = ControllerInput::new(mapping);
+    let mut frame_input = FrameInput::new();
+    input.connect(0);
+    let button = |button| Event::ControllerButtonDown {
+        timestamp: 0,
+        which: 0,
+        button,
+    };
+    input.handle_event(&button(PadButton::B), &mut frame_input);
+    assert_eq!(frame_input.next_frame()[0], 0);
+    input.handle_event(&button(PadButton::RightShoulder), &mut frame_input);
+    assert_eq!(frame_input.next_frame()[0], 1);
+}
+
+#[test]
+fn test_mouse_zapper() {
+    use crate::addressable::Readable;
+    let mut bus = test_bus_with(InputSetup::Zapper);
+    let click = Event::MouseButtonDown {
+        timestamp: 0,
+        window_id: 0,
+        which: 0,
+        mouse_btn: MouseButton::Left,
+        clicks: 1,
+        x: 30,
+        y: 30,
+    };
+    assert!(handle_mouse(&click, 3, &mut bus));
+    // 扳机D4, 没有感光D3
+    assert_eq!(bus.read(0x4017), 0x40 | 0b1_1000);
+    assert_eq!(bus.read(0x4016) & 0b1_1000, 0);
+}
+
+#[test]
+fn test_four_score_players() {
+    use crate::addressable::{Readable, Writable};
+    let mut bus = test_bus_with(InputSetup::FourScore);
+    let mut input = FrameInput::new();
+    let mut mapping = KeyboardMapping::default();
+    mapping.bind(Keycode::Kp2, Port::P4, Control::Button(Button::A));
+    assert!(mapping.handle_key(Keycode::Kp2, true, &mut input));
+    apply_input(&mut input, &mut bus);
+    assert!(bus.joypad(Port::P4).unwrap().buttons().button_a);
+
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    let bits: Vec<u8> = (0..24).map(|_| bus.read(0x4017) & 1).collect();
+    // 4P的A, 以及2P端口的标识
+    assert_eq!(bits[8], 1);
+    assert_eq!(bits[16 + 5], 1);
+    assert_eq!(bits.iter().filter(|bit| **bit == 1).count(), 2);
+
+    // 没有四人适配器时3P不存在
+    let mut bus = test_bus();
+    assert!(bus.joypad(Port::P3).is_none());
+}
+
+#[test]
+fn test_peripheral_keys() {
+    use crate::addressable::{Readable, Writable};
+    let mut bus = test_bus();
+    assert!(!handle_peripheral_key(Keycode::Q, true, &mut bus));
+
+    let mut bus = test_bus_with(InputSetup::PowerPad);
+    // 按钮2是D3的第一位
+    assert!(handle_peripheral_key(Keycode::Num2, true, &mut bus));
+    assert!(!handle_peripheral_key(Keycode::Z, true, &mut bus));
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    assert_eq!(bus.read(0x4017) & 0b1000, 0b1000);
+
+    let mut bus = test_bus_with(InputSetup::FamilyKeyboard);
+    // RETURN位于第0行第0列的D2
+    assert!(handle_peripheral_key(Keycode::Return, true, &mut bus));
+    bus.write(0x4016, 0b101);
+    assert_eq!(bus.read(0x4017) & 0b1_1110, 0b1_1010);
+}
+
+#[test]
+fn test_peripheral_keys_avoid_hotkeys() {
+    for key in POWER_PAD_KEYS.iter().chain(&FAMILY_KEYBOARD_KEYS_MAP) {
+        assert!(!HOTKEYS.contains(key), "{:?} is a hotkey", key);
+    }
+    // Power Pad与1P手柄可以同时使用
+    let mapping = KeyboardMapping::default();
+    for key in &POWER_PAD_KEYS {
+        assert!(mapping
+            .bindings
+            .get(key)
+            .is_none_or(|(port, _)| *port != Port::P1));
+    }
+}
@@ -1,14 +1,24 @@
 extern crate core;
 
-use crate::bus::Bus;
+use apu::Apu;
+use audio::{AudioOutput, DEFAULT_LATENCY_MS};
 use bus::BusBuilder;
-use ppu::Ppu;
+use frame_input::{FrameInput, Macro, PLAYERS};
+use input::{ControllerMapping, Controllers, InputSetup, KeyboardMapping};
+use meta::Region;
+use movie::{Movie, MovieMode, MovieSession};
+use nsf::{Nsf, NsfPlayer};
+use peripheral::{Button, Port};
+use ppu::{Palette, Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
 use rand::Rng;
+use rewind::{Rewind, DEFAULT_REWIND_BUDGET_MB, DEFAULT_REWIND_INTERVAL};
 use rom::Rom;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::EventPump;
+use speed::{Speed, SpeedControl, DEFAULT_FAST_FORWARD, DEFAULT_SLOW_MOTION};
+use std::path::{Path, PathBuf};
 
 use crate::cpu::CPU;
 use crate::memory::Memory;
@@ -17,14 +27,24 @@ mod asm;
 mod bus;
 mod cpu;
 mod flag;
+mod frame_input;
+mod hash;
+mod input;
 mod mapper;
 mod memory;
 mod meta;
+mod movie;
+mod nsf;
+mod peripheral;
 mod ppu;
 
 mod apu;
-mod joypad;
+mod audio;
+mod rewind;
 mod rom;
+mod speed;
+mod state;
+mod wav;
 
 fn color(byte: u8) -> Color {
     match byte {
@@ -57,6 +77,218 @@ fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     update
 }
 
+/// 窗口相对NES画面的放大倍数
+const SCALE: i32 = 3;
+/// 每个游戏的存档位置数量
+const STATE_SLOTS: u8 = 10;
+
+/// 游戏运行时的主机输入
+struct HostInput {
+    keyboard: KeyboardMapping,
+    controllers: Option<Controllers>,
+    /// 按下按键时在1P上播放的宏
+    macros: Vec<(Keycode, Macro)>,
+    frame_input: FrameInput,
+    /// 正在录制或播放的录像与保存的路径
+    movie: Option<(MovieSession, String)>,
+    /// 游戏ROM的路径,存档保存在同一目录
+    rom: String,
+    /// 当前的存档位置
+    slot: u8,
+    rewind: Rewind,
+    /// 按住倒带键
+    rewinding: bool,
+    speed: SpeedControl,
+}
+
+impl HostInput {
+    /// 计算这一帧的手柄状态并写入,播放录像时使用录像中的状态
+    fn apply(&mut self, cpu: &mut CPU) -> [u8; PLAYERS] {
+        let live = self.frame_input.next_frame();
+        let states = match &mut self.movie {
+            Some((session, _)) => {
+                let synced = session.desync().is_none();
+                let states = session.next_frame(live, cpu.bus.as_ref());
+                if let Some(frame) = session.desync().filter(|_| synced) {
+                    eprintln!("Movie desynced at frame {}", frame);
+                }
+                states
+            }
+            None => live,
+        };
+        frame_input::apply_states(&states, cpu.bus.as_mut());
+        states
+    }
+
+    /// 存档位置对应的文件,与ROM同名,扩展名为.ss0到.ss9
+    fn state_path(&self) -> PathBuf {
+        Path::new(&self.rom).with_extension(format!("ss{}", self.slot))
+    }
+
+    fn save_state(&self, cpu: &CPU) {
+        let path = self.state_path();
+        match std::fs::write(&path, cpu.save_state()) {
+            Ok(()) => eprintln!("Saved state to slot {}", self.slot),
+            Err(error) => eprintln!("{}: {}", path.display(), error),
+        }
+    }
+
+    /// 录像进行中时不能读取存档,否则录像无法同步
+    fn load_state(&mut self, cpu: &mut CPU) {
+        if self.movie.is_some() {
+            eprintln!("Cannot load a save state while a movie is active");
+            return;
+        }
+        let path = self.state_path();
+        let result = std::fs::read(&path)
+            .map_err(|error| format!("{}: {}", path.display(), error))
+            .and_then(|data| cpu.load_state(&data));
+        match result {
+            Ok(()) => {
+                self.rewind.clear();
+                eprintln!("Loaded state from slot {}", self.slot);
+            }
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// 退出前保存录制的录像
+    fn quit(&self) -> ! {
+        if let Some((session, path)) = &self.movie {
+            if session.mode() == MovieMode::Recording {
+                if let Err(error) = session.movie.save(path) {
+                    eprintln!("{}", error);
+                }
+            }
+        }
+        std::process::exit(0)
+    }
+}
+
+/// 处理游戏运行时的事件
+///
+/// 键盘与游戏手柄映射到NES手柄,鼠标控制光枪与打砖块的控制器,
+/// 连接Power Pad或Family BASIC键盘时键盘优先操作这些设备,其余按键见USAGE中的热键
+fn handle_nes_events(cpu: &mut CPU, event_pump: &mut EventPump, input: &mut HostInput) {
+    for event in event_pump.poll_iter() {
+        if let Some(controllers) = &mut input.controllers {
+            if controllers.handle_event(&event, &mut input.frame_input) {
+                continue;
+            }
+        }
+        if input::handle_mouse(&event, SCALE, cpu.bus.as_mut()) {
+            continue;
+        }
+        match event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } if input::handle_peripheral_key(keycode, true, cpu.bus.as_mut()) => {}
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } if input::handle_peripheral_key(keycode, false, cpu.bus.as_mut()) => {}
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => input.quit(),
+            Event::KeyDown {
+                keycode: Some(Keycode::F4),
+                ..
+            } => {
+                if let Some((session, _)) = &mut input.movie {
+                    if session.take_over() {
+                        eprintln!("Recording from the current frame");
+                    }
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::F5),
+                ..
+            } => input.save_state(cpu),
+            Event::KeyDown {
+                keycode: Some(Keycode::F6),
+                ..
+            } => {
+                input.slot = (input.slot + 1) % STATE_SLOTS;
+                eprintln!("Save state slot {}", input.slot);
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::F7),
+                ..
+            } => input.load_state(cpu),
+            Event::KeyDown {
+                keycode: Some(Keycode::Backspace),
+                repeat: false,
+                ..
+            } => {
+                if input.movie.is_some() {
+                    eprintln!("Cannot rewind while a movie is active");
+                } else {
+                    input.rewinding = true;
+                }
+            }
+            Event::KeyUp {
+                keycode: Some(Keycode::Backspace),
+                ..
+            } => input.rewinding = false,
+            Event::KeyDown {
+                keycode: Some(Keycode::F9),
+                ..
+            } => input.speed.toggle_pause(),
+            Event::KeyDown {
+                keycode: Some(Keycode::F10),
+                ..
+            } => input.speed.advance_frame(),
+            Event::KeyDown {
+                keycode: Some(Keycode::F11),
+                ..
+            } => input.speed.toggle_slow_motion(),
+            Event::KeyDown {
+                keycode: Some(Keycode::Tab),
+                ..
+            } => input.speed.set_fast_forward(true),
+            Event::KeyUp {
+                keycode: Some(Keycode::Tab),
+                ..
+            } => input.speed.set_fast_forward(false),
+            Event::KeyDown {
+                keycode: Some(Keycode::F2),
+                ..
+            } => {
+                if let Some(controllers) = &mut input.controllers {
+                    controllers.input.swap_ports();
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat,
+                ..
+            } => match input.macros.iter().find(|(key, _)| *key == keycode) {
+                Some((_, input_macro)) if !repeat => {
+                    input.frame_input.play_macro(Port::P1, input_macro)
+                }
+                _ => {
+                    input
+                        .keyboard
+                        .handle_key(keycode, true, &mut input.frame_input);
+                }
+            },
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => {
+                input
+                    .keyboard
+                    .handle_key(keycode, false, &mut input.frame_input);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 贪吃蛇演示从$FF读取最后按下的键
 fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     for event in event_pump.poll_iter() {
         match event {
@@ -94,7 +326,643 @@ fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
     }
 }
 
+const USAGE: &str = "Usage: nes-emulator-rs [rom.nes|music.nsf] [options]
+    --palette file.pal          load a .pal palette
+    --region ntsc|pal|dendy     override the region of the rom
+    --latency ms                audio latency
+    --wav out.wav               run headless and record audio
+    --frames n                  number of frames to record (default 600)
+    --stems                     also record each channel to out.<channel>.wav
+    --track n                   NSF track to play (starting from 1)
+    --keys file                 load keyboard bindings (lines like p1.a = X)
+    --pad file                  load game controller bindings (lines like a = b)
+    --turbo-a n                 frames turbo A stays pressed and released (default 2)
+    --turbo-b n                 frames turbo B stays pressed and released (default 2)
+    --macro key=file            play an input macro on player 1 when the key is pressed
+    --record movie              record controller input from power-on (.fm2 or native format)
+    --play movie                play back a recorded movie
+    --read-write                continue recording when playback ends or F4 is pressed
+    --state file                start from a save state (recorded movies start from it too)
+    --rewind-interval n         frames between rewind snapshots (default 10)
+    --rewind-mb n               memory used by the rewind buffer, 0 to disable (default 64)
+    --fast-forward n            speed while fast-forwarding, 0 for uncapped (default 0)
+    --slow-motion n             slow motion runs at 1/n speed (default 4)
+    --input joypad|zapper|fourscore|famicom4|paddle|powerpad|keyboard
+                                devices on the controller ports (default from the rom header)
+
+Hotkeys:
+    Escape                      quit (saves the movie being recorded)
+    F2                          swap the ports of the two game controllers
+    F4                          take over movie playback in read-write mode
+    F5 / F6 / F7                save state, select slot 0-9, load state
+    Backspace                   hold to rewind
+    F9 / F10                    pause and resume, advance one frame
+    Tab / F11                   hold to fast-forward, toggle slow motion
+    macro keys                  play the macros given with --macro";
+
+/// 命令行参数
+struct Options {
+    /// 游戏ROM或NSF音乐的路径,为空时运行贪吃蛇演示
+    rom: Option<String>,
+    /// .pal调色板文件路径
+    palette: Option<String>,
+    /// 强制指定电视制式,为空时使用ROM头中的设置
+    region: Option<Region>,
+    /// 音频延迟(毫秒)
+    latency: u32,
+    /// 不显示画面,录制音频写入该WAV文件
+    wav: Option<String>,
+    /// 录制的帧数,NSF曲目没有长度信息时为PLAY的调用次数
+    frames: u32,
+    /// 同时输出每个通道单独的WAV文件
+    stems: bool,
+    /// NSF的曲目(从0开始),为空时使用文件中的起始曲目
+    track: Option<u8>,
+    /// 按键配置文件
+    keys: Option<String>,
+    /// 游戏手柄配置文件
+    pad: Option<String>,
+    /// 连发A与B按下与释放各持续的帧数
+    turbo_a: Option<u8>,
+    turbo_b: Option<u8>,
+    /// 宏对应的按键与文件
+    macros: Vec<(Keycode, Macro)>,
+    /// 录制录像的路径
+    record: Option<String>,
+    /// 播放录像的路径
+    play: Option<String>,
+    /// 以读写模式播放录像
+    read_write: bool,
+    /// 开始时读取的存档,录制的录像从该存档开始
+    state: Option<String>,
+    /// 倒带快照的间隔帧数
+    rewind_interval: u32,
+    /// 倒带缓冲区的内存预算(MB),为0时不能倒带
+    rewind_mb: usize,
+    /// 快进的倍数,为0时不限速
+    fast_forward: u32,
+    /// 慢动作的速度为1/slow_motion
+    slow_motion: u32,
+    /// 控制器端口上的设备,为空时使用ROM头中的设置
+    input: Option<InputSetup>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        rom: None,
+        palette: None,
+        region: None,
+        latency: DEFAULT_LATENCY_MS,
+        wav: None,
+        frames: 600,
+        stems: false,
+        track: None,
+        keys: None,
+        pad: None,
+        turbo_a: None,
+        turbo_b: None,
+        macros: Vec::new(),
+        record: None,
+        play: None,
+        read_write: false,
+        state: None,
+        rewind_interval: DEFAULT_REWIND_INTERVAL,
+        rewind_mb: DEFAULT_REWIND_BUDGET_MB,
+        fast_forward: DEFAULT_FAST_FORWARD,
+        slow_motion: DEFAULT_SLOW_MOTION,
+        input: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palette" => {
+                options.palette = Some(args.next().ok_or("--palette requires a file")?);
+            }
+            "--region" => {
+                let name = args.next().ok_or("--region requires ntsc, pal or dendy")?;
+                options.region = Some(Region::from_name(&name)?);
+            }
+            "--latency" => {
+                let latency = args.next().ok_or("--latency requires milliseconds")?;
+                options.latency = latency
+                    .parse()
+                    .map_err(|_| format!("Invalid latency: {}", latency))?;
+            }
+            "--wav" => {
+                options.wav = Some(args.next().ok_or("--wav requires a file")?);
+            }
+            "--frames" => {
+                let frames = args.next().ok_or("--frames requires a number")?;
+                options.frames = frames
+                    .parse()
+                    .map_err(|_| format!("Invalid frames: {}", frames))?;
+            }
+            "--stems" => options.stems = true,
+            "--track" => {
+                let track = args.next().ok_or("--track requires a number")?;
+                match track.parse::<u8>() {
+                    Ok(track) if track > 0 => options.track = Some(track - 1),
+                    _ => return Err(format!("Invalid track: {}", track)),
+                }
+            }
+            "--keys" => {
+                options.keys = Some(args.next().ok_or("--keys requires a file")?);
+            }
+            "--pad" => {
+                options.pad = Some(args.next().ok_or("--pad requires a file")?);
+            }
+            "--turbo-a" | "--turbo-b" => {
+                let frames = args.next().ok_or(format!("{} requires a number", arg))?;
+                let frames = match frames.parse::<u8>() {
+                    Ok(frames) if frames > 0 => Some(frames),
+                    _ => return Err(format!("Invalid turbo frames: {}", frames)),
+                };
+                if arg == "--turbo-a" {
+                    options.turbo_a = frames;
+                } else {
+                    options.turbo_b = frames;
+                }
+            }
+            "--macro" => {
+                let binding = args.next().ok_or("--macro requires key=file")?;
+                let (key, file) = binding
+                    .split_once('=')
+                    .ok_or(format!("Invalid macro binding: {}", binding))?;
+                let keycode = Keycode::from_name(key).ok_or(format!("Unknown key: {}", key))?;
+                options.macros.push((keycode, Macro::load(file)?));
+            }
+            "--record" => {
+                options.record = Some(args.next().ok_or("--record requires a file")?);
+            }
+            "--play" => {
+                options.play = Some(args.next().ok_or("--play requires a file")?);
+            }
+            "--read-write" => options.read_write = true,
+            "--state" => {
+                options.state = Some(args.next().ok_or("--state requires a file")?);
+            }
+            "--rewind-interval" => {
+                let interval = args.next().ok_or("--rewind-interval requires a number")?;
+                match interval.parse::<u32>() {
+                    Ok(interval) if interval > 0 => options.rewind_interval = interval,
+                    _ => return Err(format!("Invalid rewind interval: {}", interval)),
+                }
+            }
+            "--rewind-mb" => {
+                let budget = args.next().ok_or("--rewind-mb requires a number")?;
+                options.rewind_mb = budget
+                    .parse()
+                    .map_err(|_| format!("Invalid rewind memory: {}", budget))?;
+            }
+            "--fast-forward" => {
+                let rate = args.next().ok_or("--fast-forward requires a number")?;
+                options.fast_forward = rate
+                    .parse()
+                    .map_err(|_| format!("Invalid fast-forward speed: {}", rate))?;
+            }
+            "--slow-motion" => {
+                let rate = args.next().ok_or("--slow-motion requires a number")?;
+                match rate.parse::<u32>() {
+                    Ok(rate) if rate > 0 => options.slow_motion = rate,
+                    _ => return Err(format!("Invalid slow motion speed: {}", rate)),
+                }
+            }
+            "--input" => {
+                let name = args.next().ok_or("--input requires a device name")?;
+                options.input = Some(InputSetup::from_name(&name)?);
+            }
+            _ if options.rom.is_none() => options.rom = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+    if options.state.is_some() && options.play.is_some() {
+        return Err("--state cannot be used with --play".to_string());
+    }
+    if options.state.is_some() && options.record.as_deref().is_some_and(movie::is_fm2_path) {
+        return Err("Movies starting from a save state cannot be recorded as FM2".to_string());
+    }
+    Ok(options)
+}
+
+fn load_palette(options: &Options) -> Palette {
+    match &options.palette {
+        Some(file) => Palette::load(file).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }),
+        None => Palette::default(),
+    }
+}
+
+fn load_cpu(
+    path: &str,
+    region: Option<Region>,
+    input: Option<InputSetup>,
+    palette: &Palette,
+) -> CPU {
+    let bytes: Vec<u8> = std::fs::read(path).unwrap();
+    let rom = Box::new(Rom::new(&bytes).unwrap());
+    let region = region.unwrap_or(rom.region);
+    let memory = Box::new(Memory::new(0xFFFF));
+
+    let chr_rom = rom.chr_rom.clone();
+    let mirror = rom.mirror;
+    let ppu = Box::new(Ppu::new(chr_rom, mirror));
+    let (p1, p2) = input
+        .or_else(|| InputSetup::from_nes2(rom.input_device))
+        .unwrap_or(InputSetup::Joypads)
+        .create(palette);
+
+    let bus = BusBuilder::new()
+        .ram(memory)
+        .rom(rom)
+        .ppu(ppu)
+        .apu(Box::new(Apu::new()))
+        .input_p1(p1)
+        .input_p2(p2)
+        .region(region)
+        .build()
+        .unwrap();
+    let mut cpu = CPU::new(Box::new(bus));
+    cpu.reset();
+    cpu
+}
+
+/// 文件是NSF时创建播放器
+fn load_nsf(path: &str, options: &Options) -> Option<NsfPlayer> {
+    let bytes: Vec<u8> = std::fs::read(path).unwrap();
+    if !nsf::is_nsf(&bytes) {
+        return None;
+    }
+    let mut player = Nsf::new(&bytes)
+        .and_then(|nsf| NsfPlayer::new(nsf, options.region))
+        .unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        });
+    let unsupported = player.nsf.expansion_audio & !nsf::SUPPORTED_EXPANSION;
+    if unsupported != 0 {
+        eprintln!(
+            "Expansion audio ({:#04X}) is not supported and will be silent",
+            unsupported
+        );
+    }
+    if let Some(track) = options.track {
+        player.start_track(track);
+    }
+    Some(player)
+}
+
 fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Some(mut player) = options
+        .rom
+        .as_deref()
+        .and_then(|rom| load_nsf(rom, &options))
+    {
+        match &options.wav {
+            Some(wav) => {
+                let frames = player.track_frames(options.frames);
+                let recording = player.record(frames, options.stems);
+                if let Err(error) = wav::export(wav, &recording) {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+            None => run_nsf(player, &options),
+        }
+        return;
+    }
+    match (&options.rom, &options.wav) {
+        (Some(rom), Some(wav)) => {
+            let palette = load_palette(&options);
+            let mut cpu = load_cpu(rom, options.region, options.input, &palette);
+            let recording = wav::record(&mut cpu, options.frames, options.stems);
+            if let Err(error) = wav::export(wav, &recording) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        (Some(rom), None) => run_nes(rom, &options),
+        (None, _) => run_snake(),
+    }
+}
+
+/// 运行游戏,显示PPU输出的画面
+fn run_nes(path: &str, options: &Options) {
+    let palette = load_palette(options);
+    let keyboard = match &options.keys {
+        Some(file) => KeyboardMapping::load(file).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }),
+        None => KeyboardMapping::default(),
+    };
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "NES",
+            SCREEN_WIDTH as u32 * SCALE as u32,
+            SCREEN_HEIGHT as u32 * SCALE as u32,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(SCALE as f32, SCALE as f32).unwrap();
+
+    let pad_mapping = match &options.pad {
+        Some(file) => ControllerMapping::load(file).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }),
+        None => ControllerMapping::default(),
+    };
+    // 没有手柄子系统时只使用键盘
+    let controllers = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(Controllers::new(subsystem, pad_mapping)),
+        Err(error) => {
+            eprintln!("Failed to init game controllers: {}", error);
+            None
+        }
+    };
+    let mut frame_input = FrameInput::new();
+    if let Some(frames) = options.turbo_a {
+        frame_input.set_turbo_frames(Button::A, frames);
+    }
+    if let Some(frames) = options.turbo_b {
+        frame_input.set_turbo_frames(Button::B, frames);
+    }
+    let mut input = HostInput {
+        keyboard,
+        controllers,
+        macros: options.macros.clone(),
+        frame_input,
+        movie: None,
+        rom: path.to_string(),
+        slot: 0,
+        rewind: Rewind::new(options.rewind_interval, options.rewind_mb << 20),
+        rewinding: false,
+        speed: SpeedControl::new(options.fast_forward, options.slow_motion),
+    };
+
+    let playback = options.play.as_ref().map(|file| {
+        Movie::load(file).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })
+    });
+    // 播放录像时使用录制时的制式
+    let region = options
+        .region
+        .or(playback.as_ref().map(|movie| movie.region));
+    let mut cpu = load_cpu(path, region, options.input, &palette);
+    let rom_hash = hash::fnv1a(&std::fs::read(path).unwrap());
+    // 从存档开始运行,播放的录像自带开始时的存档
+    let start_state = match (&playback, &options.state) {
+        (Some(movie), _) => movie.start_state.clone(),
+        (None, Some(file)) => Some(std::fs::read(file).unwrap_or_else(|error| {
+            eprintln!("{}: {}", file, error);
+            std::process::exit(1);
+        })),
+        (None, None) => None,
+    };
+    if let Some(state) = &start_state {
+        if let Err(error) = cpu.load_state(state) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    input.movie = match (playback, &options.record) {
+        (Some(movie), _) => {
+            if movie.rom_hash != 0 && movie.rom_hash != rom_hash {
+                eprintln!("Warning: the movie was recorded with a different rom");
+            }
+            let session = MovieSession::play(movie, !options.read_write);
+            Some((session, options.play.clone().unwrap()))
+        }
+        (None, Some(file)) => {
+            let players = if cpu.bus.joypad(Port::P3).is_some() {
+                PLAYERS
+            } else {
+                2
+            };
+            let mut movie = Movie::new(players, cpu.bus.region(), rom_hash);
+            movie.start_state = start_state;
+            Some((MovieSession::record(movie), file.clone()))
+        }
+        (None, None) => None,
+    };
+    // 录像从开机或者读取存档后的第一帧开始
+    let states = input.apply(&mut cpu);
+    input.rewind.record(&cpu, states);
+    // 打开音频设备失败时按照制式的帧率控制速度
+    let mut audio = match sdl_context
+        .audio()
+        .and_then(|audio| AudioOutput::open(&audio, options.latency))
+    {
+        Ok(audio) => {
+            cpu.bus.set_audio_sample_rate(audio.sample_rate());
+            Some(audio)
+        }
+        Err(error) => {
+            eprintln!("Failed to open audio device: {}", error);
+            None
+        }
+    };
+    let frame_duration = std::time::Duration::from_secs_f64(1.0 / cpu.bus.region().frame_rate());
+    let mut next_frame = std::time::Instant::now();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(
+            PixelFormatEnum::RGB24,
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+        )
+        .unwrap();
+
+    let mut screen = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let mut draw = move |frame: &[u16]| {
+        palette.to_rgb24(frame, &mut screen);
+        texture.update(None, &screen, SCREEN_WIDTH * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+    };
+    cpu.run_with_callback(move |cpu| {
+        if let Some(frame) = cpu.bus.poll_frame() {
+            draw(frame);
+            handle_nes_events(cpu, &mut event_pump, &mut input);
+            // 暂停时静音并继续处理事件,直到恢复、逐帧或者开始倒带
+            if input.speed.wait() {
+                if let Some(audio) = &mut audio {
+                    audio.set_paused(true);
+                }
+                while !input.rewinding && input.speed.wait() {
+                    std::thread::sleep(frame_duration);
+                    handle_nes_events(cpu, &mut event_pump, &mut input);
+                }
+                next_frame = std::time::Instant::now();
+            }
+            // 倒带时静音,按帧率逐帧显示倒退的画面,松开后从倒退到的帧继续
+            if input.rewinding {
+                if let Some(audio) = &mut audio {
+                    audio.set_paused(true);
+                }
+                while input.rewinding {
+                    if let Some(frame) = input.rewind.step_back(cpu) {
+                        draw(&frame);
+                    }
+                    if let Some(audio) = &mut audio {
+                        audio.skip_frame(cpu.bus.as_mut());
+                    }
+                    std::thread::sleep(frame_duration);
+                    handle_nes_events(cpu, &mut event_pump, &mut input);
+                }
+                next_frame = std::time::Instant::now();
+            }
+            // 每运行一帧写入一次手柄的状态
+            let states = input.apply(cpu);
+            input.rewind.record(cpu, states);
+            // 正常速度由音频控制;快进时丢弃多余的采样,慢动作时静音,都按计时器控制速度
+            let speed = input.speed.speed();
+            if let Some(audio) = &mut audio {
+                audio.set_paused(matches!(speed, Speed::Slow(_)));
+            }
+            match &mut audio {
+                Some(audio) if speed == Speed::Normal => {
+                    audio.push_frame(cpu.bus.as_mut());
+                    audio.wait();
+                }
+                _ => {
+                    if let Some(audio) = &mut audio {
+                        audio.skip_frame(cpu.bus.as_mut());
+                    }
+                    next_frame += input.speed.frame_duration(frame_duration);
+                    let now = std::time::Instant::now();
+                    if next_frame > now {
+                        std::thread::sleep(next_frame - now);
+                    } else {
+                        next_frame = now;
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// 窗口标题显示曲名与当前曲目
+fn nsf_title(player: &NsfPlayer) -> String {
+    let track = player.nsf.track(player.track());
+    let mut title = format!(
+        "{} - {} [{}/{}]",
+        player.nsf.name,
+        player.nsf.artist,
+        player.track() + 1,
+        player.nsf.songs
+    );
+    if let Some(name) = track.name {
+        title = format!("{} {}", title, name);
+    }
+    title
+}
+
+/// 播放NSF音乐,左右方向键切换曲目,NSFe提供了曲目长度时播放完自动切换到下一首
+fn run_nsf(mut player: NsfPlayer, options: &Options) {
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(&nsf_title(&player), 512, 64)
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let mut audio = match sdl_context
+        .audio()
+        .and_then(|audio| AudioOutput::open(&audio, options.latency))
+    {
+        Ok(audio) => {
+            player.bus().set_audio_sample_rate(audio.sample_rate());
+            Some(audio)
+        }
+        Err(error) => {
+            eprintln!("Failed to open audio device: {}", error);
+            None
+        }
+    };
+    let frame_duration = std::time::Duration::from_secs_f64(1.0 / player.play_rate());
+    let mut next_frame = std::time::Instant::now();
+
+    let songs = player.nsf.songs;
+    let mut frames = 0;
+    loop {
+        let mut track = player.track();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Left),
+                    ..
+                } => track = (track + songs - 1) % songs,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Right),
+                    ..
+                } => track = (track + 1) % songs,
+                _ => {}
+            }
+        }
+        if frames >= player.track_frames(u32::MAX) {
+            track = (track + 1) % songs;
+        }
+        if track != player.track() {
+            player.start_track(track);
+            canvas.window_mut().set_title(&nsf_title(&player)).unwrap();
+            frames = 0;
+        }
+
+        player.run_frame();
+        frames += 1;
+        canvas.present();
+        match &mut audio {
+            Some(audio) => {
+                audio.push_frame(player.bus());
+                audio.wait();
+            }
+            None => {
+                next_frame += frame_duration;
+                let now = std::time::Instant::now();
+                if next_frame > now {
+                    std::thread::sleep(next_frame - now);
+                } else {
+                    next_frame = now;
+                }
+            }
+        }
+    }
+}
+
+/// 运行贪吃蛇演示,画面直接读取自内存$0200~$05FF
+fn run_snake() {
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -112,22 +980,7 @@ fn main() {
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
         .unwrap();
-    let bytes: Vec<u8> = std::fs::read("snake.nes").unwrap();
-    let rom = Box::new(Rom::new(&bytes).unwrap());
-    let memory = Box::new(Memory::new(0xFFFF));
-
-    let chr_rom = rom.chr_rom.clone();
-    let mirror = rom.mirror.clone();
-    let ppu = Box::new(Ppu::new(chr_rom, mirror));
-
-    let bus = BusBuilder::new()
-        .ram(memory)
-        .rom(rom)
-        .ppu(ppu)
-        .build()
-        .unwrap();
-    let mut cpu = CPU::new(Box::new(bus));
-    cpu.reset();
+    let mut cpu = load_cpu("snake.nes", None, None, &Palette::default());
 
     let mut screen_state = [0 as u8; 32 * 3 * 32];
     let mut rng = rand::thread_rng();
@@ -1,4 +1,7 @@
-use crate::addressable::*;
+use crate::{
+    addressable::*,
+    state::{Snapshot, StateReader, StateWriter},
+};
 
 pub struct Memory {
     data: Vec<u8>,
@@ -22,4 +25,21 @@ impl Writable for Memory {
     }
 }
 
+impl Snapshot for Memory {
+    fn save(&self, w: &mut StateWriter) {
+        (self.data.len() as u32).save(w);
+        w.write_bytes(&self.data);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut length = 0u32;
+        length.load(r)?;
+        if length as usize != self.data.len() {
+            return Err("Memory size mismatch in save state".to_string());
+        }
+        self.data.copy_from_slice(r.read_bytes(length as usize)?);
+        Ok(())
+    }
+}
+
 impl Addressable for Memory {}
@@ -4,3 +4,75 @@ pub enum Mirror {
     Horizontal,
     FourScreen,
 }
+
+/// 电视制式,决定了CPU/PPU的时钟与每帧的扫描线数
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// 每帧的扫描线数(含预渲染扫描线)
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// 垂直消隐开始的扫描线
+    pub fn vblank_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// 预渲染扫描线(每帧的最后一条扫描线)
+    pub fn pre_render_line(&self) -> u16 {
+        self.scanlines() - 1
+    }
+
+    /// 只有NTSC在奇数帧跳过一个点
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    /// 每个CPU周期对应的PPU点数乘以5(NTSC与Dendy为3,PAL为3.2)
+    pub fn ppu_dots_per_cpu_cycle_x5(&self) -> u32 {
+        match self {
+            Region::Ntsc | Region::Dendy => 15,
+            Region::Pal => 16,
+        }
+    }
+
+    /// CPU时钟频率(Hz)
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// 帧率
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    /// 解析命令行中的制式名称
+    pub fn from_name(name: &str) -> Result<Region, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region: {}", name)),
+        }
+    }
+}
@@ -0,0 +1,468 @@
+use crate::{
+    bus::CpuBus,
+    frame_input::{format_buttons, parse_buttons, PLAYERS},
+    hash::fnv1a,
+    meta::Region,
+};
+
+// 原生录像格式(小端)
+//
+// $00 "NMV\x1A"
+// $04 版本
+// $05 手柄数量(2或4)
+// $06 制式(0: NTSC, 1: PAL, 2: Dendy)
+// $07 标志,bit 0: 从存档开始录制
+// $08 ROM文件的哈希(FNV-1a),为0时不检查
+// $10 重录次数
+// $14 RAM哈希的间隔帧数
+// $18 帧数
+// $1C RAM哈希的数量
+// $20 每帧每个手柄一个字节的状态,之后是每个RAM哈希8字节,
+//     从存档开始录制时最后是存档的长度(4字节)与存档
+//
+// FM2为FCEUX的文本格式,文件头每行一个`键 值`,之后每帧一行`|命令|1P|2P||`,
+// 使用四人适配器时为`|命令|1P|2P|3P|4P||`,手柄按RLDUTSBA的顺序,没有按下的按钮为'.'
+// FM2不包含RAM哈希,也不写入romChecksum(需要MD5),FCEUX加载时只会给出警告;
+// FCEUX的存档格式不同,因此不支持从存档开始的FM2录像
+
+const MOVIE_TAG: [u8; 4] = [0x4E, 0x4D, 0x56, 0x1A];
+const MOVIE_VERSION: u8 = 1;
+const MOVIE_HEADER_SIZE: usize = 0x20;
+/// 默认每60帧检查一次RAM
+pub const DEFAULT_SYNC_INTERVAL: u32 = 60;
+/// FM2命令: 开机
+const FM2_POWER: u8 = 0b10;
+/// 标志: 从存档开始录制
+const FLAG_START_STATE: u8 = 0b1;
+
+/// 逐帧记录的手柄输入,从开机或者存档开始
+///
+/// 只记录标准手柄,光枪等其他设备的输入不会被录制
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// 录制的手柄数量,没有四人适配器时为2
+    pub players: usize,
+    pub region: Region,
+    /// ROM文件的哈希,为0时不检查
+    pub rom_hash: u64,
+    /// 重录次数
+    pub rerecords: u32,
+    pub frames: Vec<[u8; PLAYERS]>,
+    /// RAM哈希的间隔帧数
+    pub sync_interval: u32,
+    /// 第i个为第i * sync_interval帧开始时RAM的哈希
+    pub ram_hashes: Vec<u64>,
+    /// 录制开始时的存档,为None时从开机开始
+    pub start_state: Option<Vec<u8>>,
+}
+
+impl Movie {
+    pub fn new(players: usize, region: Region, rom_hash: u64) -> Movie {
+        Movie {
+            players,
+            region,
+            rom_hash,
+            rerecords: 0,
+            frames: Vec::new(),
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+            ram_hashes: Vec::new(),
+            start_state: None,
+        }
+    }
+
+    /// 根据扩展名选择格式,.fm2为FM2,其他为原生格式
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if is_fm2_path(path) {
+            let text = String::from_utf8(data).map_err(|_| format!("{}: not a text file", path))?;
+            Movie::from_fm2(&text)
+        } else {
+            Movie::from_bytes(&data)
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let data = if is_fm2_path(path) {
+            self.to_fm2()?.into_bytes()
+        } else {
+            self.to_bytes()
+        };
+        std::fs::write(path, data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, String> {
+        if data.len() < MOVIE_HEADER_SIZE || data[0..4] != MOVIE_TAG {
+            return Err("Not a movie file".to_string());
+        }
+        if data[4] != MOVIE_VERSION {
+            return Err(format!("Unsupported movie version: {}", data[4]));
+        }
+        let players = data[5] as usize;
+        if players != 2 && players != PLAYERS {
+            return Err(format!("Invalid number of players: {}", players));
+        }
+        let region = match data[6] {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            region => return Err(format!("Invalid region: {}", region)),
+        };
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let frame_count = u32_at(0x18) as usize;
+        let hash_count = u32_at(0x1C) as usize;
+        let hashes_start = MOVIE_HEADER_SIZE + frame_count * players;
+        let hashes_end = hashes_start + hash_count * 8;
+        let truncated = || "Truncated movie file".to_string();
+        let start_state = if data[7] & FLAG_START_STATE != 0 {
+            let length = data.get(hashes_end..hashes_end + 4).ok_or_else(truncated)?;
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+            let state = data
+                .get(hashes_end + 4..hashes_end + 4 + length)
+                .ok_or_else(truncated)?;
+            Some(state.to_vec())
+        } else {
+            None
+        };
+        let state_size = start_state.as_ref().map_or(0, |state| 4 + state.len());
+        if data.len() != hashes_end + state_size {
+            return Err(truncated());
+        }
+        let frames = data[MOVIE_HEADER_SIZE..hashes_start]
+            .chunks(players)
+            .map(|chunk| {
+                let mut states = [0; PLAYERS];
+                states[..players].copy_from_slice(chunk);
+                states
+            })
+            .collect();
+        let ram_hashes = data[hashes_start..hashes_end]
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Movie {
+            players,
+            region,
+            rom_hash: u64::from_le_bytes(data[0x08..0x10].try_into().unwrap()),
+            rerecords: u32_at(0x10),
+            frames,
+            sync_interval: u32_at(0x14).max(1),
+            ram_hashes,
+            start_state,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MOVIE_TAG.to_vec();
+        data.push(MOVIE_VERSION);
+        data.push(self.players as u8);
+        data.push(match self.region {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => 2,
+        });
+        data.push(if self.start_state.is_some() {
+            FLAG_START_STATE
+        } else {
+            0
+        });
+        data.extend(self.rom_hash.to_le_bytes());
+        data.extend(self.rerecords.to_le_bytes());
+        data.extend(self.sync_interval.to_le_bytes());
+        data.extend((self.frames.len() as u32).to_le_bytes());
+        data.extend((self.ram_hashes.len() as u32).to_le_bytes());
+        for states in &self.frames {
+            data.extend(&states[..self.players]);
+        }
+        for hash in &self.ram_hashes {
+            data.extend(hash.to_le_bytes());
+        }
+        if let Some(state) = &self.start_state {
+            data.extend((state.len() as u32).to_le_bytes());
+            data.extend(state);
+        }
+        data
+    }
+
+    pub fn from_fm2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::new(2, Region::Ntsc, 0);
+        for line in text.lines() {
+            let line = line.trim_end();
+            if let Some(fields) = line.strip_prefix('|') {
+                let states = parse_fm2_frame(fields, movie.players, movie.frames.len())?;
+                movie.frames.push(states);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "palFlag" if value == "1" => movie.region = Region::Pal,
+                "fourscore" if value == "1" => movie.players = PLAYERS,
+                "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+                "binary" if value == "1" => {
+                    return Err("Binary FM2 input is not supported".to_string())
+                }
+                "savestate" => {
+                    return Err("FM2 movies starting from a savestate are not supported".to_string())
+                }
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> Result<String, String> {
+        if self.start_state.is_some() {
+            return Err("Movies starting from a save state cannot be saved as FM2".to_string());
+        }
+        let four_score = self.players == PLAYERS;
+        let mut text = String::new();
+        text.push_str("version 3\n");
+        text.push_str("emuVersion 22020\n");
+        text.push_str(&format!("rerecordCount {}\n", self.rerecords));
+        text.push_str(&format!("palFlag {}\n", (self.region == Region::Pal) as u8));
+        text.push_str("guid 00000000-0000-0000-0000-000000000000\n");
+        text.push_str(&format!("fourscore {}\n", four_score as u8));
+        // 使用四人适配器时port0与port1被忽略
+        text.push_str("port0 1\nport1 1\nport2 0\n");
+        for states in &self.frames {
+            text.push_str("|0|");
+            for state in &states[..self.players] {
+                text.push_str(&format_buttons(*state));
+                text.push('|');
+            }
+            text.push_str("|\n");
+        }
+        Ok(text)
+    }
+}
+
+pub fn is_fm2_path(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".fm2")
+}
+
+/// 解析FM2的一帧,fields不包含开头的'|'
+fn parse_fm2_frame(fields: &str, players: usize, frame: usize) -> Result<[u8; PLAYERS], String> {
+    let fields: Vec<&str> = fields.split('|').collect();
+    let invalid = || format!("Invalid FM2 input at frame {}", frame);
+    if fields.len() < players + 1 {
+        return Err(invalid());
+    }
+    let command: u8 = fields[0].trim().parse().map_err(|_| invalid())?;
+    // 第一帧的开机命令与从开机开始录制相同
+    if command != 0 && !(frame == 0 && command == FM2_POWER) {
+        return Err(format!(
+            "Unsupported FM2 command {} at frame {}",
+            command, frame
+        ));
+    }
+    let mut states = [0; PLAYERS];
+    for (state, field) in states.iter_mut().zip(&fields[1..=players]) {
+        // 按钮的位置固定,任何非'.'与空格的字符都表示按下
+        let letters: String = field
+            .chars()
+            .zip("RLDUTSBA".chars())
+            .map(|(c, letter)| if c == '.' || c == ' ' { '.' } else { letter })
+            .collect();
+        *state = parse_buttons(&letters)?;
+    }
+    Ok(states)
+}
+
+/// CPU内部2KB RAM的哈希,用于检查录像是否同步
+pub fn ram_hash(bus: &dyn CpuBus) -> u64 {
+    let ram: Vec<u8> = (0..0x800).map(|addr| bus.read(addr)).collect();
+    fnv1a(&ram)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    /// 只播放,录像结束后恢复主机输入
+    ReadOnly,
+    /// 播放,录像结束或接管后从当前帧继续录制
+    ReadWrite,
+}
+
+/// 正在录制或播放的录像
+pub struct MovieSession {
+    pub movie: Movie,
+    mode: MovieMode,
+    /// 下一帧的序号
+    frame: usize,
+    /// 第一次发现RAM哈希不一致的帧
+    desync: Option<usize>,
+}
+
+impl MovieSession {
+    pub fn record(movie: Movie) -> Self {
+        MovieSession {
+            movie,
+            mode: MovieMode::Recording,
+            frame: 0,
+            desync: None,
+        }
+    }
+
+    pub fn play(movie: Movie, read_only: bool) -> Self {
+        MovieSession {
+            movie,
+            mode: if read_only {
+                MovieMode::ReadOnly
+            } else {
+                MovieMode::ReadWrite
+            },
+            frame: 0,
+            desync: None,
+        }
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    pub fn desync(&self) -> Option<usize> {
+        self.desync
+    }
+
+    /// 读写模式下放弃当前帧之后的录像,从当前帧开始录制
+    pub fn take_over(&mut self) -> bool {
+        if self.mode != MovieMode::ReadWrite {
+            return false;
+        }
+        let interval = self.movie.sync_interval as usize;
+        self.movie.frames.truncate(self.frame);
+        self.movie
+            .ram_hashes
+            .truncate(self.frame.div_ceil(interval));
+        self.movie.rerecords += 1;
+        self.mode = MovieMode::Recording;
+        true
+    }
+
+    /// 每帧开始前调用,live为主机输入的状态,返回这一帧实际使用的状态
+    pub fn next_frame(&mut self, live: [u8; PLAYERS], bus: &dyn CpuBus) -> [u8; PLAYERS] {
+        if self.mode == MovieMode::ReadWrite && self.frame >= self.movie.frames.len() {
+            self.take_over();
+        }
+        let interval = self.movie.sync_interval as usize;
+        let sync_index = self
+            .frame
+            .is_multiple_of(interval)
+            .then_some(self.frame / interval);
+        if self.mode == MovieMode::Recording {
+            if sync_index == Some(self.movie.ram_hashes.len()) {
+                self.movie.ram_hashes.push(ram_hash(bus));
+            }
+            let mut states = [0; PLAYERS];
+            states[..self.movie.players].copy_from_slice(&live[..self.movie.players]);
+            self.movie.frames.push(states);
+            self.frame += 1;
+            return states;
+        }
+        // 只读模式下录像已经结束
+        if self.frame >= self.movie.frames.len() {
+            return live;
+        }
+        let expected = sync_index.and_then(|index| self.movie.ram_hashes.get(index));
+        if self.desync.is_none() && expected.is_some_and(|hash| *hash != ram_hash(bus)) {
+            self.desync = Some(self.frame);
+        }
+        self.frame += 1;
+        self.movie.frames[self.frame - 1]
+    }
+}
+
+#[cfg(test)]
+fn test_movie() -> Movie {
+    let mut movie = Movie::new(PLAYERS, Region::Pal, 0x1234_5678_9ABC_DEF0);
+    movie.rerecords = 3;
+    movie.frames = vec![[0x01, 0x80, 0x00, 0x08], [0x00, 0x00, 0xFF, 0x00]];
+    movie.ram_hashes = vec![42];
+    movie
+}
+
+#[test]
+fn test_native_format() {
+    let movie = test_movie();
+    let data = movie.to_bytes();
+    assert_eq!(data.len(), MOVIE_HEADER_SIZE + 2 * 4 + 8);
+    assert_eq!(Movie::from_bytes(&data), Ok(movie));
+    assert!(Movie::from_bytes(&data[..data.len() - 1]).is_err());
+
+    // 从存档开始的录像在最后保存存档
+    let mut movie = test_movie();
+    movie.start_state = Some(vec![1, 2, 3]);
+    let data = movie.to_bytes();
+    assert_eq!(data.len(), MOVIE_HEADER_SIZE + 2 * 4 + 8 + 4 + 3);
+    assert_eq!(Movie::from_bytes(&data), Ok(movie.clone()));
+    assert!(Movie::from_bytes(&data[..data.len() - 1]).is_err());
+    assert!(movie.to_fm2().is_err());
+}
+
+#[test]
+fn test_fm2_format() {
+    let mut movie = test_movie();
+    let text = movie.to_fm2().unwrap();
+    assert!(text.contains("fourscore 1\n"));
+    assert!(text.contains("\n|0|.......A|R.......|........|....T...||\n"));
+    // FM2不保存RAM哈希与ROM哈希
+    movie.ram_hashes.clear();
+    movie.rom_hash = 0;
+    assert_eq!(Movie::from_fm2(&text), Ok(movie));
+
+    let fceux =
+        "version 3\nport0 1\nport1 1\nport2 0\n|2|........|........||\n|0|R  UT  A|.L..... ||\n";
+    let movie = Movie::from_fm2(fceux).unwrap();
+    assert_eq!(movie.players, 2);
+    assert_eq!(movie.frames, [[0; PLAYERS], [0x99, 0x40, 0, 0]]);
+    assert!(Movie::from_fm2("|1|........|........||\n").is_err());
+    assert!(Movie::from_fm2("savestate base64:AAAA\n|0|........|........||\n").is_err());
+}
+
+#[test]
+fn test_movie_session() {
+    use crate::{addressable::Writable, bus::Bus, input::InputSetup};
+    let mut bus: Bus = {
+        use crate::{apu::Apu, bus::BusBuilder, memory::Memory, rom::test::test_rom};
+        let (p1, p2) = InputSetup::Joypads.create(&crate::ppu::Palette::default());
+        BusBuilder::new()
+            .ram(Box::new(Memory::new(0xFFFF)))
+            .rom(Box::new(test_rom()))
+            .apu(Box::new(Apu::new()))
+            .input_p1(p1)
+            .input_p2(p2)
+            .build()
+            .unwrap()
+    };
+    let mut movie = Movie::new(2, Region::Ntsc, 0);
+    movie.sync_interval = 2;
+    let mut session = MovieSession::record(movie);
+    for frame in 0..5u8 {
+        bus.write(0x10, frame);
+        assert_eq!(session.next_frame([frame, 1, 2, 3], &bus), [frame, 1, 0, 0]);
+    }
+    assert_eq!(session.movie.frames.len(), 5);
+    assert_eq!(session.movie.ram_hashes.len(), 3);
+
+    // 只读播放时忽略主机输入,RAM不同时报告不同步的帧
+    let mut session = MovieSession::play(session.movie, true);
+    for frame in 0..5u8 {
+        bus.write(0x10, if frame < 4 { frame } else { 0xFF });
+        assert_eq!(session.next_frame([0xFF; PLAYERS], &bus), [frame, 1, 0, 0]);
+    }
+    assert_eq!(session.desync(), Some(4));
+    assert_eq!(session.next_frame([7; PLAYERS], &bus), [7; PLAYERS]);
+
+    // 读写模式接管后截断录像
+    let mut session = MovieSession::play(session.movie, false);
+    session.next_frame([0; PLAYERS], &bus);
+    session.next_frame([0; PLAYERS], &bus);
+    session.next_frame([0; PLAYERS], &bus);
+    assert!(session.take_over());
+    assert_eq!(session.mode(), MovieMode::Recording);
+    assert_eq!(session.movie.frames.len(), 3);
+    assert_eq!(session.movie.ram_hashes.len(), 2);
+    assert_eq!(session.movie.rerecords, 1);
+    session.next_frame([9, 9, 9, 9], &bus);
+    assert_eq!(session.movie.frames[3], [9, 9, 0, 0]);
+}
@@ -0,0 +1,758 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    addressable::*,
+    apu::{Apu, ExpansionAudio, Fds, Mmc5, Sunsoft5b, Vrc6, Vrc7, N163},
+    bus::{BusBuilder, CpuBus},
+    cpu::CPU,
+    memory::Memory,
+    meta::Region,
+    state::{Snapshot, StateReader, StateWriter},
+    wav::Recording,
+};
+
+// NSF文件格式
+//
+// $00 "NESM\x1A"
+// $05 版本
+// $06 曲目总数
+// $07 起始曲目(从1开始)
+// $08 装载地址
+// $0A INIT地址
+// $0C PLAY地址
+// $0E 曲名, $2E 作者, $4E 版权 (各32字节,以0结尾)
+// $6E NTSC下PLAY的调用周期(微秒)
+// $70 8个bank的初始值,全为0时不切换bank
+// $78 PAL下PLAY的调用周期(微秒)
+// $7A 制式(bit0: PAL, bit1: 同时支持NTSC与PAL)
+// $7B 扩展音源(bit0: VRC6, bit1: VRC7, bit2: FDS, bit3: MMC5, bit4: N163, bit5: 5B)
+// $80 程序数据
+//
+// NSFe文件以"NSFE"开头,之后是一系列数据块: 长度(4字节) 标识(4字节) 数据
+// INFO 地址、制式、扩展音源、曲目数
+// DATA 程序数据
+// BANK 8个bank的初始值
+// RATE PLAY的调用周期
+// time 每首曲目的长度(毫秒)
+// fade 每首曲目的淡出时间(毫秒)
+// tlbl 每首曲目的名称
+// auth 曲名、作者、版权、提取者
+// NEND 结束
+// 标识以大写字母开头的数据块是必须理解的,无法识别时拒绝加载
+
+const NSF_TAG: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A];
+const NSFE_TAG: [u8; 4] = [0x4E, 0x53, 0x46, 0x45];
+const NSF_HEADER_SIZE: usize = 0x80;
+/// bank的大小
+const BANK_SIZE: usize = 0x1000;
+/// 未指定时PLAY的调用周期(微秒)
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+const EXPANSION_VRC6: u8 = 0x01;
+const EXPANSION_VRC7: u8 = 0x02;
+const EXPANSION_FDS: u8 = 0x04;
+const EXPANSION_MMC5: u8 = 0x08;
+const EXPANSION_N163: u8 = 0x10;
+const EXPANSION_5B: u8 = 0x20;
+/// 支持的扩展音源
+pub const SUPPORTED_EXPANSION: u8 = 0x3F;
+/// $6000~$FFFF的bank数
+const PAGES: usize = 10;
+
+/// 曲目信息(NSFe)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Track {
+    pub name: Option<String>,
+    /// 长度(毫秒)
+    pub length: Option<u32>,
+    /// 淡出时间(毫秒)
+    pub fade: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    /// 曲目总数
+    pub songs: u8,
+    /// 起始曲目(从0开始)
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub region: Region,
+    /// bank的初始值,为None时不切换bank
+    pub banks: Option<[u8; 8]>,
+    /// 扩展音源(VRC6, VRC7, FDS, MMC5, N163, 5B)
+    pub expansion_audio: u8,
+    /// 每首曲目的信息,只有NSFe提供
+    pub tracks: Vec<Track>,
+    pub data: Vec<u8>,
+}
+
+/// 是否是NSF或NSFe文件
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(&NSF_TAG) || raw.starts_with(&NSFE_TAG)
+}
+
+impl Nsf {
+    pub fn new(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.starts_with(&NSF_TAG) {
+            Self::parse_nsf(raw)
+        } else if raw.starts_with(&NSFE_TAG) {
+            Self::parse_nsfe(raw)
+        } else {
+            Err("File is not in NSF format".to_string())
+        }
+    }
+
+    fn empty() -> Nsf {
+        Nsf {
+            name: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            songs: 1,
+            starting_song: 0,
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            region: Region::Ntsc,
+            banks: None,
+            expansion_audio: 0,
+            tracks: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.len() < NSF_HEADER_SIZE {
+            return Err("NSF header is truncated".to_string());
+        }
+        let banks: [u8; 8] = raw[0x70..0x78].try_into().unwrap();
+        Ok(Nsf {
+            name: read_string(&raw[0x0E..0x2E]),
+            artist: read_string(&raw[0x2E..0x4E]),
+            copyright: read_string(&raw[0x4E..0x6E]),
+            songs: raw[0x06],
+            starting_song: raw[0x07].saturating_sub(1),
+            load_address: read_u16(raw, 0x08),
+            init_address: read_u16(raw, 0x0A),
+            play_address: read_u16(raw, 0x0C),
+            ntsc_speed: read_u16(raw, 0x6E),
+            pal_speed: read_u16(raw, 0x78),
+            region: parse_region(raw[0x7A]),
+            banks: banks.iter().any(|b| *b != 0).then_some(banks),
+            expansion_audio: raw[0x7B],
+            tracks: Vec::new(),
+            data: raw[NSF_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, String> {
+        let mut nsf = Nsf::empty();
+        let mut offset = NSFE_TAG.len();
+        let mut has_info = false;
+        let mut names = Vec::new();
+        let mut lengths = Vec::new();
+        let mut fades = Vec::new();
+        loop {
+            if offset + 8 > raw.len() {
+                return Err("NSFe chunk is truncated".to_string());
+            }
+            let length = u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap()) as usize;
+            let id = &raw[offset + 4..offset + 8];
+            offset += 8;
+            if offset + length > raw.len() {
+                return Err("NSFe chunk is truncated".to_string());
+            }
+            let chunk = &raw[offset..offset + length];
+            offset += length;
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err("NSFe INFO chunk is too short".to_string());
+                    }
+                    has_info = true;
+                    nsf.load_address = read_u16(chunk, 0);
+                    nsf.init_address = read_u16(chunk, 2);
+                    nsf.play_address = read_u16(chunk, 4);
+                    nsf.region = parse_region(chunk[6]);
+                    nsf.expansion_audio = chunk[7];
+                    nsf.songs = chunk.get(8).copied().unwrap_or(1);
+                    nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, data) in banks.iter_mut().zip(chunk) {
+                        *bank = *data;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.ntsc_speed = read_u16(chunk, 0);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.pal_speed = read_u16(chunk, 2);
+                    }
+                }
+                b"time" => lengths = read_times(chunk),
+                b"fade" => fades = read_times(chunk),
+                b"tlbl" => names = read_strings(chunk),
+                b"auth" => {
+                    let mut strings = read_strings(chunk).into_iter();
+                    nsf.name = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(format!(
+                        "Unsupported NSFe chunk: {}",
+                        String::from_utf8_lossy(id)
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if !has_info {
+            return Err("NSFe has no INFO chunk".to_string());
+        }
+        nsf.tracks = (0..nsf.songs as usize)
+            .map(|i| Track {
+                name: names.get(i).cloned(),
+                length: lengths.get(i).copied().flatten(),
+                fade: fades.get(i).copied().flatten(),
+            })
+            .collect();
+        Ok(nsf)
+    }
+
+    /// 曲目信息,NSF文件没有时返回空的信息
+    pub fn track(&self, track: u8) -> Track {
+        self.tracks.get(track as usize).cloned().unwrap_or_default()
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// 读取以0结尾的字符串
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// 读取一系列以0结尾的字符串
+fn read_strings(data: &[u8]) -> Vec<String> {
+    data.split(|b| *b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect()
+}
+
+/// 读取一系列毫秒数,负数表示未知
+fn read_times(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks_exact(4)
+        .map(|c| {
+            let time = i32::from_le_bytes(c.try_into().unwrap());
+            (time >= 0).then_some(time as u32)
+        })
+        .collect()
+}
+
+/// 同时支持两种制式时使用NTSC
+fn parse_region(flags: u8) -> Region {
+    if flags & 0b11 == 0b01 {
+        Region::Pal
+    } else {
+        Region::Ntsc
+    }
+}
+
+/// 头部的扩展音源标志对应的音源
+fn expansion_chips(flags: u8) -> Vec<Box<dyn ExpansionAudio>> {
+    let mut chips: Vec<Box<dyn ExpansionAudio>> = Vec::new();
+    if flags & EXPANSION_VRC6 != 0 {
+        chips.push(Box::new(Vrc6::new()));
+    }
+    if flags & EXPANSION_VRC7 != 0 {
+        chips.push(Box::new(Vrc7::new()));
+    }
+    if flags & EXPANSION_FDS != 0 {
+        chips.push(Box::new(Fds::new()));
+    }
+    if flags & EXPANSION_MMC5 != 0 {
+        chips.push(Box::new(Mmc5::new()));
+    }
+    if flags & EXPANSION_N163 != 0 {
+        chips.push(Box::new(N163::new()));
+    }
+    if flags & EXPANSION_5B != 0 {
+        chips.push(Box::new(Sunsoft5b::new()));
+    }
+    chips
+}
+
+/// NSF的程序空间,$6000~$FFFF分为10个4K的bank
+///
+/// 通常只有$8000~$FFFF映射到程序数据,$6000~$7FFF是普通的SRAM;
+/// 使用FDS时$6000~$FFFF都是RAM,切换bank时把程序数据复制到RAM中
+struct NsfMemory {
+    data: Vec<u8>,
+    banks: [u8; PAGES],
+    ram: Option<Vec<u8>>,
+}
+
+impl NsfMemory {
+    fn switch_bank(&mut self, page: usize, bank: u8) {
+        self.banks[page] = bank;
+        if let Some(ram) = &mut self.ram {
+            let start = bank as usize * BANK_SIZE;
+            for (i, byte) in ram[page * BANK_SIZE..(page + 1) * BANK_SIZE]
+                .iter_mut()
+                .enumerate()
+            {
+                *byte = self.data.get(start + i).copied().unwrap_or(0);
+            }
+        }
+    }
+
+    /// 恢复初始的bank,FDS的RAM重新装载程序数据
+    fn reset(&mut self, banks: [u8; PAGES]) {
+        for (page, bank) in banks.into_iter().enumerate() {
+            self.switch_bank(page, bank);
+        }
+    }
+
+    /// addr为$6000~$FFFF
+    fn read(&self, addr: u16) -> u8 {
+        let offset = addr as usize - 0x6000;
+        match &self.ram {
+            Some(ram) => ram[offset],
+            None => {
+                let bank = self.banks[offset / BANK_SIZE] as usize;
+                let index = bank * BANK_SIZE + offset % BANK_SIZE;
+                self.data.get(index).copied().unwrap_or(0)
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(ram) = &mut self.ram {
+            ram[addr as usize - 0x6000] = data;
+        }
+    }
+}
+
+/// 总线上的一段程序空间,base为起始地址
+struct NsfRom {
+    memory: Rc<RefCell<NsfMemory>>,
+    base: u16,
+}
+
+impl Readable for NsfRom {
+    fn read(&self, addr: u16) -> u8 {
+        self.memory.borrow().read(self.base + addr)
+    }
+}
+
+impl Writable for NsfRom {
+    fn write(&mut self, addr: u16, data: u8) {
+        self.memory.borrow_mut().write(self.base + addr, data);
+    }
+}
+
+/// bank与RAM由BankSwitch保存
+impl Snapshot for NsfRom {
+    fn save(&self, _: &mut StateWriter) {}
+
+    fn load(&mut self, _: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Addressable for NsfRom {}
+
+/// 写入$5FF8~$5FFF切换$8000~$FFFF的bank,使用FDS时$5FF6~$5FF7切换$6000~$7FFF的bank
+struct BankSwitch {
+    memory: Rc<RefCell<NsfMemory>>,
+}
+
+impl Readable for BankSwitch {
+    fn read(&self, _addr: u16) -> u8 {
+        0
+    }
+}
+
+impl Writable for BankSwitch {
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0x5FF6..=0x5FFF = addr {
+            self.memory
+                .borrow_mut()
+                .switch_bank((addr - 0x5FF6) as usize, data);
+        }
+    }
+}
+
+impl Snapshot for BankSwitch {
+    fn save(&self, w: &mut StateWriter) {
+        let memory = self.memory.borrow();
+        memory.banks.save(w);
+        memory.ram.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut memory = self.memory.borrow_mut();
+        memory.banks.load(r)?;
+        memory.ram.load(r)
+    }
+}
+
+impl Addressable for BankSwitch {}
+
+/// NSF播放器
+/// 总线上只有RAM、SRAM、APU、扩展音源与NSF的程序,没有PPU;
+/// 切换曲目时调用INIT,之后按照头部的周期调用PLAY
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    pub cpu: CPU,
+    region: Region,
+    memory: Rc<RefCell<NsfMemory>>,
+    initial_banks: [u8; PAGES],
+    track: u8,
+    /// PLAY的调用周期(CPU周期)
+    play_period: f64,
+    /// 上一次调用PLAY后不足一个周期的部分
+    play_remainder: f64,
+}
+
+impl NsfPlayer {
+    /// region为None时使用NSF头部的制式
+    pub fn new(nsf: Nsf, region: Option<Region>) -> Result<NsfPlayer, String> {
+        let region = region.unwrap_or(nsf.region);
+        let fds = nsf.expansion_audio & EXPANSION_FDS != 0;
+        // 不切换bank时程序从装载地址开始连续存放
+        let program_start = if fds { 0x6000 } else { 0x8000 };
+        let (padding, initial_banks) = match nsf.banks {
+            Some(banks) => {
+                let mut initial = [0; PAGES];
+                initial[2..].copy_from_slice(&banks);
+                // FDS的$6000~$7FFF使用$E000~$FFFF的bank
+                initial[0] = banks[6];
+                initial[1] = banks[7];
+                (nsf.load_address as usize % BANK_SIZE, initial)
+            }
+            None if nsf.load_address >= program_start => {
+                let mut initial = [0; PAGES];
+                let first = (program_start as usize - 0x6000) / BANK_SIZE;
+                for (bank, page) in initial[first..].iter_mut().zip(0..) {
+                    *bank = page;
+                }
+                (nsf.load_address as usize - program_start as usize, initial)
+            }
+            None => {
+                return Err(format!(
+                    "Unsupported load address: {:04X}",
+                    nsf.load_address
+                ))
+            }
+        };
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        let memory = Rc::new(RefCell::new(NsfMemory {
+            data,
+            banks: initial_banks,
+            ram: fds.then(|| vec![0; PAGES * BANK_SIZE]),
+        }));
+        let mut apu = Apu::new();
+        for chip in expansion_chips(nsf.expansion_audio) {
+            apu.add_expansion(chip);
+        }
+        let mut builder = BusBuilder::new()
+            .ram(Box::new(Memory::new(0x0800)))
+            .rom(Box::new(NsfRom {
+                memory: memory.clone(),
+                base: 0x8000,
+            }))
+            .apu(Box::new(apu))
+            .expansion(Box::new(BankSwitch {
+                memory: memory.clone(),
+            }))
+            .region(region);
+        if fds {
+            builder = builder.sram(Box::new(NsfRom {
+                memory: memory.clone(),
+                base: 0x6000,
+            }));
+        }
+        let bus = builder.build()?;
+
+        let speed = match region {
+            Region::Pal => nsf.pal_speed,
+            Region::Ntsc | Region::Dendy => nsf.ntsc_speed,
+        };
+        let speed = match (speed, region) {
+            (0, Region::Pal) => DEFAULT_PAL_SPEED,
+            (0, _) => DEFAULT_NTSC_SPEED,
+            (speed, _) => speed,
+        };
+        let play_period = region.cpu_clock_rate() * speed as f64 / 1_000_000.0;
+
+        let track = nsf.starting_song;
+        let mut player = NsfPlayer {
+            nsf,
+            cpu: CPU::new(Box::new(bus)),
+            region,
+            memory,
+            initial_banks,
+            track,
+            play_period,
+            play_remainder: 0.0,
+        };
+        player.start_track(track);
+        Ok(player)
+    }
+
+    /// 当前曲目(从0开始)
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    /// 每秒调用PLAY的次数
+    pub fn play_rate(&self) -> f64 {
+        self.region.cpu_clock_rate() / self.play_period
+    }
+
+    /// 开始播放曲目(从0开始)
+    pub fn start_track(&mut self, track: u8) {
+        self.track = track.min(self.nsf.songs.saturating_sub(1));
+        let bus = &mut self.cpu.bus;
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+            bus.write(addr, 0);
+        }
+        // FDS的程序在RAM中,清零之后重新装载
+        self.memory.borrow_mut().reset(self.initial_banks);
+        for addr in 0x4000..=0x4013 {
+            bus.write(addr, 0);
+        }
+        bus.write(0x4015, 0x00);
+        bus.write(0x4015, 0x0F);
+        bus.write(0x4017, 0x40);
+
+        self.cpu.register.a = self.track;
+        self.cpu.register.x = (self.region == Region::Pal) as u8;
+        self.cpu.register.sp = 0xFD;
+        self.cpu.register.status.interrupt_disable = true;
+        self.play_remainder = 0.0;
+        // INIT可能需要解压数据,给它足够多的时间
+        let max_cycles = self.region.cpu_clock_rate() as usize;
+        if !self.cpu.call_subroutine(self.nsf.init_address, max_cycles) {
+            eprintln!("NSF INIT did not return");
+        }
+    }
+
+    /// 调用一次PLAY,并空转到下一次调用的时间
+    pub fn run_frame(&mut self) {
+        let period = self.play_period + self.play_remainder;
+        let cycles = period as usize;
+        self.play_remainder = period - cycles as f64;
+
+        let start = self.cpu.cycles;
+        self.cpu.call_subroutine(self.nsf.play_address, cycles);
+        let mut idle = cycles.saturating_sub(self.cpu.cycles - start);
+        while idle > 0 {
+            let step = idle.min(u8::MAX as usize);
+            self.cpu.bus.tick(step as u8);
+            self.cpu.cycles += step;
+            idle -= step;
+        }
+    }
+
+    pub fn bus(&mut self) -> &mut dyn CpuBus {
+        self.cpu.bus.as_mut()
+    }
+
+    /// 当前曲目的播放次数,NSFe提供了长度时包含淡出时间,否则使用default
+    pub fn track_frames(&self, default: u32) -> u32 {
+        let track = self.nsf.track(self.track);
+        match track.length {
+            Some(length) => {
+                let ms = length + track.fade.unwrap_or(0);
+                (ms as f64 / 1000.0 * self.play_rate()).ceil() as u32
+            }
+            None => default,
+        }
+    }
+
+    /// 播放若干次并录制输出
+    pub fn record(&mut self, frames: u32, stems: bool) -> Recording {
+        let mut recording = Recording::start(self.bus(), stems);
+        for _ in 0..frames {
+            self.run_frame();
+            recording.capture(self.bus());
+        }
+        recording
+    }
+}
+
+#[cfg(test)]
+fn test_nsf(program: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; NSF_HEADER_SIZE];
+    raw[..5].copy_from_slice(&NSF_TAG);
+    raw[0x05] = 1;
+    raw[0x06] = 3;
+    raw[0x07] = 2;
+    raw[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+    raw[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+    raw[0x0C..0x0E].copy_from_slice(&0x8010u16.to_le_bytes());
+    raw[0x0E..0x12].copy_from_slice(b"Test");
+    raw[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+    raw.extend_from_slice(program);
+    raw
+}
+
+#[test]
+fn test_parse_nsf() {
+    let nsf = Nsf::new(&test_nsf(&[0x60])).unwrap();
+    assert_eq!(nsf.name, "Test");
+    assert_eq!(nsf.songs, 3);
+    assert_eq!(nsf.starting_song, 1);
+    assert_eq!(nsf.play_address, 0x8010);
+    assert_eq!(nsf.region, Region::Ntsc);
+    assert_eq!(nsf.banks, None);
+    assert_eq!(nsf.data, vec![0x60]);
+    assert_eq!(nsf.track(0), Track::default());
+}
+
+#[test]
+fn test_parse_nsfe() {
+    let mut raw = NSFE_TAG.to_vec();
+    let mut chunk = |id: &[u8], data: &[u8]| {
+        raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        raw.extend_from_slice(id);
+        raw.extend_from_slice(data);
+    };
+    chunk(
+        b"INFO",
+        &[0x00, 0x80, 0x00, 0x80, 0x10, 0x80, 0x01, 0x00, 0x02, 0x00],
+    );
+    chunk(b"DATA", &[0x60]);
+    let mut times = 90_000i32.to_le_bytes().to_vec();
+    times.extend_from_slice(&(-1i32).to_le_bytes());
+    chunk(b"time", &times);
+    chunk(b"tlbl", b"Title\0Ending\0");
+    chunk(b"auth", b"Game\0Composer\0Company\0Ripper\0");
+    chunk(b"xtra", &[1, 2, 3]);
+    chunk(b"NEND", &[]);
+
+    let nsf = Nsf::new(&raw).unwrap();
+    assert_eq!(nsf.region, Region::Pal);
+    assert_eq!(nsf.songs, 2);
+    assert_eq!(nsf.artist, "Composer");
+    assert_eq!(
+        nsf.track(0),
+        Track {
+            name: Some("Title".to_string()),
+            length: Some(90_000),
+            fade: None,
+        }
+    );
+    assert_eq!(nsf.track(1).length, None);
+
+    // 无法识别的必需数据块
+    let mut raw = NSFE_TAG.to_vec();
+    raw.extend_from_slice(&0u32.to_le_bytes());
+    raw.extend_from_slice(b"VRC7");
+    assert_eq!(Nsf::new(&raw).unwrap_err(), "Unsupported NSFe chunk: VRC7");
+}
+
+#[test]
+fn test_player() {
+    #[rustfmt::skip]
+    let program = [
+        // INIT: 把曲目号保存到$00
+        0x85, 0x00,       // STA $00
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x15, 0x40, // STA $4015
+        0x60,             // RTS
+        0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA,
+        // PLAY: 每次调用$01加1
+        0xE6, 0x01,       // INC $01
+        0x60,             // RTS
+    ];
+    let nsf = Nsf::new(&test_nsf(&program)).unwrap();
+    let mut player = NsfPlayer::new(nsf, None).unwrap();
+    assert_eq!(player.track(), 1);
+    assert_eq!(player.bus().read(0x00), 1);
+    let start = player.cpu.cycles;
+    for _ in 0..60 {
+        player.run_frame();
+    }
+    assert_eq!(player.bus().read(0x01), 60);
+    // 16639微秒的周期约为60.1Hz
+    let cycles = (player.cpu.cycles - start) as f64;
+    assert!((cycles - 60.0 * 1_789_773.0 * 0.016639).abs() <= 1.0);
+    assert!((player.play_rate() - 60.1).abs() < 0.01);
+
+    player.start_track(2);
+    assert_eq!(player.bus().read(0x00), 2);
+    assert_eq!(player.bus().read(0x01), 0);
+    assert_eq!(player.track_frames(100), 100);
+
+    let recording = player.record(60, false);
+    assert_eq!(player.bus().read(0x01), 60);
+    assert!((43900..=44100).contains(&recording.mixed.len()));
+}
+
+#[test]
+fn test_bank_switch() {
+    let mut raw = test_nsf(&[]);
+    raw[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 1]);
+    // INIT直接返回
+    raw.push(0x60);
+    raw.extend(vec![0xAA; BANK_SIZE - 1]);
+    raw.extend(vec![0xBB; BANK_SIZE]);
+    let nsf = Nsf::new(&raw).unwrap();
+    let mut player = NsfPlayer::new(nsf, None).unwrap();
+    let bus = player.bus();
+    assert_eq!(bus.read(0x8001), 0xAA);
+    assert_eq!(bus.read(0xF001), 0xBB);
+    bus.write(0x5FFF, 0);
+    assert_eq!(bus.read(0xF001), 0xAA);
+}
+
+#[test]
+fn test_fds_ram() {
+    let mut raw = test_nsf(&[]);
+    raw[0x7B] = EXPANSION_FDS;
+    raw.push(0x60);
+    raw.extend(vec![0xAA; BANK_SIZE - 1]);
+    raw.extend(vec![0xBB; BANK_SIZE]);
+    let nsf = Nsf::new(&raw).unwrap();
+    let mut player = NsfPlayer::new(nsf, None).unwrap();
+    let bus = player.bus();
+    // 程序装载到RAM中,可以写入
+    assert_eq!(bus.read(0x8001), 0xAA);
+    bus.write(0x8001, 0x12);
+    assert_eq!(bus.read(0x8001), 0x12);
+    // $5FF6把bank复制到$6000
+    bus.write(0x5FF6, 2);
+    assert_eq!(bus.read(0x6001), 0xAA);
+    // FDS的寄存器
+    bus.write(0x4080, 0x80 | 20);
+    assert_eq!(bus.read(0x4090), 0x40 | 20);
+
+    // 切换曲目时重新装载程序
+    player.start_track(0);
+    assert_eq!(player.bus().read(0x8001), 0xAA);
+    assert_eq!(player.bus().read(0x6001), 0x00);
+}
@@ -0,0 +1,88 @@
+use std::{any::Any, cell::Cell};
+
+use super::InputDevice;
+use crate::{
+    ppu::{IPpu, SCREEN_WIDTH},
+    snapshot,
+};
+
+/// 旋钮在最左与最右时电位器的读数
+const PADDLE_MIN: u8 = 98;
+const PADDLE_MAX: u8 = 242;
+
+/// 打砖块(Arkanoid)的Vaus控制器,连接在2P端口
+/// strobe时锁存旋钮位置的8位读数,之后每次读取从D3输出一位(高位先出,取反)
+/// D4: 1表示按钮按下
+pub struct ArkanoidPaddle {
+    position: u8,
+    button: bool,
+    strobe: bool,
+    shift: Cell<u8>,
+}
+
+snapshot!(ArkanoidPaddle, position, button, strobe, shift);
+
+impl ArkanoidPaddle {
+    pub fn new() -> Self {
+        ArkanoidPaddle {
+            position: PADDLE_MIN,
+            button: false,
+            strobe: false,
+            shift: Cell::new(0),
+        }
+    }
+
+    /// 按画面的横坐标设置旋钮的位置
+    pub fn aim(&mut self, x: usize) {
+        let x = x.min(SCREEN_WIDTH - 1);
+        let range = (PADDLE_MAX - PADDLE_MIN) as usize;
+        self.position = PADDLE_MIN + (x * range / (SCREEN_WIDTH - 1)) as u8;
+    }
+
+    pub fn set_button(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+}
+
+impl InputDevice for ArkanoidPaddle {
+    fn read(&self, _: Option<&dyn IPpu>) -> u8 {
+        let shift = self.shift.get();
+        if !self.strobe {
+            self.shift.set(shift << 1);
+        }
+        (!shift >> 7) << 3 | (self.button as u8) << 4
+    }
+
+    fn write(&mut self, latch: u8) {
+        self.strobe = latch & 1 == 1;
+        if self.strobe {
+            self.shift.set(self.position);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[test]
+fn test_paddle_serial() {
+    let mut paddle = ArkanoidPaddle::new();
+    paddle.aim(SCREEN_WIDTH - 1);
+    assert_eq!(paddle.position, PADDLE_MAX);
+    paddle.aim(0);
+    assert_eq!(paddle.position, PADDLE_MIN);
+
+    paddle.position = 0b1010_0011;
+    paddle.set_button(true);
+    paddle.write(1);
+    paddle.write(0);
+    let value = (0..8).fold(0, |value, _| {
+        let data = paddle.read(None);
+        assert_eq!(data & 0b1_0000, 0b1_0000);
+        value << 1 | (!data >> 3 & 1)
+    });
+    assert_eq!(value, 0b1010_0011);
+    // 移出全部位后读到的是取反的0
+    assert_eq!(paddle.read(None) & 0b1000, 0b1000);
+}
@@ -0,0 +1,103 @@
+use std::any::Any;
+
+use super::InputDevice;
+use crate::{ppu::IPpu, snapshot};
+
+/// 键盘矩阵的大小: 9行, 每行2列, 每列4个键
+pub const FAMILY_KEYBOARD_KEYS: usize = 72;
+const ROWS: u8 = 9;
+
+/// Family BASIC键盘,连接在Famicom的扩展端口
+/// 写$4016: bit2 开启键盘, bit1 选择列(从1变为0时切换到下一行), bit0 回到第0行
+/// 读$4017: D1~D4为当前行列的4个键(0表示按下)
+///
+/// 键的序号为 行*8 + 列*4 + 位(0~3对应D1~D4)
+pub struct FamilyKeyboard {
+    keys: [bool; FAMILY_KEYBOARD_KEYS],
+    enabled: bool,
+    row: u8,
+    column: u8,
+}
+
+snapshot!(FamilyKeyboard, keys, enabled, row, column);
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard {
+            keys: [false; FAMILY_KEYBOARD_KEYS],
+            enabled: false,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        if let Some(state) = self.keys.get_mut(key) {
+            *state = pressed;
+        }
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    fn read(&self, _: Option<&dyn IPpu>) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // 扫描完9行后没有按下的键
+        if self.row >= ROWS {
+            return 0b1_1110;
+        }
+        let start = (self.row * 8 + self.column * 4) as usize;
+        let pressed = self.keys[start..start + 4]
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, pressed)| bits | (*pressed as u8) << i);
+        !pressed << 1 & 0b1_1110
+    }
+
+    fn write(&mut self, latch: u8) {
+        let column = latch >> 1 & 1;
+        self.enabled = latch & 0b100 != 0;
+        if !self.enabled {
+            self.column = column;
+            return;
+        }
+        if self.column == 1 && column == 0 {
+            self.row = (self.row + 1) % (ROWS + 1);
+        }
+        self.column = column;
+        if latch & 1 != 0 {
+            self.row = 0;
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[test]
+fn test_keyboard_scan() {
+    let mut keyboard = FamilyKeyboard::new();
+    // 第0行第0列的D2, 第3行第1列的D4
+    keyboard.set_key(1, true);
+    keyboard.set_key(3 * 8 + 4 + 3, true);
+    assert_eq!(keyboard.read(None), 0);
+
+    // 与Family BASIC相同的扫描方式: 复位后每行先读第0列再读第1列
+    keyboard.write(0b101);
+    let mut scan = Vec::new();
+    for _ in 0..ROWS {
+        keyboard.write(0b100);
+        scan.push(keyboard.read(None));
+        keyboard.write(0b110);
+        scan.push(keyboard.read(None));
+    }
+    let mut expected = vec![0b1_1110; 18];
+    expected[0] = 0b1_1010;
+    expected[3 * 2 + 1] = 0b0_1110;
+    assert_eq!(scan, expected);
+    // 第9行之后没有键
+    keyboard.write(0b100);
+    assert_eq!(keyboard.read(None), 0b1_1110);
+}
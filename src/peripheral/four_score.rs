@@ -0,0 +1,144 @@
+use std::{any::Any, cell::Cell};
+
+use super::{InputDevice, Joypad};
+use crate::{ppu::IPpu, snapshot};
+
+/// 四人适配器的类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FourScoreMode {
+    /// NES Four Score: 每个端口的D0依次输出两个手柄与标识共24位
+    Nes,
+    /// Famicom扩展端口的四人适配器: 3P与4P分别从$4016与$4017的D1输出
+    Famicom,
+}
+
+/// 四人适配器连接在一个端口上的一半,1P端口连接1P与3P手柄,2P端口连接2P与4P手柄
+///
+/// NES模式下的读取顺序为
+/// 前8次: 1P(2P)的按钮
+/// 中8次: 3P(4P)的按钮
+/// 后8次: 标识,$4016为0b0001_0000,$4017为0b0010_0000(低位先出)
+/// 之后一直返回1
+pub struct FourScore {
+    mode: FourScoreMode,
+    joypads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    /// NES模式下已经输出的位数
+    index: Cell<u8>,
+}
+
+snapshot!(FourScore, joypads, strobe, index);
+
+impl FourScore {
+    /// 连接在1P端口上的一半
+    pub fn port1(mode: FourScoreMode) -> Self {
+        Self::new(mode, 0b0001_0000)
+    }
+
+    /// 连接在2P端口上的一半
+    pub fn port2(mode: FourScoreMode) -> Self {
+        Self::new(mode, 0b0010_0000)
+    }
+
+    fn new(mode: FourScoreMode, signature: u8) -> Self {
+        FourScore {
+            mode,
+            joypads: [Joypad::new(), Joypad::new()],
+            signature,
+            strobe: false,
+            index: Cell::new(0),
+        }
+    }
+
+    /// 0为本端口的手柄,1为3P或4P的手柄
+    pub fn joypad(&mut self, index: usize) -> Option<&mut Joypad> {
+        self.joypads.get_mut(index)
+    }
+
+    fn read_serial(&self) -> u8 {
+        let index = self.index.get();
+        let first: u8 = self.joypads[0].buttons().into();
+        let second: u8 = self.joypads[1].buttons().into();
+        let bits = first as u32 | (second as u32) << 8 | (self.signature as u32) << 16;
+        if index >= 24 {
+            return 1;
+        }
+        if !self.strobe {
+            self.index.set(index + 1);
+        }
+        (bits >> index) as u8 & 1
+    }
+}
+
+impl InputDevice for FourScore {
+    fn read(&self, ppu: Option<&dyn IPpu>) -> u8 {
+        match self.mode {
+            FourScoreMode::Nes => self.read_serial(),
+            FourScoreMode::Famicom => {
+                self.joypads[0].read(ppu) & 1 | (self.joypads[1].read(ppu) & 1) << 1
+            }
+        }
+    }
+
+    fn write(&mut self, latch: u8) {
+        self.strobe = latch & 1 == 1;
+        if self.strobe {
+            self.index.set(0);
+        }
+        for joypad in self.joypads.iter_mut() {
+            joypad.write(latch);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+fn read_bits(device: &dyn InputDevice, count: usize) -> Vec<u8> {
+    (0..count).map(|_| device.read(None)).collect()
+}
+
+#[test]
+fn test_nes_four_score() {
+    use super::Button;
+    let mut port1 = FourScore::port1(FourScoreMode::Nes);
+    let mut port2 = FourScore::port2(FourScoreMode::Nes);
+    port1.joypad(0).unwrap().set_button(Button::A, true);
+    port1.joypad(1).unwrap().set_button(Button::Start, true);
+    port2.joypad(1).unwrap().set_button(Button::Right, true);
+    for port in [&mut port1, &mut port2] {
+        port.write(1);
+        port.write(0);
+    }
+
+    let mut expected = vec![0; 26];
+    expected[0] = 1;
+    expected[8 + 3] = 1;
+    expected[16 + 4] = 1;
+    expected[24] = 1;
+    expected[25] = 1;
+    assert_eq!(read_bits(&port1, 26), expected);
+
+    let mut expected = vec![0; 26];
+    expected[8 + 7] = 1;
+    expected[16 + 5] = 1;
+    expected[24] = 1;
+    expected[25] = 1;
+    assert_eq!(read_bits(&port2, 26), expected);
+}
+
+#[test]
+fn test_famicom_four_player() {
+    use super::Button;
+    let mut port1 = FourScore::port1(FourScoreMode::Famicom);
+    port1.joypad(0).unwrap().set_button(Button::B, true);
+    port1.joypad(1).unwrap().set_button(Button::B, true);
+    port1.joypad(1).unwrap().set_button(Button::Select, true);
+    port1.write(1);
+    port1.write(0);
+    // 1P在D0, 3P在D1
+    assert_eq!(read_bits(&port1, 4), [0b00, 0b11, 0b10, 0b00]);
+}
@@ -0,0 +1,203 @@
+use std::{any::Any, cell::RefCell};
+
+use super::InputDevice;
+use crate::{flag_reg, ppu::IPpu, snapshot};
+
+flag_reg!(
+    JoypadButton,
+    button_a,
+    button_b,
+    select,
+    start,
+    up,
+    down,
+    left,
+    right
+);
+
+/// 手柄上的按钮
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// 按上报顺序排列
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Button, String> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name() == name.to_ascii_lowercase())
+            .ok_or(format!("Unknown button: {}", name))
+    }
+}
+
+/// 手柄连接的端口,3P与4P通过四人适配器连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Port {
+    /// $4016
+    P1,
+    /// $4017
+    P2,
+    /// 与1P共用$4016
+    P3,
+    /// 与2P共用$4017
+    P4,
+}
+
+impl Port {
+    pub const ALL: [Port; 4] = [Port::P1, Port::P2, Port::P3, Port::P4];
+
+    pub fn from_name(name: &str) -> Result<Port, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "p1" | "1" => Ok(Port::P1),
+            "p2" | "2" => Ok(Port::P2),
+            "p3" | "3" => Ok(Port::P3),
+            "p4" | "4" => Ok(Port::P4),
+            _ => Err(format!("Unknown port: {}", name)),
+        }
+    }
+
+    /// 在Port::ALL中的序号
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// 游戏机有两个手柄,分别映射到0x4016与0x4017两个cpu地址空间
+/// 同一个寄存器是可读可写的,通过读取按钮状态(1按下, 0释放),
+/// 来上传按钮状态,为了获取所有按钮状态,cpu必须读取控制寄存器8次
+/// Button的上报顺序如下
+/// A -> B -> Select -> Start -> Up -> Down -> Left -> Right
+/// 在上报完right的 状态后,控制器将返回连续的1用于后续的读取,直到strobe发生变化
+/// CPU可通过向寄存器写入一个字节来改变控制器模式,只有第一位重要
+
+/// 手柄控制器有两种模式运行
+/// + strobe on   : 此时指针重置到按钮 A
+/// + strobe off  : 循环上报按钮状态
+
+pub struct Joypad {
+    button: JoypadButton,
+    strobe: bool,
+    button_pointer: RefCell<u8>,
+}
+
+snapshot!(Joypad, button, strobe, button_pointer);
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            button: JoypadButton::from(0),
+            strobe: false,
+            button_pointer: RefCell::new(0),
+        }
+    }
+
+    /// 当前的按钮状态
+    pub fn buttons(&self) -> JoypadButton {
+        self.button
+    }
+
+    /// 按下或释放一个按钮
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let flag = match button {
+            Button::A => &mut self.button.button_a,
+            Button::B => &mut self.button.button_b,
+            Button::Select => &mut self.button.select,
+            Button::Start => &mut self.button.start,
+            Button::Up => &mut self.button.up,
+            Button::Down => &mut self.button.down,
+            Button::Left => &mut self.button.left,
+            Button::Right => &mut self.button.right,
+        };
+        *flag = pressed;
+    }
+
+    /// 一次设置全部按钮
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.button = buttons;
+    }
+}
+
+impl InputDevice for Joypad {
+    /// 按钮状态从D0串行输出
+    fn read(&self, _: Option<&dyn IPpu>) -> u8 {
+        // 在上报完right的状态后,控制器将返回连续的1用于后续的读取
+        let mut btn_ptr_ref = self.button_pointer.borrow_mut();
+        let btn_ptr = btn_ptr_ref.clone();
+        if btn_ptr > 7 {
+            return 1;
+        }
+        let button_bits: u8 = self.button.into();
+        let response = button_bits >> btn_ptr & 1;
+        if !self.strobe && btn_ptr <= 7 {
+            *btn_ptr_ref = btn_ptr + 1;
+        }
+        response
+    }
+
+    // CPU可通过向寄存器写入一个字节来改变控制器模式,只有第一位(OUT0)重要
+    fn write(&mut self, latch: u8) {
+        self.strobe = latch & 1 == 1;
+        if self.strobe {
+            let mut btn_ptr_ref = self.button_pointer.borrow_mut();
+            *btn_ptr_ref = 0;
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[test]
+fn test_read_buttons() {
+    let mut joypad = Joypad::new();
+    joypad.set_button(Button::A, true);
+    joypad.set_button(Button::Start, true);
+    joypad.set_button(Button::Left, true);
+    joypad.set_button(Button::Left, false);
+    joypad.set_button(Button::Right, true);
+    // strobe开启时一直返回A的状态
+    joypad.write(1);
+    assert_eq!(joypad.read(None), 1);
+    assert_eq!(joypad.read(None), 1);
+    joypad.write(0);
+    let bits: Vec<u8> = (0..10).map(|_| joypad.read(None)).collect();
+    assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    // 重新strobe后从A开始
+    joypad.write(1);
+    joypad.write(0);
+    assert_eq!(joypad.read(None), 1);
+    assert_eq!(joypad.read(None), 0);
+}
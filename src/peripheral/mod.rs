@@ -0,0 +1,32 @@
+use std::any::Any;
+
+use crate::{ppu::IPpu, state::Snapshot};
+
+mod arkanoid;
+mod family_keyboard;
+mod four_score;
+mod joypad;
+mod power_pad;
+mod zapper;
+
+pub use arkanoid::ArkanoidPaddle;
+pub use family_keyboard::{FamilyKeyboard, FAMILY_KEYBOARD_KEYS};
+pub use four_score::{FourScore, FourScoreMode};
+pub use joypad::{Button, Joypad, JoypadButton, Port};
+pub use power_pad::{PowerPad, POWER_PAD_BUTTONS};
+pub use zapper::Zapper;
+
+// 控制器端口
+// $4016 读: 1P端口的D0~D4, 写: OUT0~OUT2锁存(两个端口都接收OUT0)
+// $4017 读: 2P端口的D0~D4
+// D5~D7没有连接,读到的是总线上残留的值(开放总线)
+
+/// 连接在$4016/$4017上的输入设备,存档保存其按键与移位寄存器的状态
+pub trait InputDevice: Snapshot {
+    /// 读取端口的D0~D4,ppu用于光枪等需要知道电子束位置的设备,播放NSF时为None
+    fn read(&self, ppu: Option<&dyn IPpu>) -> u8;
+    /// 写入$4016时的OUT0~OUT2(低3位)
+    fn write(&mut self, latch: u8);
+    /// 用于前端取得具体的设备以更新输入状态
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
@@ -0,0 +1,92 @@
+use std::{any::Any, cell::Cell};
+
+use super::InputDevice;
+use crate::{ppu::IPpu, snapshot};
+
+/// Power Pad的按钮数
+pub const POWER_PAD_BUTTONS: usize = 12;
+/// D3依次输出的按钮(从1开始)
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+/// D4依次输出的按钮,之后一直为1
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+/// Power Pad跳舞毯,连接在2P端口,按钮排列为
+///  1  2  3  4
+///  5  6  7  8
+///  9 10 11 12
+/// strobe时锁存全部按钮,之后每次读取从D3与D4各输出一位(1表示按下)
+pub struct PowerPad {
+    buttons: [bool; POWER_PAD_BUTTONS],
+    strobe: bool,
+    /// D3与D4的移位寄存器
+    shift: Cell<(u8, u8)>,
+}
+
+snapshot!(PowerPad, buttons, strobe, shift);
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad {
+            buttons: [false; POWER_PAD_BUTTONS],
+            strobe: false,
+            shift: Cell::new((0, 0)),
+        }
+    }
+
+    /// button从1开始
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        if let Some(state) = self.buttons.get_mut(button.wrapping_sub(1)) {
+            *state = pressed;
+        }
+    }
+
+    fn latch(&self) {
+        let bits = |order: &[usize]| {
+            order.iter().enumerate().fold(0, |bits, (i, button)| {
+                bits | (self.buttons[button - 1] as u8) << i
+            })
+        };
+        self.shift.set((bits(&D3_ORDER), bits(&D4_ORDER) | 0xF0));
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn read(&self, _: Option<&dyn IPpu>) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+        let (d3, d4) = self.shift.get();
+        if !self.strobe {
+            self.shift.set((d3 >> 1 | 0x80, d4 >> 1 | 0x80));
+        }
+        (d3 & 1) << 3 | (d4 & 1) << 4
+    }
+
+    fn write(&mut self, latch: u8) {
+        self.strobe = latch & 1 == 1;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[test]
+fn test_power_pad_serial() {
+    let mut pad = PowerPad::new();
+    for button in [1, 3, 10] {
+        pad.set_button(button, true);
+    }
+    pad.write(1);
+    pad.write(0);
+    let reads: Vec<u8> = (0..10).map(|_| pad.read(None)).collect();
+    let d3: Vec<u8> = reads.iter().map(|data| data >> 3 & 1).collect();
+    let d4: Vec<u8> = reads.iter().map(|data| data >> 4 & 1).collect();
+    // D3: 2, 1, 5, 9, 6, 10, 11, 7
+    assert_eq!(d3, [0, 1, 0, 0, 0, 1, 0, 0, 1, 1]);
+    // D4: 4, 3, 12, 8
+    assert_eq!(d4, [0, 1, 0, 0, 1, 1, 1, 1, 1, 1]);
+}
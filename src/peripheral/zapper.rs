@@ -0,0 +1,134 @@
+use std::any::Any;
+
+use super::InputDevice;
+use crate::{
+    ppu::{IPpu, Palette, SCREEN_HEIGHT, SCREEN_WIDTH},
+    snapshot,
+};
+
+/// 检测瞄准点周围的像素范围
+const SENSE_RADIUS: usize = 2;
+/// 电子束经过后光电管保持感光的扫描线数
+const LIGHT_SCANLINES: usize = 20;
+/// 亮度(0~255)达到该值时视为感光
+const LIGHT_THRESHOLD: u32 = 0x80;
+
+/// 光枪,通常连接在2P端口
+/// D3: 0表示光电管检测到亮光
+/// D4: 1表示扳机扣下
+///
+/// 光电管只在电子束经过瞄准点后的一小段时间内感光,
+/// 游戏在扣下扳机后把目标画成白色,再在绘制过程中轮询D3来判断是否命中
+pub struct Zapper {
+    /// 瞄准的画面坐标,移出画面时为None
+    aim: Option<(usize, usize)>,
+    trigger: bool,
+    palette: Palette,
+}
+
+snapshot!(Zapper, aim, trigger);
+
+impl Zapper {
+    /// palette为画面使用的调色板,按显示的颜色判断亮度
+    pub fn new(palette: Palette) -> Self {
+        Zapper {
+            aim: None,
+            trigger: false,
+            palette,
+        }
+    }
+
+    pub fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim.filter(|(x, y)| *x < SCREEN_WIDTH && *y < SCREEN_HEIGHT);
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// 电子束位于(scanline, dot)时光电管是否感光
+    fn senses_light(&self, frame: &[u16], scanline: u16, dot: usize) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        let scanline = scanline as usize;
+        let rows = y.saturating_sub(SENSE_RADIUS)..=(y + SENSE_RADIUS).min(SCREEN_HEIGHT - 1);
+        let columns = x.saturating_sub(SENSE_RADIUS)..=(x + SENSE_RADIUS).min(SCREEN_WIDTH - 1);
+        rows.filter(|row| scanline >= *row && scanline - row < LIGHT_SCANLINES)
+            .any(|row| {
+                columns
+                    .clone()
+                    // 第x个像素在第x+1个点输出
+                    .filter(|column| scanline > row || dot > *column)
+                    .any(|column| {
+                        self.brightness(frame[row * SCREEN_WIDTH + column]) >= LIGHT_THRESHOLD
+                    })
+            })
+    }
+
+    fn brightness(&self, pixel: u16) -> u32 {
+        let (r, g, b) = self.palette.rgb(pixel);
+        (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000
+    }
+}
+
+impl InputDevice for Zapper {
+    fn read(&self, ppu: Option<&dyn IPpu>) -> u8 {
+        let light = ppu.is_some_and(|ppu| {
+            let (scanline, dot) = ppu.position();
+            self.senses_light(ppu.frame(), scanline, dot)
+        });
+        (!light as u8) << 3 | (self.trigger as u8) << 4
+    }
+
+    fn write(&mut self, _: u8) {}
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[test]
+fn test_light_sense() {
+    const WHITE: u16 = 0x30;
+    const BLACK: u16 = 0x0F;
+    let mut frame = vec![BLACK; SCREEN_WIDTH * SCREEN_HEIGHT];
+    // (100, 50)处有一个白色方块
+    for y in 48..56 {
+        for x in 96..104 {
+            frame[y * SCREEN_WIDTH + x] = WHITE;
+        }
+    }
+    let mut zapper = Zapper::new(Palette::default());
+    assert!(!zapper.senses_light(&frame, 60, 0));
+
+    zapper.set_aim(Some((100, 50)));
+    // 电子束还没有到达方块
+    assert!(!zapper.senses_light(&frame, 40, 0));
+    assert!(!zapper.senses_light(&frame, 48, 90));
+    assert!(zapper.senses_light(&frame, 48, 100));
+    assert!(zapper.senses_light(&frame, 60, 0));
+    // 感光只持续一段时间
+    assert!(!zapper.senses_light(&frame, 80, 0));
+
+    zapper.set_aim(Some((20, 50)));
+    assert!(!zapper.senses_light(&frame, 60, 0));
+    zapper.set_aim(Some((300, 50)));
+    assert!(zapper.aim.is_none());
+
+    // 使用自定义调色板时按显示的颜色判断,白色被换成黑色后不再感光
+    let dark = Palette::from_pal(&[0; 64 * 3]).unwrap();
+    let mut zapper = Zapper::new(dark);
+    zapper.set_aim(Some((100, 50)));
+    assert!(!zapper.senses_light(&frame, 60, 0));
+}
+
+#[test]
+fn test_zapper_read() {
+    let mut zapper = Zapper::new(Palette::default());
+    // 没有PPU时不会感光
+    assert_eq!(zapper.read(None), 0b0_1000);
+    zapper.set_trigger(true);
+    assert_eq!(zapper.read(None), 0b1_1000);
+}
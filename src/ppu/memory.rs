@@ -0,0 +1,166 @@
+use super::Ppu;
+use crate::meta::Mirror;
+
+// PPU的地址空间(14位)
+//  _______________ $4000  _______________
+// | Mirrors       |       |               |
+// | $3F00-$3F1F   |       |               |
+// |_ _ _ _ _ _ _ _| $3F20 | Palettes      |
+// | Palettes      |       |               |
+// |_______________| $3F00 |_______________|
+// | Mirrors       |       |               |
+// | $2000-$2EFF   |       |               |
+// |_ _ _ _ _ _ _ _| $3000 | Name Tables   |
+// | Name Tables   |       | (VRAM)        |
+// |_______________| $2000 |_______________|
+// | Pattern Table |       |               |
+// | (CHR ROM/RAM) |       | Pattern Tables|
+// |_______________| $0000 |_______________|
+
+/// CHR RAM的大小
+pub const CHR_RAM_SIZE: usize = 0x2000;
+
+impl Ppu {
+    /// 读取PPU地址空间
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => self.read_chr(addr),
+            0x2000..=0x3EFF => self.read_nametable(addr),
+            0x3F00..=0x3FFF => self.read_palette(addr as u8),
+            _ => unreachable!(),
+        }
+    }
+
+    /// 写入PPU地址空间
+    pub fn write_memory(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_ram {
+                    self.chr_rom[addr as usize] = data;
+                } else {
+                    println!("attempt to write to chr rom space {}", addr);
+                }
+            }
+            0x2000..=0x3EFF => self.write_nametable(addr, data),
+            0x3F00..=0x3FFF => {
+                self.palette_table[palette_index(addr as u8)] = data & 0x3F;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// 读取图案表数据
+    pub(super) fn read_chr(&self, addr: u16) -> u8 {
+        self.chr_rom.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// 读取名称表,$3000~$3EFF是$2000~$2EFF的镜像
+    pub(super) fn read_nametable(&self, addr: u16) -> u8 {
+        let index = self.mirror_vram_addr(addr) as usize;
+        if index < self.vram.len() {
+            self.vram[index]
+        } else {
+            self.cartridge_vram[index - self.vram.len()]
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, data: u8) {
+        let index = self.mirror_vram_addr(addr) as usize;
+        if index < self.vram.len() {
+            self.vram[index] = data;
+        } else {
+            self.cartridge_vram[index - self.vram.len()] = data;
+        }
+    }
+
+    /// 读取调色板
+    pub(super) fn read_palette(&self, index: u8) -> u8 {
+        self.palette_table[palette_index(index)] & 0x3F
+    }
+}
+
+/// 调色板每32字节镜像一次,$3F10/$3F14/$3F18/$3F1C是$3F00/$3F04/$3F08/$3F0C的镜像
+fn palette_index(index: u8) -> usize {
+    let mut index = index & 0x1F;
+    if index & 0x13 == 0x10 {
+        index &= 0x0F;
+    }
+    index as usize
+}
+
+/// 四屏模式下卡带额外提供的2K显存
+pub fn cartridge_vram(mirror: Mirror) -> Vec<u8> {
+    match mirror {
+        Mirror::FourScreen => vec![0; 0x800],
+        _ => Vec::new(),
+    }
+}
+
+#[test]
+fn test_nametable_mirror() {
+    let mut ppu = Ppu::new(vec![0; 0x2000], Mirror::Horizontal);
+    ppu.write_memory(0x2005, 0x12);
+    assert_eq!(ppu.read_memory(0x3005), 0x12);
+    assert_eq!(ppu.read_memory(0x2405), 0x12);
+    ppu.write_memory(0x3EFF, 0x34);
+    assert_eq!(ppu.read_memory(0x2EFF), 0x34);
+    assert_eq!(ppu.read_memory(0x2AFF), 0x34);
+}
+
+#[test]
+fn test_palette_mirror() {
+    let mut ppu = Ppu::new_empty();
+    ppu.write_memory(0x3F10, 0x2A);
+    assert_eq!(ppu.read_memory(0x3F00), 0x2A);
+    assert_eq!(ppu.read_memory(0x3F20), 0x2A);
+    assert_eq!(ppu.read_memory(0x3FF0), 0x2A);
+    ppu.write_memory(0x3F3D, 0xFF);
+    assert_eq!(ppu.read_memory(0x3F1D), 0x3F);
+    // 超出14位的地址回绕到$0000~$3FFF
+    assert_eq!(ppu.read_memory(0x7F1D), 0x3F);
+}
+
+#[test]
+fn test_four_screen() {
+    let mut ppu = Ppu::new(vec![0; 0x2000], Mirror::FourScreen);
+    for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+        ppu.write_memory(*addr, i as u8 + 1);
+    }
+    for (i, addr) in [0x3000, 0x3400, 0x3800, 0x3C00].iter().enumerate() {
+        assert_eq!(ppu.read_memory(*addr), i as u8 + 1);
+    }
+}
+
+#[test]
+fn test_chr_ram() {
+    let mut ppu = Ppu::new(Vec::new(), Mirror::Vertical);
+    ppu.write_memory(0x1234, 0x56);
+    assert_eq!(ppu.read_memory(0x1234), 0x56);
+
+    let mut ppu = Ppu::new(vec![0; 0x2000], Mirror::Vertical);
+    ppu.write_memory(0x1234, 0x56);
+    assert_eq!(ppu.read_memory(0x1234), 0);
+}
+
+#[test]
+fn test_buffered_read() {
+    use super::IPpu;
+    let mut ppu = Ppu::new(vec![0; 0x2000], Mirror::Vertical);
+    ppu.write_memory(0x2F05, 0x66);
+    ppu.write_memory(0x3F05, 0x12);
+
+    ppu.write_to_ppu_addr(0x2F);
+    ppu.write_to_ppu_addr(0x05);
+    ppu.read_data();
+    assert_eq!(ppu.read_data(), 0x66);
+
+    // 调色板直接返回,读缓冲被填入$2F05的数据
+    ppu.write_to_ppu_addr(0x3F);
+    ppu.write_to_ppu_addr(0x05);
+    assert_eq!(ppu.read_data(), 0x12);
+    ppu.write_to_ppu_addr(0x00);
+    ppu.write_to_ppu_addr(0x00);
+    assert_eq!(ppu.read_data(), 0x66);
+}
@@ -1,18 +1,35 @@
 use std::cell::RefCell;
 
-use crate::{addressable::*, flag::FlagRegister, meta::Mirror};
+use crate::{
+    addressable::*,
+    flag::FlagRegister,
+    meta::{Mirror, Region},
+    state::{Snapshot, StateReader, StateWriter},
+};
 
 use self::register::PpuRegister;
+use self::{render::BackgroundPipeline, sprite::SpriteSlot};
+mod memory;
+mod palette;
 mod register;
+mod render;
+mod sprite;
+
+pub use palette::Palette;
+pub use render::{SCREEN_HEIGHT, SCREEN_WIDTH};
 // NES的分辨率为256x240
 
 pub struct Ppu {
     /// 卡带上的数据
     pub chr_rom: Vec<u8>,
+    /// 卡带没有CHR ROM时使用可写的CHR RAM
+    chr_ram: bool,
     /// 调色板
     pub palette_table: [u8; 32],
     /// 背景信息
     pub vram: [u8; 2048],
+    /// 四屏模式下由卡带提供的额外显存
+    cartridge_vram: Vec<u8>,
     /// 精灵数据
     pub oam_address: u8,
     pub oam_data: [u8; 256],
@@ -25,8 +42,24 @@ pub struct Ppu {
 
     internal_data_buffer: RefCell<u8>,
 
+    /// 电视制式
+    region: Region,
+    /// 当前扫描线(NTSC: 0~239可见, 240后渲染, 241~260垂直消隐, 261预渲染)
     scanline: u16,
+    /// 当前扫描线上的点(0~340)
     cycles: usize,
+    /// 奇数帧在渲染开启时跳过预渲染扫描线的最后一个点
+    odd_frame: bool,
+    /// 上电以来经过的点数
+    clock: u64,
+
+    background: BackgroundPipeline,
+    /// 下一条扫描线的精灵(OAM序号)
+    secondary_oam: Vec<u8>,
+    /// 当前扫描线正在输出的精灵
+    sprite_slots: Vec<SpriteSlot>,
+    /// 帧缓冲,每个像素为颜色值(低6位)与色彩强调位(高3位),由Palette转换为RGB
+    pub frame: Vec<u16>,
 }
 
 impl Ppu {
@@ -34,10 +67,18 @@ impl Ppu {
         Self::new(vec![0; 2048], Mirror::Horizontal)
     }
     pub fn new(chr_rom: Vec<u8>, mirror: Mirror) -> Self {
+        let chr_ram = chr_rom.is_empty();
+        let chr_rom = if chr_ram {
+            vec![0; memory::CHR_RAM_SIZE]
+        } else {
+            chr_rom
+        };
         Self {
             chr_rom,
+            chr_ram,
             palette_table: [0; 32],
             vram: [0; 2048],
+            cartridge_vram: memory::cartridge_vram(mirror),
             oam_data: [0; 64 * 4],
             mirror,
             register: RefCell::new(PpuRegister::new()),
@@ -45,6 +86,13 @@ impl Ppu {
             internal_data_buffer: RefCell::new(0),
             cycles: 0,
             scanline: 0,
+            region: Region::default(),
+            odd_frame: false,
+            clock: 0,
+            background: BackgroundPipeline::default(),
+            secondary_oam: Vec::with_capacity(8),
+            sprite_slots: Vec::with_capacity(8),
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
             nmi_interrupt: None,
         }
     }
@@ -71,47 +119,114 @@ impl Ppu {
         }
     }
 
-    fn increment_vram_addr(&self) {
-        let reg_ref = self.register.borrow();
-        let inc = reg_ref.control.vram_address_increment();
-        self.register.borrow_mut().address.increment(inc);
+    /// 渲染(背景或精灵)是否开启
+    fn rendering_enabled(&self) -> bool {
+        let reg = self.register.borrow();
+        reg.mask.show_background || reg.mask.show_sprite
+    }
+
+    /// 渲染开启且处于可见扫描线或预渲染扫描线时,PPU正在使用v进行取址
+    fn is_rendering(&self) -> bool {
+        self.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == self.region.pre_render_line())
     }
 
-    pub fn tick(&mut self, cycles: u8) -> bool {
+    fn increment_vram_addr(&self) {
+        let rendering = self.is_rendering();
         let mut reg_ref = self.register.borrow_mut();
-        self.cycles += cycles as usize;
-        if self.cycles >= 341 {
-            self.cycles = self.cycles - 341;
-            self.scanline += 1;
+        if rendering {
+            // 渲染期间访问$2007会同时触发水平与垂直递增
+            reg_ref.loopy.increment_coarse_x();
+            reg_ref.loopy.increment_y();
+        } else {
+            let inc = reg_ref.control.vram_address_increment();
+            reg_ref.loopy.increment(inc);
+        }
+    }
 
-            if self.scanline == 241 {
-                reg_ref.status.vblank_started = true;
-                reg_ref.status.sprite_zero_hit = false;
+    /// 推进一个点,返回值为true表示一帧结束
+    fn step(&mut self) -> bool {
+        let rendering_enabled = self.rendering_enabled();
+        let pre_render_line = self.region.pre_render_line();
+        if self.scanline < 240 || self.scanline == pre_render_line {
+            if rendering_enabled {
+                self.render_dot();
+            } else if self.scanline < 240 && (1..=256).contains(&self.cycles) {
+                self.render_backdrop();
+            }
+        }
 
-                if reg_ref.control.generate_vblank_nmi() {
-                    self.nmi_interrupt = Some(1);
-                }
+        if self.scanline == self.region.vblank_line() && self.cycles == 1 {
+            let mut reg_ref = self.register.borrow_mut();
+            reg_ref.status.vblank_started = true;
+            if reg_ref.control.generate_vblank_nmi() {
+                self.nmi_interrupt = Some(1);
             }
+        }
 
-            if self.scanline >= 262 {
+        // 预渲染扫描线的第1个点清除状态标志
+        if self.scanline == pre_render_line && self.cycles == 1 {
+            let mut reg_ref = self.register.borrow_mut();
+            reg_ref.status.reset_vblank_status();
+            reg_ref.status.sprite_zero_hit = false;
+            reg_ref.status.sprite_overflow = false;
+            self.nmi_interrupt = None;
+        }
+
+        self.clock += 1;
+        self.cycles += 1;
+        // NTSC的奇数帧且渲染开启时跳过预渲染扫描线的第340个点
+        if self.scanline == pre_render_line
+            && self.cycles == 340
+            && self.odd_frame
+            && rendering_enabled
+            && self.region.skips_odd_frame_dot()
+        {
+            self.cycles = 341;
+        }
+        if self.cycles >= 341 {
+            self.cycles = 0;
+            self.scanline += 1;
+            if self.scanline > pre_render_line {
                 self.scanline = 0;
-                self.nmi_interrupt = None;
-                // self.status.
-                reg_ref.status.sprite_zero_hit = false;
-                reg_ref.status.reset_vblank_status();
+                self.odd_frame = !self.odd_frame;
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    /// 轮询中断
-    fn poll_nmi_interrupt(&mut self) -> Option<u8> {
-        self.nmi_interrupt.take()
+    /// 渲染期间v寄存器的自动递增与复制
+    fn update_vram_address(&mut self) {
+        let dot = self.cycles;
+        let mut reg_ref = self.register.borrow_mut();
+        let loopy = &mut reg_ref.loopy;
+        if ((1..=256).contains(&dot) || (321..=336).contains(&dot)) && dot.is_multiple_of(8) {
+            loopy.increment_coarse_x();
+        }
+        if dot == 256 {
+            loopy.increment_y();
+        }
+        if dot == 257 {
+            loopy.copy_horizontal();
+        }
+        if self.scanline == self.region.pre_render_line() && (280..=304).contains(&dot) {
+            loopy.copy_vertical();
+        }
     }
 }
 
-pub trait IPpu {
+pub trait IPpu: Addressable {
+    /// 推进若干个PPU周期(点),返回值为true表示一帧结束
+    fn tick(&mut self, cycles: u8) -> bool;
+    /// 轮询NMI中断
+    fn poll_nmi_interrupt(&mut self) -> Option<u8>;
+    /// 帧缓冲
+    fn frame(&self) -> &[u16];
+    /// 设置电视制式
+    fn set_region(&mut self, region: Region);
+    /// 电子束当前的位置(扫描线, 点),用于光枪
+    fn position(&self) -> (u16, usize);
     fn write_to_ctrl(&mut self, value: u8);
     fn write_to_mask(&mut self, value: u8);
     fn read_status(&self) -> u8;
@@ -126,11 +241,36 @@ pub trait IPpu {
 }
 
 impl IPpu for Ppu {
+    fn tick(&mut self, cycles: u8) -> bool {
+        let mut frame_finished = false;
+        for _ in 0..cycles {
+            frame_finished |= self.step();
+        }
+        frame_finished
+    }
+
+    fn poll_nmi_interrupt(&mut self) -> Option<u8> {
+        self.nmi_interrupt.take()
+    }
+
+    fn frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn position(&self) -> (u16, usize) {
+        (self.scanline, self.cycles)
+    }
+
     fn write_to_ctrl(&mut self, value: u8) {
         let mut reg_ref = self.register.borrow_mut();
 
         let before_nmi_status = reg_ref.control.generate_vblank_nmi();
         reg_ref.control.update(value);
+        reg_ref.loopy.write_ctrl(value);
         if !before_nmi_status
             && reg_ref.control.generate_vblank_nmi()
             && reg_ref.status.vblank_started
@@ -148,8 +288,7 @@ impl IPpu for Ppu {
         let mut reg_ref = self.register.borrow_mut();
         let data = reg_ref.status.snapshot();
         reg_ref.status.reset_vblank_status();
-        reg_ref.address.reset_latch();
-        reg_ref.scroll.reset_latch();
+        reg_ref.loopy.reset_latch();
         data
     }
 
@@ -167,62 +306,32 @@ impl IPpu for Ppu {
     }
 
     fn write_to_scroll(&mut self, value: u8) {
-        self.register.borrow_mut().scroll.write(value);
+        self.register.borrow_mut().loopy.write_scroll(value);
     }
 
     fn write_to_ppu_addr(&mut self, value: u8) {
-        self.register.borrow_mut().address.update(value);
+        self.register.borrow_mut().loopy.write_addr(value);
     }
 
     fn write_to_data(&mut self, value: u8) {
-        let addr = self.register.borrow_mut().address.get();
-        match addr {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", addr),
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
-            }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
-
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize] = value;
-            }
-            0x3f00..=0x3fff => {
-                self.palette_table[(addr - 0x3f00) as usize] = value;
-            }
-            _ => panic!("unexpected access to mirrored space {}", addr),
-        }
+        let addr = self.register.borrow().loopy.get();
+        self.write_memory(addr, value);
         self.increment_vram_addr();
     }
 
     fn read_data(&self) -> u8 {
-        let addr = self.register.borrow().address.get();
-
+        let addr = self.register.borrow().loopy.get();
         self.increment_vram_addr();
-        let mut internal_data_buffer_ref = self.internal_data_buffer.borrow_mut();
-        match addr {
-            0..=0x1fff => {
-                let result = self.internal_data_buffer.borrow().clone();
-                self.internal_data_buffer.borrow_mut();
-                *internal_data_buffer_ref = self.chr_rom[addr as usize];
-                result
-            }
-            0x2000..=0x2fff => {
-                let result = self.internal_data_buffer.borrow().clone();
-                *internal_data_buffer_ref = self.vram[self.mirror_vram_addr(addr) as usize];
-                result
-            }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
-
-            //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror - 0x3f00) as usize]
-            }
 
-            0x3f00..=0x3fff => self.palette_table[(addr - 0x3f00) as usize],
-            _ => panic!("unexpected access to mirrored space {}", addr),
+        let mut buffer = self.internal_data_buffer.borrow_mut();
+        if addr >= 0x3F00 {
+            // 调色板不经过读缓冲直接返回,缓冲区填入调色板"下方"的名称表数据
+            *buffer = self.read_nametable(addr - 0x1000);
+            self.read_memory(addr)
+        } else {
+            let result = *buffer;
+            *buffer = self.read_memory(addr);
+            result
         }
     }
 
@@ -235,34 +344,110 @@ impl IPpu for Ppu {
 }
 
 /// cpu通过总线内存访问与ppu通信，共暴露8个字节的寄存器
+/// 只写寄存器的读取返回I/O锁存器的值
 impl Readable for Ppu {
     fn read(&self, addr: u16) -> u8 {
-        match addr {
-            0 | 1 | 3 | 5 | 6 => {
-                panic!("Attempt to read from write-only PPU address {}", addr);
-                // 0
+        let now = self.clock;
+        let (data, mask) = match addr & 0x07 {
+            // 状态寄存器只驱动高3位,低5位来自锁存器
+            2 => (self.read_status(), 0xE0),
+            4 => (self.read_oam_data(), 0xFF),
+            7 => {
+                // 调色板只有6位,高2位来自锁存器
+                let palette = self.register.borrow().loopy.get() >= 0x3F00;
+                let data = self.read_data();
+                (data, if palette { 0x3F } else { 0xFF })
             }
-            2 => self.read_status(),
-            4 => self.read_oam_data(),
-            7 => self.read_data(),
-            _ => panic!("Can't read ppu register: {}", addr),
-        }
+            _ => return self.register.borrow_mut().latch.read(now),
+        };
+        self.register.borrow_mut().latch.refresh(data, mask, now)
     }
 }
 impl Writable for Ppu {
     fn write(&mut self, addr: u16, data: u8) {
-        match addr {
+        let now = self.clock;
+        self.register.borrow_mut().latch.write(data, now);
+        match addr & 0x07 {
             0 => self.write_to_ctrl(data),
             1 => self.write_to_mask(data),
-            2 => panic!("attempt to write to PPU status register"),
+            // 状态寄存器只读,写入只影响锁存器
+            2 => {}
             3 => self.write_to_oam_addr(data),
             4 => self.write_to_oam_data(data),
             5 => self.write_to_scroll(data),
             6 => self.write_to_ppu_addr(data),
             7 => self.write_to_data(data),
-            _ => panic!("Can't write ppu register: {}, data: {}", addr, data),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for Ppu {
+    fn save(&self, w: &mut StateWriter) {
+        // CHR ROM不会改变,只保存CHR RAM
+        if self.chr_ram {
+            self.chr_rom.save(w);
         }
+        self.palette_table.save(w);
+        self.vram.save(w);
+        self.cartridge_vram.save(w);
+        self.oam_address.save(w);
+        self.oam_data.save(w);
+        self.register.save(w);
+        self.nmi_interrupt.save(w);
+        self.internal_data_buffer.save(w);
+        self.scanline.save(w);
+        self.cycles.save(w);
+        self.odd_frame.save(w);
+        self.clock.save(w);
+        self.background.save(w);
+        self.secondary_oam.save(w);
+        self.sprite_slots.save(w);
+        self.frame.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        if self.chr_ram {
+            self.chr_rom.load(r)?;
+        }
+        self.palette_table.load(r)?;
+        self.vram.load(r)?;
+        self.cartridge_vram.load(r)?;
+        self.oam_address.load(r)?;
+        self.oam_data.load(r)?;
+        self.register.load(r)?;
+        self.nmi_interrupt.load(r)?;
+        self.internal_data_buffer.load(r)?;
+        self.scanline.load(r)?;
+        self.cycles.load(r)?;
+        self.odd_frame.load(r)?;
+        self.clock.load(r)?;
+        self.background.load(r)?;
+        self.secondary_oam.load(r)?;
+        self.sprite_slots.load(r)?;
+        self.frame.load(r)
     }
 }
 
 impl Addressable for Ppu {}
+
+#[test]
+fn test_open_bus() {
+    let mut ppu = Ppu::new_empty();
+    ppu.write(0, 0x00);
+    ppu.write(2, 0x5A);
+    assert_eq!(ppu.read(5), 0x5A);
+    assert_eq!(ppu.read(0), 0x5A);
+    // $2002的低5位来自锁存器
+    assert_eq!(ppu.read(2), 0x1A);
+    assert_eq!(ppu.read(6), 0x1A);
+
+    // 调色板读取的高2位来自锁存器
+    ppu.write_memory(0x3F00, 0x15);
+    ppu.write(6, 0x3F);
+    ppu.write(6, 0xC0);
+    assert_eq!(ppu.read(7), 0xD5);
+
+    ppu.clock += register::latch::DECAY_DOTS + 1;
+    assert_eq!(ppu.read(1), 0);
+}
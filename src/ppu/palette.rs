@@ -0,0 +1,152 @@
+// NES的颜色由6位颜色值(0~63)加上PPUMASK中的3位色彩强调位组成,共512种
+//
+// 8  bit  0
+// ---- ----
+// BGRH HHHH (帧缓冲中的像素)
+// |||| ||||
+// |||+-++++- 颜色值(色调与亮度)
+// +++------- 色彩强调位(取自PPUMASK的高3位)
+
+/// 标准.pal文件中一个颜色占3个字节(RGB)
+const COLOR_SIZE: usize = 3;
+/// 不带强调位的调色板颜色数
+const BASE_COLORS: usize = 64;
+/// 带强调位的调色板颜色数
+const ALL_COLORS: usize = BASE_COLORS * 8;
+/// 开启强调位后,未被强调的颜色分量的衰减系数
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// 内置的NTSC 2C02调色板
+#[rustfmt::skip]
+static NTSC_PALETTE: [(u8, u8, u8); BASE_COLORS] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+/// 将帧缓冲中的像素转换为RGB颜色
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_base(&NTSC_PALETTE)
+    }
+}
+
+impl Palette {
+    /// 由64色调色板生成全部512种颜色
+    fn from_base(base: &[(u8, u8, u8)]) -> Self {
+        let mut colors = Vec::with_capacity(ALL_COLORS);
+        for emphasis in 0..8u8 {
+            for &(r, g, b) in base {
+                colors.push(emphasize((r, g, b), emphasis));
+            }
+        }
+        Self { colors }
+    }
+
+    /// 解析.pal文件,支持64色(192字节)与512色(1536字节)两种格式
+    pub fn from_pal(data: &[u8]) -> Result<Palette, String> {
+        let colors: Vec<(u8, u8, u8)> = data
+            .chunks_exact(COLOR_SIZE)
+            .map(|c| (c[0], c[1], c[2]))
+            .collect();
+        match data.len() {
+            len if len == BASE_COLORS * COLOR_SIZE => Ok(Self::from_base(&colors)),
+            len if len == ALL_COLORS * COLOR_SIZE => Ok(Self { colors }),
+            len => Err(format!("Invalid palette file size: {}", len)),
+        }
+    }
+
+    /// 从文件加载.pal调色板
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_pal(&data)
+    }
+
+    /// 获取像素(颜色值与强调位)对应的RGB颜色
+    pub fn rgb(&self, pixel: u16) -> (u8, u8, u8) {
+        self.colors[pixel as usize % ALL_COLORS]
+    }
+
+    /// 将帧缓冲转换为RGB24格式
+    pub fn to_rgb24(&self, frame: &[u16], output: &mut [u8]) {
+        for (pixel, rgb) in frame.iter().zip(output.chunks_exact_mut(3)) {
+            let (r, g, b) = self.rgb(*pixel);
+            rgb[0] = r;
+            rgb[1] = g;
+            rgb[2] = b;
+        }
+    }
+}
+
+/// 强调位会使未被强调的颜色分量变暗
+fn emphasize((r, g, b): (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+    if emphasis == 0 {
+        return (r, g, b);
+    }
+    let attenuate = |value: u8, emphasized: bool| {
+        if emphasized {
+            value
+        } else {
+            (value as f32 * EMPHASIS_ATTENUATION) as u8
+        }
+    };
+    (
+        attenuate(r, emphasis & 0b001 != 0),
+        attenuate(g, emphasis & 0b010 != 0),
+        attenuate(b, emphasis & 0b100 != 0),
+    )
+}
+
+#[test]
+fn test_default_palette() {
+    let palette = Palette::default();
+    assert_eq!(palette.rgb(0x0F), (0x05, 0x05, 0x05));
+    assert_eq!(palette.rgb(0x30), (0xFF, 0xFF, 0xFF));
+    // 强调红色时绿色与蓝色分量变暗
+    assert_eq!(palette.rgb(0x30 | 0b001 << 6), (0xFF, 0xD0, 0xD0));
+    assert_eq!(palette.rgb(0x30 | 0b111 << 6), (0xFF, 0xFF, 0xFF));
+}
+
+#[test]
+fn test_load_pal() {
+    let mut data = vec![0; BASE_COLORS * COLOR_SIZE];
+    data[3..6].copy_from_slice(&[1, 2, 3]);
+    let palette = Palette::from_pal(&data).unwrap();
+    assert_eq!(palette.rgb(1), (1, 2, 3));
+
+    let mut data = vec![0; ALL_COLORS * COLOR_SIZE];
+    data[(65 * 3)..(66 * 3)].copy_from_slice(&[4, 5, 6]);
+    let palette = Palette::from_pal(&data).unwrap();
+    assert_eq!(palette.rgb(1 | 1 << 6), (4, 5, 6));
+
+    assert_eq!(
+        Palette::from_pal(&[0; 10]),
+        Err("Invalid palette file size: 10".to_string())
+    );
+}
+
+#[test]
+fn test_to_rgb24() {
+    let palette = Palette::default();
+    let mut output = [0; 6];
+    palette.to_rgb24(&[0x30, 0x0D], &mut output);
+    assert_eq!(output, [0xFF, 0xFF, 0xFF, 0, 0, 0]);
+}
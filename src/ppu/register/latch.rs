@@ -0,0 +1,70 @@
+use crate::snapshot;
+
+/// 锁存的数据位在大约600毫秒后衰减为0(以PPU的点为单位)
+pub const DECAY_DOTS: u64 = 3_220_000;
+
+/// PPU的I/O锁存器(开路总线)
+/// CPU写入任意PPU寄存器都会刷新整个锁存器,
+/// 读取只写寄存器时返回锁存器的值,
+/// 读取可读寄存器时只刷新实际被驱动的数据位,
+/// 长时间未被刷新的数据位会衰减为0
+pub struct IoLatch {
+    value: u8,
+    /// 每个数据位最后一次被刷新的时间
+    refreshed_at: [u64; 8],
+}
+
+snapshot!(IoLatch, value, refreshed_at);
+
+impl IoLatch {
+    pub fn new() -> Self {
+        IoLatch {
+            value: 0,
+            refreshed_at: [0; 8],
+        }
+    }
+
+    /// 衰减长时间未刷新的数据位
+    fn decay(&mut self, now: u64) {
+        for bit in 0..8 {
+            if now.saturating_sub(self.refreshed_at[bit]) > DECAY_DOTS {
+                self.value &= !(1 << bit);
+            }
+        }
+    }
+
+    /// 写入PPU寄存器时刷新整个锁存器
+    pub fn write(&mut self, data: u8, now: u64) {
+        self.refresh(data, 0xFF, now);
+    }
+
+    /// 刷新mask中的数据位,返回锁存器的值
+    pub fn refresh(&mut self, data: u8, mask: u8, now: u64) -> u8 {
+        self.decay(now);
+        self.value = (self.value & !mask) | (data & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.refreshed_at[bit] = now;
+            }
+        }
+        self.value
+    }
+
+    /// 读取只写寄存器时返回锁存器的值
+    pub fn read(&mut self, now: u64) -> u8 {
+        self.decay(now);
+        self.value
+    }
+}
+
+#[test]
+fn test_latch_decay() {
+    let mut latch = IoLatch::new();
+    latch.write(0xFF, 0);
+    assert_eq!(latch.read(DECAY_DOTS), 0xFF);
+    // 只刷新高3位
+    assert_eq!(latch.refresh(0x00, 0xE0, DECAY_DOTS), 0x1F);
+    assert_eq!(latch.refresh(0xA0, 0xE0, DECAY_DOTS), 0xBF);
+    assert_eq!(latch.read(DECAY_DOTS + 1), 0xA0);
+    assert_eq!(latch.read(DECAY_DOTS * 2 + 1), 0);
+}
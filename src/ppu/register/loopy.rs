@@ -0,0 +1,216 @@
+// PPU内部的v/t寄存器布局(15位)
+//
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+//
+// $2000/$2005/$2006 共享 t、v、fine X 以及同一个写入开关 w
+
+use crate::snapshot;
+
+const COARSE_X: u16 = 0x001F;
+const COARSE_Y: u16 = 0x03E0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const NAMETABLE: u16 = NAMETABLE_X | NAMETABLE_Y;
+const FINE_Y: u16 = 0x7000;
+
+pub struct LoopyRegister {
+    /// 当前VRAM地址
+    pub v: u16,
+    /// 临时VRAM地址,可以看作屏幕左上角图块的地址
+    pub t: u16,
+    /// 精细X滚动(3位)
+    pub x: u8,
+    /// 第一次/第二次写入开关,$2005与$2006共享
+    pub w: bool,
+}
+
+snapshot!(LoopyRegister, v, t, x, w);
+
+impl LoopyRegister {
+    pub fn new() -> Self {
+        LoopyRegister {
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+        }
+    }
+
+    /// 写入$2000: t: ...GH.. ........ <- d: ......GH
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.t = (self.t & !NAMETABLE) | ((data as u16 & 0b11) << 10);
+    }
+
+    /// 写入$2005
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            // t: ....... ...ABCDE <- d: ABCDE...
+            // x:              FGH <- d: .....FGH
+            self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+            self.x = data & 0b111;
+        } else {
+            // t: FGH..AB CDE..... <- d: ABCDEFGH
+            self.t = (self.t & !(FINE_Y | COARSE_Y))
+                | ((data as u16 & 0b111) << 12)
+                | ((data as u16 >> 3) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    /// 写入$2006
+    pub fn write_addr(&mut self, data: u8) {
+        if !self.w {
+            // t: .CDEFGH ........ <- d: ..CDEFGH
+            // t: Z...... ........ <- 0
+            self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+        } else {
+            // t: ....... ABCDEFGH <- d: ABCDEFGH
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// 读取$2002时重置写入开关
+    pub fn reset_latch(&mut self) {
+        self.w = false;
+    }
+
+    /// 非渲染期间访问$2007后的地址递增
+    pub fn increment(&mut self, inc: u8) {
+        self.v = (self.v + inc as u16) & 0x7FFF;
+    }
+
+    /// 当前访问的PPU地址(14位)
+    pub fn get(&self) -> u16 {
+        self.v & 0x3FFF
+    }
+
+    /// 水平方向移动到下一个图块,越界时切换水平名称表
+    pub fn increment_coarse_x(&mut self) {
+        if self.v & COARSE_X == 31 {
+            self.v &= !COARSE_X;
+            self.v ^= NAMETABLE_X;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// 垂直方向移动到下一行像素,第29行图块之后切换垂直名称表
+    pub fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !FINE_Y;
+        let mut coarse_y = (self.v & COARSE_Y) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= NAMETABLE_Y;
+        } else if coarse_y == 31 {
+            // 超出名称表范围(属性表区域)时回绕但不切换名称表
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    /// v: ....A.. ...BCDEF <- t: ....A.. ...BCDEF
+    pub fn copy_horizontal(&mut self) {
+        self.v = (self.v & !(NAMETABLE_X | COARSE_X)) | (self.t & (NAMETABLE_X | COARSE_X));
+    }
+
+    /// v: GHIA.BC DEF..... <- t: GHIA.BC DEF.....
+    pub fn copy_vertical(&mut self) {
+        let mask = FINE_Y | NAMETABLE_Y | COARSE_Y;
+        self.v = (self.v & !mask) | (self.t & mask);
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.v & COARSE_X
+    }
+
+    pub fn coarse_y(&self) -> u16 {
+        (self.v & COARSE_Y) >> 5
+    }
+
+    pub fn fine_y(&self) -> u16 {
+        (self.v & FINE_Y) >> 12
+    }
+
+    /// 当前图块在名称表中的地址
+    pub fn tile_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    /// 当前图块对应的属性表地址
+    pub fn attribute_address(&self) -> u16 {
+        0x23C0 | (self.v & NAMETABLE) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+}
+
+#[test]
+fn test_loopy_scroll_writes() {
+    let mut loopy = LoopyRegister::new();
+    loopy.write_ctrl(0b10);
+    loopy.write_scroll(0b0111_1101);
+    assert_eq!(loopy.t, 0b000_1000_0000_1111);
+    assert_eq!(loopy.x, 0b101);
+    assert!(loopy.w);
+    loopy.write_scroll(0b0101_1110);
+    assert_eq!(loopy.t, 0b110_1001_0110_1111);
+    assert!(!loopy.w);
+}
+
+#[test]
+fn test_loopy_addr_writes() {
+    let mut loopy = LoopyRegister::new();
+    loopy.write_addr(0xFF);
+    assert_eq!(loopy.t, 0x3F00);
+    assert_eq!(loopy.v, 0);
+    loopy.write_addr(0x10);
+    assert_eq!(loopy.v, 0x3F10);
+    assert_eq!(loopy.get(), 0x3F10);
+}
+
+#[test]
+fn test_loopy_shared_toggle() {
+    // $2005与$2006共享写入开关,常见的"$2006滚动技巧"依赖于此
+    let mut loopy = LoopyRegister::new();
+    loopy.write_addr(0x04);
+    loopy.write_scroll(0x48);
+    loopy.write_scroll(0x20);
+    loopy.write_addr(0x29);
+    assert_eq!(loopy.v, 0x0529);
+    assert_eq!(loopy.x, 0);
+    loopy.reset_latch();
+    assert!(!loopy.w);
+}
+
+#[test]
+fn test_loopy_increments() {
+    let mut loopy = LoopyRegister::new();
+    loopy.v = 31;
+    loopy.increment_coarse_x();
+    assert_eq!(loopy.v, NAMETABLE_X);
+
+    loopy.v = FINE_Y | (29 << 5);
+    loopy.increment_y();
+    assert_eq!(loopy.v, NAMETABLE_Y);
+
+    loopy.v = FINE_Y | (31 << 5) | NAMETABLE_Y;
+    loopy.increment_y();
+    assert_eq!(loopy.v, NAMETABLE_Y);
+
+    loopy.t = 0x7FFF;
+    loopy.v = 0;
+    loopy.copy_horizontal();
+    assert_eq!(loopy.v, NAMETABLE_X | COARSE_X);
+    loopy.copy_vertical();
+    assert_eq!(loopy.v, 0x7FFF);
+}
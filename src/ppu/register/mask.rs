@@ -45,4 +45,10 @@ impl MaskRegister {
         }
         result
     }
+    /// 3位色彩强调位(BGR)
+    pub fn emphasis_bits(&self) -> u8 {
+        (self.emphasise_blue as u8) << 2
+            | (self.emphasise_green as u8) << 1
+            | self.emphasise_red as u8
+    }
 }
@@ -1,30 +1,36 @@
 use self::{
-    address::AddressRegister, control::ControlRegister, mask::MaskRegister, scroll::ScrollRegister,
+    control::ControlRegister, latch::IoLatch, loopy::LoopyRegister, mask::MaskRegister,
     status::StatusRegister,
 };
 
-pub mod address;
+use crate::snapshot;
+
 pub mod control;
+pub mod latch;
+pub mod loopy;
 pub mod mask;
-pub mod scroll;
 pub mod status;
 
 pub struct PpuRegister {
     pub control: ControlRegister,
     pub mask: MaskRegister,
     pub status: StatusRegister,
-    pub scroll: ScrollRegister,
-    pub address: AddressRegister,
+    /// $2000/$2005/$2006共享的内部寄存器
+    pub loopy: LoopyRegister,
+    /// CPU与PPU之间的I/O锁存器
+    pub latch: IoLatch,
 }
 
+snapshot!(PpuRegister, control, mask, status, loopy, latch);
+
 impl PpuRegister {
     pub fn new() -> Self {
         Self {
             control: ControlRegister::new(),
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
-            scroll: ScrollRegister::new(),
-            address: AddressRegister::new(),
+            loopy: LoopyRegister::new(),
+            latch: IoLatch::new(),
         }
     }
 }
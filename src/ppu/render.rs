@@ -0,0 +1,291 @@
+use super::Ppu;
+use crate::snapshot;
+
+/// 屏幕宽度
+pub const SCREEN_WIDTH: usize = 256;
+/// 屏幕高度
+pub const SCREEN_HEIGHT: usize = 240;
+
+/// 背景渲染流水线
+/// 每8个点取一次图块数据(名称表、属性表、图案低位、图案高位),
+/// 取到的数据在下一组的第一个点装入移位寄存器的低8位
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BackgroundPipeline {
+    nametable_byte: u8,
+    attribute_byte: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    shift_pattern_low: u16,
+    shift_pattern_high: u16,
+    shift_attribute_low: u16,
+    shift_attribute_high: u16,
+}
+
+snapshot!(
+    BackgroundPipeline,
+    nametable_byte,
+    attribute_byte,
+    pattern_low,
+    pattern_high,
+    shift_pattern_low,
+    shift_pattern_high,
+    shift_attribute_low,
+    shift_attribute_high
+);
+
+impl BackgroundPipeline {
+    fn shift(&mut self) {
+        self.shift_pattern_low <<= 1;
+        self.shift_pattern_high <<= 1;
+        self.shift_attribute_low <<= 1;
+        self.shift_attribute_high <<= 1;
+    }
+
+    fn reload(&mut self) {
+        self.shift_pattern_low = (self.shift_pattern_low & 0xFF00) | self.pattern_low as u16;
+        self.shift_pattern_high = (self.shift_pattern_high & 0xFF00) | self.pattern_high as u16;
+        let attribute_low = if self.attribute_byte & 0b01 != 0 {
+            0xFF
+        } else {
+            0
+        };
+        let attribute_high = if self.attribute_byte & 0b10 != 0 {
+            0xFF
+        } else {
+            0
+        };
+        self.shift_attribute_low = (self.shift_attribute_low & 0xFF00) | attribute_low;
+        self.shift_attribute_high = (self.shift_attribute_high & 0xFF00) | attribute_high;
+    }
+
+    /// 根据精细X滚动取出当前像素,返回(调色板序号, 2位像素值)
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let mux = 0x8000 >> fine_x;
+        let bit = |reg: u16| (reg & mux != 0) as u8;
+        let pixel = (bit(self.shift_pattern_high) << 1) | bit(self.shift_pattern_low);
+        let palette = (bit(self.shift_attribute_high) << 1) | bit(self.shift_attribute_low);
+        (palette, pixel)
+    }
+}
+
+impl Ppu {
+    /// 根据PPUMASK的灰度与色彩强调位生成输出到帧缓冲的像素
+    fn output_pixel(&self, palette_index: u8) -> u16 {
+        let mut color = self.read_palette(palette_index);
+        let reg = self.register.borrow();
+        if reg.mask.is_grey_scale {
+            color &= 0x30;
+        }
+        color as u16 | (reg.mask.emphasis_bits() as u16) << 6
+    }
+
+    /// 渲染开启时可见扫描线与预渲染扫描线上每个点的工作
+    pub(super) fn render_dot(&mut self) {
+        let dot = self.cycles;
+        let visible = self.scanline < 240;
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.background.shift();
+            if dot >= 9 && (dot - 1).is_multiple_of(8) {
+                self.background.reload();
+            }
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            self.fetch_background((dot - 1) % 8);
+        }
+
+        // 第256个点完成下一条扫描线的精灵评估,第257~320个点取精灵数据
+        if dot == 256 {
+            self.evaluate_next_line();
+        }
+        if (257..=320).contains(&dot) && (dot - 257) % 8 == 7 {
+            self.fetch_sprite((dot - 257) / 8);
+        }
+
+        self.update_vram_address();
+
+        if visible && (1..=256).contains(&dot) {
+            self.render_pixel((dot - 1) as u8);
+        }
+    }
+
+    /// 按照8个点为一组的节奏取背景图块数据
+    fn fetch_background(&mut self, phase: usize) {
+        let (coarse_x, coarse_y, fine_y, tile_addr, attribute_addr, pattern_base) = {
+            let reg = self.register.borrow();
+            let loopy = &reg.loopy;
+            (
+                loopy.coarse_x(),
+                loopy.coarse_y(),
+                loopy.fine_y(),
+                loopy.tile_address(),
+                loopy.attribute_address(),
+                reg.control.background_pattern_address(),
+            )
+        };
+        match phase {
+            0 => {
+                self.background.nametable_byte = self.read_nametable(tile_addr);
+            }
+            2 => {
+                let attribute = self.read_nametable(attribute_addr);
+                let shift = ((coarse_y & 0b10) << 1) | (coarse_x & 0b10);
+                self.background.attribute_byte = (attribute >> shift) & 0b11;
+            }
+            4 => {
+                let addr = pattern_base + self.background.nametable_byte as u16 * 16 + fine_y;
+                self.background.pattern_low = self.read_chr(addr);
+            }
+            6 => {
+                let addr = pattern_base + self.background.nametable_byte as u16 * 16 + fine_y + 8;
+                self.background.pattern_high = self.read_chr(addr);
+            }
+            _ => {}
+        }
+    }
+
+    /// 合成背景与精灵像素并写入帧缓冲
+    fn render_pixel(&mut self, x: u8) {
+        let (show_background, show_sprite, clip_background, clip_sprite, fine_x) = {
+            let reg = self.register.borrow();
+            let mask = &reg.mask;
+            (
+                mask.show_background,
+                mask.show_sprite,
+                !mask.leftmost_8pxl_background && x < 8,
+                !mask.leftmost_8pxl_sprite && x < 8,
+                reg.loopy.x,
+            )
+        };
+
+        let (bg_palette, bg_pixel) = if show_background && !clip_background {
+            self.background.pixel(fine_x)
+        } else {
+            (0, 0)
+        };
+        let sprite = if show_sprite && !clip_sprite {
+            self.sprite_slots
+                .iter()
+                .find_map(|slot| slot.pixel(x).map(|pixel| (slot, pixel)))
+        } else {
+            None
+        };
+
+        let palette_index = match sprite {
+            Some((slot, sprite_pixel)) => {
+                if slot.index == 0 && bg_pixel != 0 && x != 255 {
+                    self.register.borrow_mut().status.sprite_zero_hit = true;
+                }
+                if bg_pixel != 0 && slot.behind_background() {
+                    (bg_palette << 2) | bg_pixel
+                } else {
+                    0x10 | (slot.palette() << 2) | sprite_pixel
+                }
+            }
+            None if bg_pixel != 0 => (bg_palette << 2) | bg_pixel,
+            None => 0,
+        };
+        let index = self.scanline as usize * SCREEN_WIDTH + x as usize;
+        self.frame[index] = self.output_pixel(palette_index);
+    }
+
+    /// 渲染关闭时输出背景色
+    pub(super) fn render_backdrop(&mut self) {
+        let index = self.scanline as usize * SCREEN_WIDTH + self.cycles - 1;
+        self.frame[index] = self.output_pixel(0);
+    }
+}
+
+#[cfg(test)]
+fn run_frame(ppu: &mut Ppu) -> usize {
+    use super::IPpu;
+    let mut dots = 1;
+    while !ppu.tick(1) {
+        dots += 1;
+    }
+    dots
+}
+
+#[test]
+fn test_odd_frame_skip() {
+    use crate::flag::FlagRegister;
+    let mut ppu = Ppu::new_empty();
+    ppu.register.borrow_mut().mask.update(0b0000_1000);
+    assert_eq!(run_frame(&mut ppu), 341 * 262);
+    assert_eq!(run_frame(&mut ppu), 341 * 262 - 1);
+    assert_eq!(run_frame(&mut ppu), 341 * 262);
+    // 渲染关闭时不跳过
+    ppu.register.borrow_mut().mask.update(0);
+    assert_eq!(run_frame(&mut ppu), 341 * 262);
+    assert_eq!(run_frame(&mut ppu), 341 * 262);
+}
+
+#[test]
+fn test_region_frame_length() {
+    use super::IPpu;
+    use crate::{flag::FlagRegister, meta::Region};
+    for region in [Region::Pal, Region::Dendy] {
+        let mut ppu = Ppu::new_empty();
+        ppu.set_region(region);
+        ppu.register.borrow_mut().mask.update(0b0000_1000);
+        // 只有NTSC跳过奇数帧的点
+        assert_eq!(run_frame(&mut ppu), 341 * 312);
+        assert_eq!(run_frame(&mut ppu), 341 * 312);
+        while ppu.scanline != region.vblank_line() || ppu.cycles != 2 {
+            ppu.tick(1);
+        }
+        assert!(ppu.register.borrow().status.vblank_started);
+    }
+}
+
+#[test]
+fn test_vblank_nmi_timing() {
+    use super::IPpu;
+    let mut ppu = Ppu::new_empty();
+    ppu.write_to_ctrl(0x80);
+    while ppu.scanline != 241 || ppu.cycles != 1 {
+        ppu.tick(1);
+    }
+    assert!(!ppu.register.borrow().status.vblank_started);
+    assert_eq!(ppu.poll_nmi_interrupt(), None);
+    ppu.tick(1);
+    assert!(ppu.register.borrow().status.vblank_started);
+    assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+}
+
+#[test]
+fn test_greyscale_and_emphasis() {
+    use crate::flag::FlagRegister;
+    let mut ppu = Ppu::new_empty();
+    ppu.palette_table[0] = 0x2A;
+    ppu.register.borrow_mut().mask.update(0b1010_0001);
+    run_frame(&mut ppu);
+    assert_eq!(ppu.frame[0], 0x20 | 0b101 << 6);
+}
+
+#[test]
+fn test_background_render() {
+    use crate::{flag::FlagRegister, meta::Mirror};
+    // 图块1的低位平面全为1,即像素值为1
+    let mut chr_rom = vec![0; 0x2000];
+    for byte in chr_rom[16..24].iter_mut() {
+        *byte = 0xFF;
+    }
+    let mut ppu = Ppu::new(chr_rom, Mirror::Horizontal);
+    // 左半屏为图块1,右半屏为透明的图块0
+    for row in 0..30 {
+        for col in 0..16 {
+            ppu.vram[row * 32 + col] = 1;
+        }
+    }
+    ppu.palette_table[0] = 0x0F;
+    ppu.palette_table[1] = 0x21;
+    ppu.register.borrow_mut().mask.update(0b0000_1010);
+    run_frame(&mut ppu);
+    run_frame(&mut ppu);
+    assert_eq!(ppu.frame[0], 0x21);
+    assert_eq!(ppu.frame[127], 0x21);
+    assert_eq!(ppu.frame[128], 0x0F);
+    assert_eq!(ppu.frame[239 * SCREEN_WIDTH + 100], 0x21);
+}
@@ -0,0 +1,260 @@
+use super::Ppu;
+use crate::snapshot;
+
+/// 每条扫描线最多显示8个精灵
+pub const MAX_SPRITES_PER_LINE: usize = 8;
+
+/// 一条扫描线的精灵评估结果
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SpriteEvaluation {
+    /// 命中该扫描线的精灵在OAM中的序号(最多8个)
+    pub sprites: Vec<u8>,
+    /// 是否设置精灵溢出标志
+    pub overflow: bool,
+}
+
+/// 判断OAM中的Y坐标是否落在目标扫描线上
+/// 精灵在第Y+1条扫描线开始显示
+fn in_range(y: u8, scanline: u16, height: u8) -> bool {
+    let top = y as u16 + 1;
+    scanline >= top && scanline < top + height as u16
+}
+
+/// 模拟硬件的精灵评估过程
+/// 找满8个精灵后,硬件在检查后续精灵时会错误地同时递增n与m,
+/// 导致读取的"Y坐标"沿着OAM斜向移动,即著名的精灵溢出bug
+pub fn evaluate_sprites(oam: &[u8; 256], scanline: u16, height: u8) -> SpriteEvaluation {
+    let mut result = SpriteEvaluation::default();
+    let mut n = 0;
+    while n < 64 {
+        if result.sprites.len() < MAX_SPRITES_PER_LINE {
+            if in_range(oam[n * 4], scanline, height) {
+                result.sprites.push(n as u8);
+            }
+            n += 1;
+            continue;
+        }
+        // 已找到8个精灵,开始(错误地)检查溢出
+        let mut m = 0;
+        while n < 64 {
+            if in_range(oam[n * 4 + m], scanline, height) {
+                result.overflow = true;
+                return result;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+        }
+    }
+    result
+}
+
+/// 当前扫描线上一个精灵的输出单元
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SpriteSlot {
+    /// 精灵在OAM中的序号
+    pub index: u8,
+    pub x: u8,
+    pub attribute: u8,
+    /// 已按水平翻转处理过的图案数据
+    pub pattern_low: u8,
+    pub pattern_high: u8,
+}
+
+snapshot!(SpriteSlot, index, x, attribute, pattern_low, pattern_high);
+
+impl SpriteSlot {
+    /// 获取精灵在屏幕第x个像素处的2位像素值,不透明时返回Some
+    pub fn pixel(&self, x: u8) -> Option<u8> {
+        if x < self.x || x - self.x >= 8 {
+            return None;
+        }
+        let bit = 7 - (x - self.x);
+        let pixel = (((self.pattern_high >> bit) & 1) << 1) | ((self.pattern_low >> bit) & 1);
+        if pixel == 0 {
+            None
+        } else {
+            Some(pixel)
+        }
+    }
+
+    pub fn palette(&self) -> u8 {
+        self.attribute & 0b11
+    }
+
+    /// 优先级位为1时精灵位于背景之后
+    pub fn behind_background(&self) -> bool {
+        self.attribute & 0x20 != 0
+    }
+}
+
+impl Ppu {
+    /// 为下一条扫描线评估精灵,预渲染扫描线不进行评估
+    pub(super) fn evaluate_next_line(&mut self) {
+        if self.scanline >= 240 {
+            self.secondary_oam.clear();
+            return;
+        }
+        let height = self.register.borrow().control.sprite_size();
+        let evaluation = evaluate_sprites(&self.oam_data, self.scanline + 1, height);
+        if evaluation.overflow {
+            self.register.borrow_mut().status.sprite_overflow = true;
+        }
+        self.secondary_oam = evaluation.sprites;
+    }
+
+    /// 取第slot个精灵的图案数据
+    pub(super) fn fetch_sprite(&mut self, slot: usize) {
+        if slot == 0 {
+            self.sprite_slots.clear();
+        }
+        let index = match self.secondary_oam.get(slot) {
+            Some(index) => *index,
+            None => return,
+        };
+        let (height, pattern_base) = {
+            let reg = self.register.borrow();
+            (
+                reg.control.sprite_size() as u16,
+                reg.control.sprite_pattern_address(),
+            )
+        };
+        let oam = &self.oam_data[index as usize * 4..index as usize * 4 + 4];
+        let (sprite_y, tile, attribute, x) = (oam[0] as u16 + 1, oam[1], oam[2], oam[3]);
+        let next_line = (self.scanline + 1) % self.region.scanlines();
+
+        let mut row = next_line.wrapping_sub(sprite_y);
+        // 垂直翻转
+        if attribute & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+        let addr = if height == 8 {
+            pattern_base + tile as u16 * 16 + row
+        } else {
+            // 8x16模式下图块序号的最低位选择图案表
+            let bank = (tile as u16 & 1) * 0x1000;
+            let mut tile = tile as u16 & 0xFE;
+            if row >= 8 {
+                tile += 1;
+                row -= 8;
+            }
+            bank + tile * 16 + row
+        };
+        let mut pattern_low = self.read_chr(addr);
+        let mut pattern_high = self.read_chr(addr + 8);
+        // 水平翻转
+        if attribute & 0x40 != 0 {
+            pattern_low = pattern_low.reverse_bits();
+            pattern_high = pattern_high.reverse_bits();
+        }
+        self.sprite_slots.push(SpriteSlot {
+            index,
+            x,
+            attribute,
+            pattern_low,
+            pattern_high,
+        });
+    }
+}
+
+#[test]
+fn test_evaluate_sprites() {
+    let mut oam = [0xFF; 256];
+    for i in 0..3 {
+        oam[i * 4] = 10;
+    }
+    let result = evaluate_sprites(&oam, 12, 8);
+    assert_eq!(result.sprites, vec![0, 1, 2]);
+    assert!(!result.overflow);
+    // 精灵在Y+1处开始显示
+    assert!(evaluate_sprites(&oam, 10, 8).sprites.is_empty());
+    assert_eq!(evaluate_sprites(&oam, 18, 8).sprites, vec![0, 1, 2]);
+    assert!(evaluate_sprites(&oam, 19, 8).sprites.is_empty());
+    assert_eq!(evaluate_sprites(&oam, 26, 16).sprites, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_sprite_overflow() {
+    let mut oam = [0xFF; 256];
+    for i in 0..9 {
+        oam[i * 4] = 10;
+    }
+    let result = evaluate_sprites(&oam, 12, 8);
+    assert_eq!(result.sprites.len(), 8);
+    assert!(result.overflow);
+}
+
+#[test]
+fn test_sprite_overflow_diagonal_bug() {
+    let mut oam = [0xFF; 256];
+    for i in 0..8 {
+        oam[i * 4] = 10;
+    }
+    // 第9个精灵不在范围内,第10个精灵的Y坐标在范围内,
+    // 但硬件读取的是它的图块序号(m=1),因此不会检测到溢出
+    oam[9 * 4] = 10;
+    let result = evaluate_sprites(&oam, 12, 8);
+    assert!(!result.overflow);
+    // 第10个精灵的图块序号落在范围内时反而会误报溢出
+    oam[9 * 4 + 1] = 10;
+    let result = evaluate_sprites(&oam, 12, 8);
+    assert!(result.overflow);
+}
+
+#[cfg(test)]
+fn sprite_zero_test_ppu(sprite_x: u8) -> Ppu {
+    use crate::{flag::FlagRegister, meta::Mirror};
+    // 图块1为全不透明
+    let mut chr_rom = vec![0; 0x2000];
+    for byte in chr_rom[16..32].iter_mut() {
+        *byte = 0xFF;
+    }
+    let mut ppu = Ppu::new(chr_rom, Mirror::Horizontal);
+    ppu.vram = [1; 2048];
+    ppu.oam_data[0] = 9;
+    ppu.oam_data[1] = 1;
+    ppu.oam_data[3] = sprite_x;
+    ppu.register.borrow_mut().mask.update(0b0001_1110);
+    ppu
+}
+
+#[cfg(test)]
+fn tick_to(ppu: &mut Ppu, scanline: u16, dot: usize) {
+    use super::IPpu;
+    while ppu.scanline != scanline || ppu.cycles != dot {
+        ppu.tick(1);
+    }
+}
+
+#[test]
+fn test_sprite_zero_hit_timing() {
+    let mut ppu = sprite_zero_test_ppu(20);
+    tick_to(&mut ppu, 10, 21);
+    assert!(!ppu.register.borrow().status.sprite_zero_hit);
+    tick_to(&mut ppu, 10, 22);
+    assert!(ppu.register.borrow().status.sprite_zero_hit);
+    // 预渲染扫描线清除标志
+    tick_to(&mut ppu, 261, 2);
+    assert!(!ppu.register.borrow().status.sprite_zero_hit);
+}
+
+#[test]
+fn test_sprite_zero_hit_edge_cases() {
+    // x=255处不会发生碰撞
+    let mut ppu = sprite_zero_test_ppu(255);
+    tick_to(&mut ppu, 240, 0);
+    assert!(!ppu.register.borrow().status.sprite_zero_hit);
+
+    // 左侧8像素被裁剪时,碰撞推迟到x=8
+    let mut ppu = sprite_zero_test_ppu(4);
+    ppu.register.borrow_mut().mask.leftmost_8pxl_sprite = false;
+    tick_to(&mut ppu, 10, 9);
+    assert!(!ppu.register.borrow().status.sprite_zero_hit);
+    tick_to(&mut ppu, 10, 10);
+    assert!(ppu.register.borrow().status.sprite_zero_hit);
+
+    // 精灵完全位于被裁剪的区域时不会发生碰撞
+    let mut ppu = sprite_zero_test_ppu(0);
+    ppu.register.borrow_mut().mask.leftmost_8pxl_background = false;
+    tick_to(&mut ppu, 240, 0);
+    assert!(!ppu.register.borrow().status.sprite_zero_hit);
+}
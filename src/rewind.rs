@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+
+use crate::{
+    cpu::CPU,
+    frame_input::{self, PLAYERS},
+};
+
+/// 默认每10帧保存一次快照
+pub const DEFAULT_REWIND_INTERVAL: u32 = 10;
+/// 默认的内存预算(MB)
+pub const DEFAULT_REWIND_BUDGET_MB: usize = 64;
+
+/// 最新的快照,保存完整的存档
+struct Keyframe {
+    /// 快照所在的帧
+    frame: u64,
+    state: Vec<u8>,
+    /// 从快照开始每帧的手柄状态
+    inputs: Vec<[u8; PLAYERS]>,
+}
+
+/// 较早的快照,保存与下一个快照异或后压缩的数据
+struct Delta {
+    frame: u64,
+    /// 存档的长度
+    length: usize,
+    data: Vec<u8>,
+    inputs: Vec<[u8; PLAYERS]>,
+}
+
+impl Delta {
+    fn size(&self) -> usize {
+        self.data.len() + self.inputs.len() * PLAYERS
+    }
+}
+
+/// 倒带缓冲区
+///
+/// 每隔interval帧保存一次快照,只有最新的快照是完整的存档,
+/// 较早的快照保存为与后一个快照的异或差值再做游程压缩,相邻快照大部分相同,压缩后很小;
+/// 超出内存预算时丢弃最早的快照。快照之间记录每帧的手柄状态,
+/// 倒退一帧时从之前最近的快照开始重新模拟到目标帧,因此可以逐帧倒退
+pub struct Rewind {
+    interval: u64,
+    /// 内存预算(字节)
+    budget: usize,
+    /// 已记录的帧数
+    frame: u64,
+    newest: Option<Keyframe>,
+    older: VecDeque<Delta>,
+    /// older中数据的总字节数
+    older_size: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1) as u64,
+            budget,
+            frame: 0,
+            newest: None,
+            older: VecDeque::new(),
+            older_size: 0,
+        }
+    }
+
+    /// 丢弃全部快照,读取存档后之前的记录不再有效
+    pub fn clear(&mut self) {
+        self.frame = 0;
+        self.newest = None;
+        self.older.clear();
+        self.older_size = 0;
+    }
+
+    /// 每帧开始前调用,states为这一帧的手柄状态(已经写入手柄)
+    pub fn record(&mut self, cpu: &CPU, states: [u8; PLAYERS]) {
+        if self.budget == 0 {
+            return;
+        }
+        if self.frame.is_multiple_of(self.interval) || self.newest.is_none() {
+            self.push(cpu.save_state());
+        }
+        self.newest.as_mut().unwrap().inputs.push(states);
+        self.frame += 1;
+    }
+
+    /// 倒退一帧,返回倒退后最后一帧的画面,没有更早的记录时返回None
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Option<Vec<u16>> {
+        let target = self.frame.checked_sub(1)?;
+        let oldest = match self.older.front() {
+            Some(delta) => delta.frame,
+            None => self.newest.as_ref()?.frame,
+        };
+        // 至少重新模拟一帧以得到画面
+        if oldest >= target {
+            return None;
+        }
+        while self.newest.as_ref().unwrap().frame >= target {
+            self.pop();
+        }
+        let newest = self.newest.as_mut().unwrap();
+        cpu.load_state(&newest.state).ok()?;
+        newest.inputs.truncate((target - newest.frame) as usize);
+        let mut frame = Vec::new();
+        for states in &newest.inputs {
+            frame_input::apply_states(states, cpu.bus.as_mut());
+            frame = run_frame(cpu);
+        }
+        self.frame = target;
+        Some(frame)
+    }
+
+    /// 保存新的快照,原来最新的快照转换为差值
+    fn push(&mut self, state: Vec<u8>) {
+        let keyframe = Keyframe {
+            frame: self.frame,
+            state,
+            inputs: Vec::new(),
+        };
+        if let Some(previous) = self.newest.replace(keyframe) {
+            let delta = Delta {
+                frame: previous.frame,
+                length: previous.state.len(),
+                data: compress(&xor(&previous.state, &self.newest.as_ref().unwrap().state)),
+                inputs: previous.inputs,
+            };
+            self.older_size += delta.size();
+            self.older.push_back(delta);
+        }
+        let newest_size = self.newest.as_ref().unwrap().state.len();
+        while self.older_size + newest_size > self.budget {
+            match self.older.pop_front() {
+                Some(delta) => self.older_size -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// 丢弃最新的快照,用它还原前一个快照
+    fn pop(&mut self) {
+        let delta = self.older.pop_back().unwrap();
+        self.older_size -= delta.size();
+        let newest = self.newest.as_mut().unwrap();
+        let mut state = xor(&decompress(&delta.data), &newest.state);
+        state.resize(delta.length, 0);
+        *newest = Keyframe {
+            frame: delta.frame,
+            state,
+            inputs: delta.inputs,
+        };
+    }
+}
+
+/// 运行到这一帧结束,返回画面
+fn run_frame(cpu: &mut CPU) -> Vec<u16> {
+    loop {
+        cpu.run_one_instruction();
+        if let Some(frame) = cpu.bus.poll_frame() {
+            return frame.to_vec();
+        }
+    }
+}
+
+/// 逐字节异或,结果的长度与data相同,base较短时视为补0
+fn xor(data: &[u8], base: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ base.get(i).unwrap_or(&0))
+        .collect()
+}
+
+// 游程压缩: 控制字节n < 0x80时后面是n + 1个原样的字节,
+// 为0x80时后面是LEB128编码的重复次数与重复的字节,
+// 异或差值中大段的0只需要几个字节
+const MAX_LITERAL: usize = 0x80;
+const RUN: u8 = 0x80;
+/// 短于此长度的重复按原样保存
+const MIN_RUN: usize = 4;
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+    let flush = |output: &mut Vec<u8>, literals: &[u8]| {
+        for chunk in literals.chunks(MAX_LITERAL) {
+            output.push((chunk.len() - 1) as u8);
+            output.extend(chunk);
+        }
+    };
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take_while(|byte| **byte == data[i])
+            .count();
+        if run >= MIN_RUN {
+            flush(&mut output, &data[literal_start..i]);
+            output.push(RUN);
+            let mut count = run;
+            while count >= 0x80 {
+                output.push(count as u8 | 0x80);
+                count >>= 7;
+            }
+            output.push(count as u8);
+            output.push(data[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    flush(&mut output, &data[literal_start..]);
+    output
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control == RUN {
+            let mut count = 0;
+            let mut shift = 0;
+            loop {
+                count |= ((data[i] & 0x7F) as usize) << shift;
+                shift += 7;
+                i += 1;
+                if data[i - 1] < 0x80 {
+                    break;
+                }
+            }
+            output.extend(std::iter::repeat_n(data[i], count));
+            i += 1;
+        } else {
+            let length = control as usize + 1;
+            output.extend(&data[i..i + length]);
+            i += length;
+        }
+    }
+    output
+}
+
+#[test]
+fn test_compress() {
+    let mut data = vec![0; 1000];
+    data[10] = 1;
+    data[11] = 2;
+    data.extend((0..=255).collect::<Vec<u8>>());
+    data.extend([7, 7]);
+    let compressed = compress(&data);
+    assert!(compressed.len() < 280);
+    assert_eq!(decompress(&compressed), data);
+    assert_eq!(decompress(&compress(&[])), Vec::<u8>::new());
+}
+
+#[test]
+fn test_step_back() {
+    use crate::state::test_cpu;
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E; STA $2001
+        0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #$01; STA $4016
+        0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #$00; STA $4016
+        0xAD, 0x16, 0x40,             // LDA $4016
+        0x65, 0x10, 0x85, 0x10,       // ADC $10; STA $10
+        0x4C, 0x05, 0x80,             // JMP $8005
+    ];
+    let mut cpu = test_cpu(&program);
+    run_frame(&mut cpu);
+    let mut rewind = Rewind::new(4, usize::MAX);
+    let mut history = Vec::new();
+    for frame in 0..23u8 {
+        let states = [frame % 3, 0, 0, 0];
+        frame_input::apply_states(&states, cpu.bus.as_mut());
+        rewind.record(&cpu, states);
+        let image = run_frame(&mut cpu);
+        history.push((cpu.save_state(), image));
+    }
+    // 逐帧倒退到第一帧,每一帧都与原来完全相同
+    for frame in (1..23).rev() {
+        let image = rewind.step_back(&mut cpu).unwrap();
+        assert_eq!((cpu.save_state(), image), history[frame - 1]);
+    }
+    assert_eq!(rewind.step_back(&mut cpu), None);
+
+    // 倒退后继续记录
+    let states = [1, 0, 0, 0];
+    frame_input::apply_states(&states, cpu.bus.as_mut());
+    rewind.record(&cpu, states);
+    run_frame(&mut cpu);
+    assert!(rewind.step_back(&mut cpu).is_some());
+}
+
+#[test]
+fn test_budget() {
+    use crate::state::test_cpu;
+    let mut cpu = test_cpu(&[0xE6, 0x10, 0x4C, 0x00, 0x80]);
+    let size = cpu.save_state().len();
+    // 只能容纳最新的快照与很少的差值
+    let mut rewind = Rewind::new(2, size + 100);
+    for _ in 0..20 {
+        rewind.record(&cpu, [0; PLAYERS]);
+        run_frame(&mut cpu);
+    }
+    assert!(rewind.older.len() < 10);
+    assert!(rewind.older_size + size <= rewind.budget);
+    let mut steps = 0;
+    while rewind.step_back(&mut cpu).is_some() {
+        steps += 1;
+    }
+    assert!(steps > 0 && steps < 19);
+
+    let mut disabled = Rewind::new(2, 0);
+    disabled.record(&cpu, [0; PLAYERS]);
+    assert!(disabled.newest.is_none());
+}
@@ -1,6 +1,8 @@
 use crate::{
     addressable::{Addressable, Readable, Writable},
-    meta::Mirror,
+    hash::fnv1a,
+    meta::{Mirror, Region},
+    state::{Snapshot, StateReader, StateWriter},
 };
 
 pub struct Rom {
@@ -9,6 +11,10 @@ pub struct Rom {
     pub mapper: u8,
     pub mirror: Mirror,
     pub has_battery_backed: bool,
+    /// 卡带声明的电视制式
+    pub region: Region,
+    /// NES 2.0声明的默认输入设备,0为未指定
+    pub input_device: u8,
 }
 
 impl Readable for Rom {
@@ -27,6 +33,22 @@ impl Writable for Rom {
     }
 }
 
+/// NROM没有可以切换的状态,只保存PRG ROM的哈希,防止读取其他游戏的存档
+impl Snapshot for Rom {
+    fn save(&self, w: &mut StateWriter) {
+        fnv1a(&self.prg_rom).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut hash = 0u64;
+        hash.load(r)?;
+        if hash != fnv1a(&self.prg_rom) {
+            return Err("The save state belongs to a different game".to_string());
+        }
+        Ok(())
+    }
+}
+
 impl Addressable for Rom {}
 
 const PRG_ROM_PAGE_SIZE: usize = 0x4000;
@@ -36,8 +58,22 @@ impl Rom {
         if let Some(error) = check_rom(data) {
             return Err(error);
         }
-        let prg_rom_size = data[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = data[5] as usize * CHR_ROM_PAGE_SIZE;
+        let is_nes2 = data[7] & 0b1100 == 0b1000;
+        let (prg_rom_size, chr_rom_size) = if is_nes2 {
+            // NES2.0的byte 9提供了页数的高4位
+            (
+                nes2_rom_size(data[4], data[9] & 0x0F, PRG_ROM_PAGE_SIZE),
+                nes2_rom_size(data[5], data[9] >> 4, CHR_ROM_PAGE_SIZE),
+            )
+        } else {
+            (
+                Some(data[4] as usize * PRG_ROM_PAGE_SIZE),
+                Some(data[5] as usize * CHR_ROM_PAGE_SIZE),
+            )
+        };
+        let (prg_rom_size, chr_rom_size) = prg_rom_size
+            .zip(chr_rom_size)
+            .ok_or("Invalid rom size in nes file")?;
 
         let mapper_l = data[6] >> 4;
         let mapper_h = data[7] >> 4;
@@ -56,30 +92,58 @@ impl Rom {
                 Mirror::Horizontal
             }
         };
+        let region = if is_nes2 {
+            // byte 12: 0 NTSC, 1 PAL, 2 多制式, 3 Dendy
+            match data[12] & 0b11 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                _ => Region::Ntsc,
+            }
+        } else if data[9] & 1 != 0 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        };
+        // byte 15: 默认扩展设备
+        let input_device = if is_nes2 { data[15] & 0x3F } else { 0 };
         let prg_rom_start = 16 + if has_trainer { 512 } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
+        if data.len() < chr_rom_start + chr_rom_size {
+            return Err("Truncated nes file".to_string());
+        }
         Ok(Self {
             prg_rom: data[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
             chr_rom: data[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
             mapper,
             mirror,
             has_battery_backed,
+            region,
+            input_device,
         })
     }
 }
 
+/// NES2.0的ROM大小,msb为0xF时lsb为指数形式: 2^E * (2M + 1)字节,E为高6位,M为低2位
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        Some(((msb as usize) << 8 | lsb as usize) * page_size)
+    }
+}
+
 const NES_HEADER: [u8; 4] = [78, 69, 83, 26];
 fn check_rom(data: &[u8]) -> Option<String> {
     let header: [u8; 4] = NES_HEADER;
-    if &data[0..4] != &header {
+    if data.len() < 16 || &data[0..4] != &header {
         return Some("Invalid nes file".to_string());
     }
-    if data[7] & 0b1100 == 0b1000 {
-        return Some("NES2.0 format is not supported".to_string());
-    }
     None
 }
 
+#[cfg(test)]
 pub mod test {
 
     use super::*;
@@ -122,6 +186,24 @@ pub mod test {
         Rom::new(&test_rom).unwrap()
     }
 
+    /// 程序位于$8000,复位向量指向$8000
+    pub fn test_rom_with_program(program: &[u8]) -> Rom {
+        let mut pgp_rom = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+        pgp_rom[..program.len()].copy_from_slice(program);
+        pgp_rom[0x7FFC] = 0x00;
+        pgp_rom[0x7FFD] = 0x80;
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom,
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        Rom::new(&test_rom).unwrap()
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -176,19 +258,77 @@ pub mod test {
     }
 
     #[test]
-    fn test_nes2_is_not_supported() {
-        let test_rom = create_rom(TestRom {
+    fn test_nes2_region() {
+        for (byte12, region) in [
+            (0, Region::Ntsc),
+            (1, Region::Pal),
+            (2, Region::Ntsc),
+            (3, Region::Dendy),
+        ] {
+            let test_rom = create_rom(TestRom {
+                header: vec![
+                    0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, byte12, 00, 00,
+                    00,
+                ],
+                trainer: None,
+                pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+                chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+            });
+            let rom = Rom::new(&test_rom).unwrap();
+            assert_eq!(rom.region, region);
+            assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_nes2_input_device() {
+        let nes2_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x31, 0x8, 00, 00, 00, 00, 00, 00, 00, 0x02,
             ],
             trainer: None,
-            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
-            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
         });
-        let rom = Rom::new(&test_rom);
-        match rom {
-            Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
-        }
+        assert_eq!(Rom::new(&nes2_rom).unwrap().input_device, 0x02);
+        assert_eq!(test_rom().input_device, 0);
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        // PRG: 2^14 * 1, CHR: 2^11 * 3
+        let nes2_rom = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                14 << 2,
+                11 << 2 | 1,
+                0x01,
+                0x8,
+                00,
+                0xFF,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 0x1800],
+        });
+        let rom = Rom::new(&nes2_rom).unwrap();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), 0x1800);
+
+        // 文件比头中声明的大小短时返回错误
+        assert!(Rom::new(&nes2_rom[..nes2_rom.len() - 1]).is_err());
+        let mut huge = nes2_rom.clone();
+        huge[4] = 63 << 2 | 3;
+        assert!(Rom::new(&huge).is_err());
+        assert!(Rom::new(&nes2_rom[..8]).is_err());
     }
 }
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+/// 默认快进不限速
+pub const DEFAULT_FAST_FORWARD: u32 = 0;
+/// 默认慢动作为1/4速度
+pub const DEFAULT_SLOW_MOTION: u32 = 4;
+
+/// 模拟的速度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// 正常速度,由音频时钟控制
+    Normal,
+    /// 快进,为None时不限速,否则为正常速度的倍数
+    Fast(Option<u32>),
+    /// 慢动作,为正常速度的几分之一
+    Slow(u32),
+}
+
+/// 暂停、逐帧、快进与慢动作
+///
+/// 快进在按住时生效,优先于慢动作;暂停时按逐帧键运行一帧后再次暂停
+pub struct SpeedControl {
+    paused: bool,
+    /// 暂停时等待运行的帧数
+    advance: u32,
+    fast_forward: bool,
+    fast_rate: Option<u32>,
+    slow_motion: bool,
+    slow_rate: u32,
+}
+
+impl SpeedControl {
+    /// fast_rate为0时快进不限速
+    pub fn new(fast_rate: u32, slow_rate: u32) -> Self {
+        SpeedControl {
+            paused: false,
+            advance: 0,
+            fast_forward: false,
+            fast_rate: (fast_rate > 0).then_some(fast_rate),
+            slow_motion: false,
+            slow_rate: slow_rate.max(1),
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = 0;
+    }
+
+    /// 运行一帧后暂停,正在运行时直接暂停
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.advance += 1;
+        } else {
+            self.paused = true;
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.slow_motion = !self.slow_motion;
+    }
+
+    /// 每帧结束时调用,返回是否需要暂停等待;逐帧时消耗一次并运行下一帧
+    pub fn wait(&mut self) -> bool {
+        if !self.paused {
+            return false;
+        }
+        if self.advance > 0 {
+            self.advance -= 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.fast_forward {
+            Speed::Fast(self.fast_rate)
+        } else if self.slow_motion {
+            Speed::Slow(self.slow_rate)
+        } else {
+            Speed::Normal
+        }
+    }
+
+    /// 按当前速度每帧的时长,正常速度时为frame_duration,不限速时为0
+    pub fn frame_duration(&self, frame_duration: Duration) -> Duration {
+        match self.speed() {
+            Speed::Normal => frame_duration,
+            Speed::Fast(None) => Duration::ZERO,
+            Speed::Fast(Some(rate)) => frame_duration / rate,
+            Speed::Slow(rate) => frame_duration * rate,
+        }
+    }
+}
+
+#[test]
+fn test_speed() {
+    let frame = Duration::from_millis(16);
+    let mut speed = SpeedControl::new(3, 4);
+    assert_eq!(speed.frame_duration(frame), frame);
+    speed.toggle_slow_motion();
+    assert_eq!(speed.speed(), Speed::Slow(4));
+    assert_eq!(speed.frame_duration(frame), Duration::from_millis(64));
+    // 快进优先于慢动作
+    speed.set_fast_forward(true);
+    assert_eq!(speed.frame_duration(frame), Duration::from_nanos(5_333_333));
+    speed.set_fast_forward(false);
+    speed.toggle_slow_motion();
+    assert_eq!(speed.speed(), Speed::Normal);
+
+    let mut uncapped = SpeedControl::new(0, 0);
+    uncapped.set_fast_forward(true);
+    assert_eq!(uncapped.speed(), Speed::Fast(None));
+    assert_eq!(uncapped.frame_duration(frame), Duration::ZERO);
+}
+
+#[test]
+fn test_pause() {
+    let mut speed = SpeedControl::new(0, 4);
+    assert!(!speed.wait());
+    // 运行时按逐帧键直接暂停
+    speed.advance_frame();
+    assert!(speed.wait());
+    speed.advance_frame();
+    speed.advance_frame();
+    assert!(!speed.wait());
+    assert!(!speed.wait());
+    assert!(speed.wait());
+    speed.toggle_pause();
+    assert!(!speed.wait());
+}
@@ -0,0 +1,402 @@
+use std::cell::{Cell, RefCell};
+
+// 存档格式(小端)
+//
+// $00 "NSS\x1A"
+// $04 版本(2字节)
+// $06 一系列的段: 标识(4字节) 长度(4字节) 数据
+//
+// 每个设备的状态保存在自己的段中,读取时跳过无法识别的段;
+// 段内的字段按顺序排列,新的字段只添加在段的末尾,读取时忽略段末尾多余的数据,
+// 因此旧版本可以读取新版本的存档,只有不兼容的修改才会增加版本号
+
+const STATE_TAG: [u8; 4] = [0x4E, 0x53, 0x53, 0x1A];
+const STATE_VERSION: u16 = 1;
+const STATE_HEADER_SIZE: usize = 6;
+
+/// 可以保存到存档中的状态
+///
+/// 只保存模拟的机器状态,制式、采样率等配置以及主机的音频输出不属于存档
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String>;
+}
+
+/// 按顺序保存与读取结构体的字段
+#[macro_export]
+macro_rules! snapshot {
+    ($type:ty, $($field:ident),+) => {
+        impl $crate::state::Snapshot for $type {
+            fn save(&self, w: &mut $crate::state::StateWriter) {
+                $($crate::state::Snapshot::save(&self.$field, w);)+
+            }
+
+            fn load(&mut self, r: &mut $crate::state::StateReader) -> Result<(), String> {
+                $($crate::state::Snapshot::load(&mut self.$field, r)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// 在已有的存档之后继续写入段
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        StateWriter { data }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// 写入一个段,f写入段的数据
+    pub fn section(&mut self, tag: &[u8; 4], f: impl FnOnce(&mut StateWriter)) {
+        self.data.extend(tag);
+        let start = self.data.len();
+        self.data.extend([0; 4]);
+        f(self);
+        let length = (self.data.len() - start - 4) as u32;
+        self.data[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// 检查文件头,返回读取各个段的StateReader
+    pub fn sections(data: &'a [u8]) -> Result<Self, String> {
+        if data.len() < STATE_HEADER_SIZE || data[0..4] != STATE_TAG {
+            return Err("Not a save state".to_string());
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != STATE_VERSION {
+            return Err(format!("Unsupported save state version: {}", version));
+        }
+        Ok(StateReader {
+            data,
+            position: STATE_HEADER_SIZE,
+        })
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or("Truncated save state")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    /// 从当前位置开始查找标识为tag的段,f读取段的数据
+    pub fn section<T>(
+        &self,
+        tag: &[u8; 4],
+        f: impl FnOnce(&mut StateReader) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut sections = StateReader {
+            data: self.data,
+            position: self.position,
+        };
+        while sections.position < sections.data.len() {
+            let header = sections.read_bytes(8)?;
+            let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            let data = sections.read_bytes(length)?;
+            if header[0..4] == tag[..] {
+                return f(&mut StateReader { data, position: 0 });
+            }
+        }
+        Err(format!(
+            "Missing {} in save state",
+            String::from_utf8_lossy(tag).trim_end()
+        ))
+    }
+}
+
+/// 保存状态,生成带文件头的存档
+pub fn encode(value: &dyn Snapshot) -> Vec<u8> {
+    let mut w = StateWriter::from_bytes(STATE_TAG.to_vec());
+    w.write_bytes(&STATE_VERSION.to_le_bytes());
+    value.save(&mut w);
+    w.into_bytes()
+}
+
+/// 从存档中读取状态,出错时value可能只被读取了一部分
+pub fn decode(data: &[u8], value: &mut dyn Snapshot) -> Result<(), String> {
+    value.load(&mut StateReader::sections(data)?)
+}
+
+macro_rules! snapshot_int {
+    ($($type:ty),+) => {
+        $(impl Snapshot for $type {
+            fn save(&self, w: &mut StateWriter) {
+                w.write_bytes(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+                let bytes = r.read_bytes(std::mem::size_of::<$type>())?;
+                *self = <$type>::from_le_bytes(bytes.try_into().unwrap());
+                Ok(())
+            }
+        })+
+    };
+}
+
+snapshot_int!(u8, u16, u32, u64, i8, i16, i32);
+
+/// usize按u64保存,与平台无关
+impl Snapshot for usize {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut value = 0u64;
+        value.load(r)?;
+        *self = usize::try_from(value).map_err(|_| "Invalid save state")?;
+        Ok(())
+    }
+}
+
+impl Snapshot for bool {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut value = 0u8;
+        value.load(r)?;
+        *self = value != 0;
+        Ok(())
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, w: &mut StateWriter) {
+        for item in self {
+            item.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for item in self {
+            item.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+/// 先保存长度,读取时按保存的长度重新分配
+impl<T: Snapshot + Default> Snapshot for Vec<T> {
+    fn save(&self, w: &mut StateWriter) {
+        (self.len() as u32).save(w);
+        for item in self {
+            item.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut length = 0u32;
+        length.load(r)?;
+        self.clear();
+        for _ in 0..length {
+            let mut item = T::default();
+            item.load(r)?;
+            self.push(item);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.is_some().save(w);
+        if let Some(value) = self {
+            value.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let mut some = false;
+        some.load(r)?;
+        *self = None;
+        if some {
+            let mut value = T::default();
+            value.load(r)?;
+            *self = Some(value);
+        }
+        Ok(())
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, w: &mut StateWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.0.load(r)?;
+        self.1.load(r)
+    }
+}
+
+impl<T: Snapshot + Copy> Snapshot for Cell<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.get().save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.get_mut().load(r)
+    }
+}
+
+impl<T: Snapshot> Snapshot for RefCell<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.borrow().save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.get_mut().load(r)
+    }
+}
+
+#[cfg(test)]
+struct TestState {
+    a: u8,
+    b: Option<u16>,
+    c: Vec<bool>,
+}
+
+#[cfg(test)]
+snapshot!(TestState, a, b, c);
+
+#[test]
+fn test_sections() {
+    let state = TestState {
+        a: 1,
+        b: Some(0x1234),
+        c: vec![true, false],
+    };
+    let mut w = StateWriter::from_bytes(Vec::new());
+    w.section(b"NEW ", |w| w.write_bytes(&[0xFF; 3]));
+    w.section(b"TEST", |w| {
+        state.save(w);
+        // 新版本添加在段末尾的字段
+        w.write_bytes(&[0xAA]);
+    });
+    let mut data = STATE_TAG.to_vec();
+    data.extend(STATE_VERSION.to_le_bytes());
+    data.extend(w.into_bytes());
+
+    let r = StateReader::sections(&data).unwrap();
+    let mut loaded = TestState {
+        a: 0,
+        b: None,
+        c: Vec::new(),
+    };
+    r.section(b"TEST", |r| loaded.load(r)).unwrap();
+    assert_eq!(
+        (loaded.a, loaded.b, &loaded.c[..]),
+        (1, Some(0x1234), &[true, false][..])
+    );
+    assert!(r.section(b"NEW ", |_| Ok(())).is_ok());
+    assert_eq!(
+        r.section(b"OLD ", |_| Ok(())),
+        Err("Missing OLD in save state".to_string())
+    );
+
+    // 段的数据不完整
+    assert!(r.section(b"NEW ", |r| loaded.load(r)).is_err());
+    data[4] = 2;
+    assert!(StateReader::sections(&data).is_err());
+}
+
+#[cfg(test)]
+pub fn test_cpu(program: &[u8]) -> crate::cpu::CPU {
+    use crate::{
+        apu::Apu, bus::BusBuilder, cpu::CPU, memory::Memory, peripheral::Joypad, ppu::Ppu,
+        rom::test::test_rom_with_program,
+    };
+    let bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom_with_program(program)))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .input_p1(Box::new(Joypad::new()))
+        .build()
+        .unwrap();
+    let mut cpu = CPU::new(Box::new(bus));
+    cpu.reset();
+    cpu
+}
+
+/// 运行count帧,返回最后一帧的画面
+#[cfg(test)]
+fn run_frames(cpu: &mut crate::cpu::CPU, count: usize) -> Vec<u16> {
+    let mut frames = 0;
+    loop {
+        cpu.run_one_instruction();
+        if let Some(frame) = cpu.bus.poll_frame() {
+            frames += 1;
+            if frames == count {
+                return frame.to_vec();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E; STA $2001
+        0xA9, 0x01, 0x8D, 0x15, 0x40, // LDA #$01; STA $4015
+        0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF; STA $4000
+        0xE6, 0x10,                   // INC $10
+        0xA5, 0x10, 0x8D, 0x02, 0x40, // LDA $10; STA $4002
+        0x8D, 0x03, 0x40,             // STA $4003
+        0x8D, 0x16, 0x40,             // STA $4016
+        0x4C, 0x0F, 0x80,             // JMP $800F
+    ];
+    let mut cpu = test_cpu(&program);
+    run_frames(&mut cpu, 3);
+    let saved = cpu.save_state();
+    let frame = run_frames(&mut cpu, 5);
+    let expected = cpu.save_state();
+
+    cpu.load_state(&saved).unwrap();
+    assert_eq!(cpu.save_state(), saved);
+    assert_eq!(run_frames(&mut cpu, 5), frame);
+    assert_eq!(cpu.save_state(), expected);
+
+    // 读取到另一台相同的机器上
+    let mut other = test_cpu(&program);
+    other.load_state(&saved).unwrap();
+    assert_eq!(run_frames(&mut other, 5), frame);
+    assert_eq!(other.save_state(), expected);
+}
+
+#[test]
+fn test_wrong_game() {
+    let mut cpu = test_cpu(&[0x4C, 0x00, 0x80]);
+    let mut other = test_cpu(&[0xEA, 0x4C, 0x00, 0x80]);
+    run_frames(&mut other, 1);
+    let before = cpu.save_state();
+    assert_eq!(
+        cpu.load_state(&other.save_state()),
+        Err("The save state belongs to a different game".to_string())
+    );
+    assert_eq!(cpu.save_state(), before);
+    assert!(cpu.load_state(b"NSS").is_err());
+}
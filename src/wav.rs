@@ -0,0 +1,152 @@
+use crate::{
+    apu::{Channel, DEFAULT_SAMPLE_RATE},
+    bus::CpuBus,
+    cpu::CPU,
+};
+
+// WAV文件格式(16位PCM, 单声道)
+//
+// "RIFF" 文件长度-8 "WAVE"
+// "fmt " 16 格式(1:PCM) 声道数 采样率 字节率 块对齐 采样位数
+// "data" 数据长度 采样数据(小端)
+
+/// 将-1~1的采样编码为16位单声道的WAV文件
+pub fn encode_wav(sample_rate: u32, samples: &[f32]) -> Vec<u8> {
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let data_size = samples.len() as u32 * block_align as u32;
+
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+/// 写入WAV文件
+pub fn write_wav(path: &str, sample_rate: u32, samples: &[f32]) -> Result<(), String> {
+    std::fs::write(path, encode_wav(sample_rate, samples)).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[test]
+fn test_encode_wav() {
+    let wav = encode_wav(44100, &[0.0, 1.0, -1.0, 2.0]);
+    assert_eq!(wav.len(), 44 + 8);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 44);
+    assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44100);
+    assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+    assert_eq!(&wav[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]);
+}
+
+/// 录制的音频
+pub struct Recording {
+    pub sample_rate: u32,
+    /// 混合后的输出
+    pub mixed: Vec<f32>,
+    /// 各通道单独的输出,未开启时为空
+    pub stems: Vec<(Channel, Vec<f32>)>,
+}
+
+impl Recording {
+    /// 开始录制,stems为true时同时录制各通道,丢弃之前产生的采样
+    pub fn start(bus: &mut dyn CpuBus, stems: bool) -> Recording {
+        let mut stale = vec![0.0; bus.audio_samples_available()];
+        bus.read_audio_samples(&mut stale);
+        let mut recording = Recording {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            mixed: Vec::new(),
+            stems: Vec::new(),
+        };
+        if stems {
+            bus.enable_audio_stems();
+            recording.stems = Channel::ALL.iter().map(|c| (*c, Vec::new())).collect();
+        }
+        recording
+    }
+
+    /// 取出APU目前输出的全部采样
+    pub fn capture(&mut self, bus: &mut dyn CpuBus) {
+        let mut buffer = vec![0.0; bus.audio_samples_available()];
+        let count = bus.read_audio_samples(&mut buffer);
+        self.mixed.extend_from_slice(&buffer[..count]);
+        for (channel, samples) in self.stems.iter_mut() {
+            let count = bus.read_audio_stem(*channel, &mut buffer);
+            samples.extend_from_slice(&buffer[..count]);
+        }
+    }
+}
+
+/// 运行若干帧并录制APU的输出
+pub fn record(cpu: &mut CPU, frames: u32, stems: bool) -> Recording {
+    let mut recording = Recording::start(cpu.bus.as_mut(), stems);
+    let mut frame = 0;
+    while frame < frames && cpu.run_one_instruction() {
+        if cpu.bus.poll_frame().is_some() {
+            frame += 1;
+            recording.capture(cpu.bus.as_mut());
+        }
+    }
+    recording
+}
+
+/// 将录制的音频写入文件,各通道的文件名为<文件名>.<通道名>.wav
+pub fn export(path: &str, recording: &Recording) -> Result<(), String> {
+    write_wav(path, recording.sample_rate, &recording.mixed)?;
+    let stem_base = path.strip_suffix(".wav").unwrap_or(path);
+    for (channel, samples) in &recording.stems {
+        let stem_path = format!("{}.{}.wav", stem_base, channel.name());
+        write_wav(&stem_path, recording.sample_rate, samples)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_record_hash() {
+    use crate::{
+        apu::Apu, bus::BusBuilder, memory::Memory, ppu::Ppu, rom::test::test_rom_with_program,
+    };
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01, 0x8D, 0x15, 0x40, // LDA #$01; STA $4015
+        0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF; STA $4000
+        0xA9, 0xFD, 0x8D, 0x02, 0x40, // LDA #$FD; STA $4002
+        0xA9, 0x08, 0x8D, 0x03, 0x40, // LDA #$08; STA $4003
+        0x4C, 0x14, 0x80,             // JMP $8014
+    ];
+    let bus = BusBuilder::new()
+        .ram(Box::new(Memory::new(0xFFFF)))
+        .rom(Box::new(test_rom_with_program(&program)))
+        .ppu(Box::new(Ppu::new_empty()))
+        .apu(Box::new(Apu::new()))
+        .build()
+        .unwrap();
+    let mut cpu = CPU::new(Box::new(bus));
+    cpu.reset();
+
+    let recording = record(&mut cpu, 10, true);
+    assert_eq!(recording.mixed.len(), 7337);
+    for (channel, samples) in &recording.stems {
+        assert_eq!(samples.len(), recording.mixed.len());
+        // 三角波停止时保持输出15,上电时产生一个阶跃
+        let silent = !matches!(channel, Channel::Pulse1 | Channel::Triangle);
+        assert_eq!(samples.iter().all(|s| *s == 0.0), silent);
+    }
+    let wav = encode_wav(recording.sample_rate, &recording.mixed);
+    assert_eq!(crate::hash::fnv1a(&wav), 0x178e_b8fa_56be_616a);
+}